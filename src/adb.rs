@@ -0,0 +1,229 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::{
+    process::{Output, Stdio},
+    time::Duration,
+};
+use tokio::io::AsyncBufReadExt;
+use tokio::process::Command;
+
+use crate::builder::find_sdk_binary;
+use crate::explain::Explain;
+
+const ADB_ATTEMPTS: usize = 3;
+
+/// Logcat tag the gadget logs under, including the script's `console.log` output.
+pub const DEFAULT_READY_MARKER: &str = "Frida";
+
+/// stderr fragments of adb failures that usually go away once the device settles
+const RETRYABLE_ERRORS: &[&str] = &["device offline", "closed", "no devices", "device not found"];
+
+/// Network devices `connect` attached in this run, for `disconnect_all`.
+static CONNECTED: tokio::sync::Mutex<Vec<String>> = tokio::sync::Mutex::const_new(Vec::new());
+
+/// Creates an adb command, targeting `device` (a serial) if given.
+pub fn adb_command(device: Option<&str>) -> Result<Command> {
+    let mut command = Command::new(find_sdk_binary("adb")?);
+    if let Some(device) = device {
+        command.arg("-s").arg(device);
+    }
+    Ok(command)
+}
+
+/// Checks that the device supports one of `target_abis` (e.g. `arm64-v8a`), as a
+/// module built for another ABI installs fine but never loads. A mismatch is an
+/// error with `strict`, a warning otherwise.
+pub async fn check_device_abi(
+    device: Option<&str>,
+    target_abis: &[String],
+    strict: bool,
+) -> Result<()> {
+    let output =
+        adb_output_with_retry(device, &["shell", "getprop", "ro.product.cpu.abilist"]).await?;
+    if !output.status.success() {
+        warn!(
+            "Failed to query device ABIs: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Ok(());
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let device_abis: Vec<&str> = stdout
+        .trim()
+        .split(',')
+        .map(str::trim)
+        .filter(|abi| !abi.is_empty())
+        .collect();
+    info!(
+        "→ Device ABIs: {}, target ABI: {}",
+        device_abis.join(", "),
+        target_abis.join(", ")
+    );
+    if target_abis
+        .iter()
+        .any(|abi| device_abis.contains(&abi.as_str()))
+    {
+        return Ok(());
+    }
+
+    let message = format!(
+        "Device doesn't support the target ABI {} (device ABIs: {}), it will not load",
+        target_abis.join(", "),
+        device_abis.join(", ")
+    );
+    if strict {
+        anyhow::bail!(message);
+    }
+    warn!("{message}");
+    Ok(())
+}
+
+/// Runs adb with `args`, waiting for the device and retrying on transient
+/// connection failures. Other failures are returned right away for the caller
+/// to report.
+pub async fn adb_output_with_retry(device: Option<&str>, args: &[&str]) -> Result<Output> {
+    let mut attempt = 1;
+    loop {
+        let output = adb_command(device)?.args(args).explain().output().await?;
+        if output.status.success() || attempt == ADB_ATTEMPTS {
+            return Ok(output);
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr).to_lowercase();
+        if !RETRYABLE_ERRORS.iter().any(|error| stderr.contains(error)) {
+            return Ok(output);
+        }
+
+        warn!(
+            "adb {} failed ({}), waiting for device and retrying ({attempt}/{ADB_ATTEMPTS})...",
+            args.join(" "),
+            stderr.trim()
+        );
+        let wait = adb_command(device)?
+            .arg("wait-for-device")
+            .explain()
+            .status();
+        if tokio::time::timeout(Duration::from_secs(30), wait)
+            .await
+            .is_err()
+        {
+            warn!("Timed out waiting for device");
+        }
+        attempt += 1;
+    }
+}
+
+/// Runs `adb connect` to a network device at `address` (`host:port`), after
+/// which `address` is its serial. Devices that are already connected are fine.
+pub async fn connect(address: &str) -> Result<()> {
+    let mut connected = CONNECTED.lock().await;
+    if connected.iter().any(|a| a == address) {
+        return Ok(());
+    }
+
+    info!("→ Connecting to device: {address}");
+    let output = adb_command(None)?
+        .args(["connect", address])
+        .explain()
+        .output()
+        .await?;
+    // adb connect exits with 0 on most failures, so check what it says
+    let message = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let message = message.trim();
+    if !output.status.success() || !message.contains("connected to") {
+        anyhow::bail!("Failed to connect to device {address}: {message}");
+    }
+    if message.contains("already connected") {
+        info!("✓ Already connected to {address}");
+    } else {
+        info!("✓ Connected to {address}");
+        connected.push(address.to_string());
+    }
+    Ok(())
+}
+
+/// Runs `adb disconnect` for the devices `connect` attached in this run.
+pub async fn disconnect_all() {
+    for address in CONNECTED.lock().await.drain(..) {
+        let output = match adb_command(None) {
+            Ok(mut command) => {
+                command
+                    .args(["disconnect", &address])
+                    .explain()
+                    .output()
+                    .await
+            }
+            Err(e) => {
+                warn!("Failed to disconnect from {address}: {e}");
+                continue;
+            }
+        };
+        match output {
+            Ok(output) if output.status.success() => info!("✓ Disconnected from {address}"),
+            Ok(output) => warn!(
+                "Failed to disconnect from {address}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(e) => warn!("Failed to disconnect from {address}: {e}"),
+        }
+    }
+}
+
+/// Checks that `address` looks like the `host:port` of a network device.
+pub fn validate_connect_address(address: &str) -> Result<()> {
+    let Some((host, port)) = address.rsplit_once(':') else {
+        anyhow::bail!("{address:?} is not a host:port address");
+    };
+    if host.is_empty() || port.parse::<u16>().is_err() {
+        anyhow::bail!("{address:?} is not a host:port address");
+    }
+    Ok(())
+}
+
+/// Streams the device's logcat from now on, returning whether a line containing
+/// `marker` shows up within `timeout`.
+pub async fn wait_for_logcat_marker(
+    device: Option<&str>,
+    marker: &str,
+    timeout: Duration,
+) -> Result<bool> {
+    // Start at the device's current time, so earlier runs' lines don't match
+    let output = adb_output_with_retry(device, &["shell", "date", "+%s"]).await?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to query device time: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let since = format!("{}.000", String::from_utf8_lossy(&output.stdout).trim());
+
+    let mut logcat = adb_command(device)?
+        .args(["logcat", "-T", &since])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .explain()
+        .spawn()?;
+    let stdout = logcat
+        .stdout
+        .take()
+        .context("Failed to read logcat output")?;
+    let mut lines = tokio::io::BufReader::new(stdout).lines();
+    let found = tokio::time::timeout(timeout, async {
+        while let Some(line) = lines.next_line().await? {
+            if line.contains(marker) {
+                return anyhow::Ok(true);
+            }
+        }
+        // logcat exited, e.g. because the device went away
+        anyhow::Ok(false)
+    })
+    .await;
+    let _ = logcat.kill().await;
+    found.unwrap_or(Ok(false))
+}
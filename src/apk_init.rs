@@ -0,0 +1,193 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+use crate::adb::adb_output_with_retry;
+use crate::builder::{extract_apk_libraries, find_sdk_binary, Builder};
+use crate::config::{FripackConfig, PlatformConfig, PreferSplit};
+use crate::explain::Explain;
+
+/// ABIs picked when the device's isn't known, most common first.
+const PREFERRED_ABIS: &[&str] = &["arm64-v8a", "armeabi-v7a", "x86_64", "x86"];
+
+/// Creates an `inject-apk` target for an APK file, or for a package pulled from
+/// the device, with its package name, ABI and target library filled in. Writes
+/// `config_path` if it doesn't exist yet, and prints the target otherwise.
+pub async fn init_from_apk(source: &str, config_path: &Path, device: Option<&str>) -> Result<()> {
+    let is_file = Path::new(source).is_file() || source.ends_with(".apk");
+    let (apk_path, package_name, device_abi) = if is_file {
+        let apk_path = PathBuf::from(source);
+        if !apk_path.is_file() {
+            anyhow::bail!("APK not found: {}", apk_path.display());
+        }
+        let package_name = apk_package_name(&apk_path).await?;
+        (apk_path, package_name, None)
+    } else {
+        let abi = device_abi(device).await?;
+        let apk_path = Builder::new()
+            .extract_apk_from_device(source, PreferSplit::Auto, &abi, device)
+            .await?;
+        (apk_path, source.to_string(), Some(abi))
+    };
+    info!("→ Package name: {package_name}");
+
+    let abis = apk_abis(&apk_path)?;
+    if abis.is_empty() {
+        warn!("The APK has no native libraries");
+    } else {
+        info!(
+            "→ ABIs: {}",
+            abis.iter().cloned().collect::<Vec<_>>().join(", ")
+        );
+    }
+    let abi = match device_abi {
+        Some(abi) => abi,
+        None => PREFERRED_ABIS
+            .iter()
+            .find(|abi| abis.contains(**abi))
+            .or(PREFERRED_ABIS.first())
+            .unwrap()
+            .to_string(),
+    };
+    let platform = PlatformConfig::supported()
+        .iter()
+        .filter(|platform| platform.starts_with("android-"))
+        .find(|platform| {
+            PlatformConfig::from_str(platform.to_string())
+                .and_then(|platform| platform.android_abi())
+                .is_ok_and(|platform_abi| platform_abi == abi)
+        })
+        .with_context(|| format!("Unsupported ABI: {abi}"))?;
+
+    // Without a library to add the payload to, bootstrap it from the manifest
+    let target_lib = if abis.contains(&abi) {
+        let lib_dir = tempfile::tempdir()?;
+        extract_apk_libraries(&apk_path, &format!("lib/{abi}/"), lib_dir.path())?;
+        let target_lib_path = Builder::new()
+            .find_target_library(lib_dir.path(), &None)
+            .await?;
+        let target_lib = target_lib_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        info!("→ Target library: {target_lib}");
+        Some(target_lib)
+    } else {
+        warn!("No {abi} libraries to inject into, using the ApplicationStub mode");
+        None
+    };
+
+    let mut inject_apk = serde_json::json!({
+        "injectMode": if target_lib.is_some() { "NativeAddNeeded" } else { "ApplicationStub" },
+    });
+    if is_file {
+        // Paths in the config are relative to its directory
+        let in_current_dir = config_path
+            .parent()
+            .is_none_or(|dir| dir.as_os_str().is_empty() || dir == Path::new("."));
+        inject_apk["sourceApkPath"] = if in_current_dir {
+            source.into()
+        } else {
+            std::fs::canonicalize(&apk_path)?.to_string_lossy().into()
+        };
+    } else {
+        inject_apk["sourceApkPackageName"] = source.into();
+    }
+    if let Some(target_lib) = target_lib {
+        inject_apk["targetLib"] = target_lib.into();
+    }
+    let frida_version = FripackConfig::template()
+        .targets
+        .get("base")
+        .and_then(|base| base.frida_version.clone());
+    let target = serde_json::json!({
+        "type": "inject-apk",
+        "platform": platform,
+        "fridaVersion": frida_version,
+        "entry": "main.js",
+        "injectApk": inject_apk,
+    });
+    let target_name = package_name
+        .rsplit('.')
+        .next()
+        .unwrap_or(&package_name)
+        .to_string();
+
+    if config_path.exists() {
+        info!("Add this target to {}:", config_path.display());
+        println!(
+            "\"{target_name}\": {}",
+            serde_json::to_string_pretty(&target)?
+        );
+    } else {
+        let config = serde_json::json!({ target_name.clone(): target });
+        tokio::fs::write(config_path, serde_json::to_string_pretty(&config)?).await?;
+        info!(
+            "✓ Created configuration file with target {target_name}: {}",
+            config_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Reads the package name of an APK with `aapt2` (or `aapt`) from the SDK build-tools.
+async fn apk_package_name(apk: &Path) -> Result<String> {
+    if let Ok(aapt2) = find_sdk_binary("aapt2") {
+        let output = Command::new(aapt2)
+            .arg("dump")
+            .arg("packagename")
+            .arg(apk)
+            .explain()
+            .output()
+            .await?;
+        let package_name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if output.status.success() && !package_name.is_empty() {
+            return Ok(package_name);
+        }
+    }
+    let aapt = find_sdk_binary("aapt").context(
+        "Reading the package name of an APK needs aapt2 or aapt from the Android SDK build-tools",
+    )?;
+    let output = Command::new(aapt)
+        .arg("dump")
+        .arg("badging")
+        .arg(apk)
+        .explain()
+        .output()
+        .await?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| {
+            let name = line.strip_prefix("package: name='")?;
+            Some(name[..name.find('\'')?].to_string())
+        })
+        .with_context(|| format!("Failed to read the package name of {}", apk.display()))
+}
+
+/// ABIs the APK has native libraries for.
+fn apk_abis(apk: &Path) -> Result<BTreeSet<String>> {
+    let archive = zip::ZipArchive::new(std::fs::File::open(apk)?)?;
+    Ok(archive
+        .file_names()
+        .filter_map(|name| {
+            let (abi, lib) = name.strip_prefix("lib/")?.split_once('/')?;
+            (!lib.contains('/') && lib.ends_with(".so")).then(|| abi.to_string())
+        })
+        .collect())
+}
+
+/// Primary ABI of the device.
+async fn device_abi(device: Option<&str>) -> Result<String> {
+    let output = adb_output_with_retry(device, &["shell", "getprop", "ro.product.cpu.abi"]).await?;
+    let abi = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || abi.is_empty() {
+        anyhow::bail!(
+            "Failed to query the device ABI: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(abi)
+}
@@ -0,0 +1,153 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::fs;
+
+use crate::binary::{BinaryProcessor, Compression};
+use crate::builder::{sha256_hex, EmbeddedConfigData, Mode, EMBEDDED_DATA_VERSION};
+use crate::format_bytes;
+
+/// Timings of one processing step over all iterations.
+struct StepTimings {
+    name: &'static str,
+    runs: Vec<Duration>,
+}
+
+impl StepTimings {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            runs: Vec::new(),
+        }
+    }
+
+    /// The `p`th percentile (nearest rank) of the runs.
+    fn percentile(&self, p: usize) -> Duration {
+        let mut runs = self.runs.clone();
+        runs.sort();
+        let rank = (runs.len() * p).div_ceil(100).max(1);
+        runs[rank - 1]
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "step": self.name,
+            "medianMs": self.percentile(50).as_secs_f64() * 1000.0,
+            "p95Ms": self.percentile(95).as_secs_f64() * 1000.0,
+        })
+    }
+}
+
+/// Times embedding `entry` into `prebuilt` (and anti-detection) like a build
+/// does, `iterations` times, without writing output or downloading anything.
+pub async fn bench(
+    prebuilt: PathBuf,
+    entry: PathBuf,
+    iterations: usize,
+    xz: bool,
+    anti_detection: bool,
+    json: bool,
+) -> Result<()> {
+    if iterations == 0 {
+        anyhow::bail!("--iterations must be at least 1");
+    }
+    let prebuilt_data = fs::read(&prebuilt)
+        .await
+        .with_context(|| format!("Failed to read {}", prebuilt.display()))?;
+    let entry_data = fs::read(&entry)
+        .await
+        .with_context(|| format!("Failed to read {}", entry.display()))?;
+    let js_content = String::from_utf8_lossy(&entry_data).to_string();
+    let config_data = serde_json::to_string(&EmbeddedConfigData {
+        version: EMBEDDED_DATA_VERSION,
+        mode: Mode::EmbedJs,
+        js_filepath: Some(entry.to_string_lossy().to_string()),
+        script_sha256: Some(sha256_hex(js_content.as_bytes())),
+        js_content: Some(js_content),
+        watch_path: None,
+        runtime: None,
+        runtime_config: None,
+        scripts: None,
+    })?;
+    let compression = if xz {
+        Compression::Xz
+    } else {
+        Compression::None
+    };
+
+    info!("→ Processing {} {iterations} times...", prebuilt.display());
+    let mut embed = StepTimings::new("embed");
+    let mut anti_detect = StepTimings::new("antiDetection");
+    let mut total = StepTimings::new("total");
+    let mut output_size = 0;
+    let mut anti_detection_supported = true;
+    // The processing steps log every run. A failing one is logged afterwards,
+    // so the level is restored before returning its error.
+    let max_level = log::max_level();
+    log::set_max_level(log::LevelFilter::Warn);
+    let result = (|| {
+        for _ in 0..iterations {
+            let start = Instant::now();
+            let mut processor = BinaryProcessor::new(prebuilt_data.clone())?;
+            processor.add_embedded_config_data(config_data.as_bytes(), &compression)?;
+            let embedded = Instant::now();
+            embed.runs.push(embedded - start);
+            if anti_detection {
+                anti_detection_supported = processor.anti_anti_frida()?.is_some();
+                anti_detect.runs.push(embedded.elapsed());
+            }
+            output_size = processor.into_data().len();
+            total.runs.push(start.elapsed());
+        }
+        anyhow::Ok(())
+    })();
+    log::set_max_level(max_level);
+    result?;
+    if !anti_detection_supported {
+        warn!("Anti-detection only supports ELF binaries, it did nothing");
+    }
+
+    let mut steps = vec![embed];
+    if anti_detection {
+        steps.push(anti_detect);
+    }
+    steps.push(total);
+
+    if json {
+        let report = serde_json::json!({
+            "prebuilt": prebuilt.to_string_lossy(),
+            "iterations": iterations,
+            "inputSize": prebuilt_data.len(),
+            "scriptSize": entry_data.len(),
+            "outputSize": output_size,
+            "steps": steps.iter().map(StepTimings::to_json).collect::<Vec<_>>(),
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    println!(
+        "Input:  {} ({})",
+        format_bytes(prebuilt_data.len() as u64),
+        prebuilt.display()
+    );
+    println!(
+        "Script: {} ({})",
+        format_bytes(entry_data.len() as u64),
+        entry.display()
+    );
+    println!("Output: {}", format_bytes(output_size as u64));
+    println!();
+    println!("{:<15} {:>12} {:>12}", "Step", "Median", "p95");
+    for step in &steps {
+        println!(
+            "{:<15} {:>12} {:>12}",
+            step.name,
+            format!("{:.2?}", step.percentile(50)),
+            format!("{:.2?}", step.percentile(95))
+        );
+    }
+    println!("({iterations} iterations)");
+    Ok(())
+}
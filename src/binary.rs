@@ -1,16 +1,26 @@
 use anyhow::{Context, Result};
-use log::info;
+use log::{debug, info, warn};
 use object::{
     build::{elf::Dynamic, ByteString},
-    elf::{PF_R, PF_W, PT_LOAD, PT_PHDR},
-    pe,
+    elf::{DT_NEEDED, PF_R, PF_W, PT_LOAD, PT_PHDR},
+    macho, pe,
     read::{
         coff::CoffHeader,
         pe::{ImageNtHeaders, ImageOptionalHeader},
     },
-    LittleEndian as LE, Object, ObjectSymbol,
+    LittleEndian as LE, Object, ObjectSegment, ObjectSymbol,
 };
 use rand::Rng;
+use std::ops::{Range, RangeInclusive};
+
+/// `EmbeddedConfig` versions fripack can emit and read. Versions 1 to 3 share
+/// the same layout, version 4 appends the zstd dictionary id.
+pub const EMBEDDED_CONFIG_VERSIONS: RangeInclusive<i32> = 1..=4;
+
+/// Bit of `EmbeddedConfig::compression` marking the (compressed) data as base64,
+/// understood by version 3 loaders.
+pub const BASE64_ENCODING_FLAG: u8 = 0x80;
+
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
 pub struct EmbeddedConfig {
@@ -19,7 +29,12 @@ pub struct EmbeddedConfig {
     pub version: i32,
     pub data_size: i32,
     pub data_offset: i32,
-    pub data_xz: bool,
+    /// Compression of the embedded data, see [`Compression::id`]. Older loaders
+    /// read this byte as a `data_xz` bool, so `0`/`1` keep their meaning.
+    pub compression: u8,
+    /// Id of the zstd dictionary the data was compressed with, `0` without one.
+    /// Only part of version 4 configs.
+    pub zstd_dict_id: u32,
 }
 
 impl Default for EmbeddedConfig {
@@ -30,23 +45,49 @@ impl Default for EmbeddedConfig {
             version: 1,
             data_size: 0,
             data_offset: 0,
-            data_xz: false,
+            compression: 0,
+            zstd_dict_id: 0,
         }
     }
 }
 
 impl EmbeddedConfig {
-    pub fn new(data_size: i32, data_offset: i32, data_xz: bool) -> Self {
+    pub fn new(version: i32, data_size: i32, data_offset: i32, compression: u8) -> Self {
         Self {
             magic1: 0x0d000721,
             magic2: 0x1f8a4e2b,
-            version: 1,
+            version,
             data_size,
             data_offset,
-            data_xz,
+            compression,
+            zstd_dict_id: 0,
+        }
+    }
+
+    /// Size of a config of `version` in the binary, older versions end before
+    /// `zstd_dict_id`.
+    pub fn size(version: i32) -> usize {
+        if version >= 4 {
+            std::mem::size_of::<EmbeddedConfig>()
+        } else {
+            std::mem::size_of::<EmbeddedConfig>() - std::mem::size_of::<u32>()
         }
     }
 
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let field = |i: usize| Some(i32::from_le_bytes(bytes.get(i..i + 4)?.try_into().ok()?));
+        let version = field(8)?;
+        Some(Self {
+            magic1: field(0)?,
+            magic2: field(4)?,
+            version,
+            data_size: field(12)?,
+            data_offset: field(16)?,
+            compression: *bytes.get(20)?,
+            zstd_dict_id: if version >= 4 { field(21)? as u32 } else { 0 },
+        })
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         let mut bytes = vec![0; std::mem::size_of::<EmbeddedConfig>()];
         unsafe {
@@ -57,50 +98,392 @@ impl EmbeddedConfig {
                 std::mem::size_of::<EmbeddedConfig>(),
             );
         }
+        bytes.truncate(Self::size(self.version));
         bytes
     }
 }
 
+/// Magic number at the start of a trained zstd dictionary.
+const ZSTD_DICT_MAGIC: u32 = 0xEC30A437;
+
+#[derive(Debug, Clone)]
+pub enum Compression {
+    None,
+    Xz,
+    /// zstd, optionally with a precomputed dictionary. The dictionary id is recorded
+    /// in the (version 4) embedded config, the loader must pick the dictionary with that id.
+    Zstd(Option<Vec<u8>>),
+    /// Raw LZMA2 without any container, header or integrity check
+    Lzma,
+}
+
+impl Compression {
+    pub fn id(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Xz => 1,
+            Compression::Zstd(_) => 2,
+            Compression::Lzma => 3,
+        }
+    }
+}
+
+/// Validates a trained zstd dictionary and returns its dictionary id.
+pub fn zstd_dict_id(dict: &[u8]) -> Result<u32> {
+    if dict.len() < 8 || u32::from_le_bytes(dict[0..4].try_into()?) != ZSTD_DICT_MAGIC {
+        anyhow::bail!("Not a trained zstd dictionary (missing dictionary magic)");
+    }
+    let dict_id = u32::from_le_bytes(dict[4..8].try_into()?);
+    if dict_id == 0 {
+        anyhow::bail!("zstd dictionary has no dictionary id, the loader could not select it");
+    }
+    Ok(dict_id)
+}
+
+/// Dictionary id recorded in the embedded config for `compression`, `0` unless
+/// it's zstd with a dictionary.
+fn compression_dict_id(compression: &Compression) -> Result<u32> {
+    match compression {
+        Compression::Zstd(Some(dict)) => zstd_dict_id(dict),
+        _ => Ok(0),
+    }
+}
+
 pub enum ObjectFormat {
     Elf,
     Pe,
+    MachO,
 }
 
+/// Segment (and section) holding the embedded data in Mach-O files.
+const MACHO_SEGMENT_NAME: &[u8] = b"__FRIPACK";
+const MACHO_SECTION_NAME: &[u8] = b"__fripack";
+
+/// Fields of `dyld_chained_fixups_header` (`mach-o/fixup-chains.h`), offsets of
+/// the parts of the chained fixups relative to their start.
+const CHAINED_FIXUPS_STARTS_OFFSET: usize = 4;
+const CHAINED_FIXUPS_IMPORTS_OFFSET: usize = 8;
+const CHAINED_FIXUPS_SYMBOLS_OFFSET: usize = 12;
+
 pub struct BinaryProcessor {
     data: Vec<u8>,
     format: ObjectFormat,
+    pe_section_name: Option<[u8; 8]>,
+    embedded_version: i32,
+    proxy_exports: Option<ProxyExports>,
+    base64_encoding: bool,
+    embed_vaddr: Option<u64>,
+    embed_auto_align: bool,
+    anti_detection_keywords: Vec<String>,
+}
+
+/// Bounds of user-defined anti-detection keywords. Shorter ones would match
+/// unrelated strings, and replacements are done in place with same-length
+/// random strings, so every keyword must fit in one string table entry.
+const ANTI_DETECTION_KEYWORD_LEN: RangeInclusive<usize> = 3..=64;
+
+/// Checks that `keyword` can be replaced in place by [`BinaryProcessor::anti_anti_frida`].
+pub fn validate_anti_detection_keyword(keyword: &str) -> Result<()> {
+    if !keyword.is_ascii() || keyword.bytes().any(|b| b == 0 || b.is_ascii_control()) {
+        anyhow::bail!("Anti-detection keyword {keyword:?} must be printable ASCII");
+    }
+    if !ANTI_DETECTION_KEYWORD_LEN.contains(&keyword.len()) {
+        anyhow::bail!(
+            "Anti-detection keyword {keyword:?} must be {} to {} characters long",
+            ANTI_DETECTION_KEYWORD_LEN.start(),
+            ANTI_DETECTION_KEYWORD_LEN.end()
+        );
+    }
+    Ok(())
+}
+
+/// Validates a PE section name, which must fit in the 8-byte section header field.
+pub fn pe_section_name(name: &str) -> Result<[u8; 8]> {
+    if name.is_empty() || name.len() > 8 || !name.is_ascii() {
+        anyhow::bail!("PE section name must be 1 to 8 ASCII characters: {name:?}");
+    }
+    let mut section_name = [0u8; 8];
+    section_name[..name.len()].copy_from_slice(name.as_bytes());
+    Ok(section_name)
+}
+
+/// Exports of a DLL, re-exported by a proxy DLL as forwarders to the original.
+pub struct ProxyExports {
+    /// Module the forwarders point at, e.g. `version_orig`
+    forward_to: String,
+    /// DLL name recorded in the export directory
+    dll_name: Vec<u8>,
+    ordinal_base: u32,
+    /// Ordinal and name (if any) of every export
+    exports: Vec<(u32, Option<Vec<u8>>)>,
+}
+
+impl ProxyExports {
+    /// Reads the exports of the DLL `original`, to be forwarded to `forward_to`.
+    pub fn read(original: &[u8], forward_to: String) -> Result<Self> {
+        let table = match object::FileKind::parse(original)? {
+            object::FileKind::Pe32 => {
+                object::read::pe::PeFile32::parse(original)?.export_table()?
+            }
+            object::FileKind::Pe64 => {
+                object::read::pe::PeFile64::parse(original)?.export_table()?
+            }
+            _ => anyhow::bail!("Proxy target is not a PE file"),
+        };
+        let table = table.context("Proxy target has no export table")?;
+
+        let exports: Vec<_> = table
+            .exports()?
+            .into_iter()
+            // Unused slots of the address table
+            .filter(|export| !matches!(export.target, object::read::pe::ExportTarget::Address(0)))
+            .map(|export| (export.ordinal, export.name.map(<[u8]>::to_vec)))
+            .collect();
+        if exports.is_empty() {
+            anyhow::bail!("Proxy target has no exports");
+        }
+
+        Ok(Self {
+            forward_to,
+            dll_name: table
+                .name_from_pointer(table.directory().name.get(LE))?
+                .to_vec(),
+            ordinal_base: table.ordinal_base(),
+            exports,
+        })
+    }
+
+    pub fn export_count(&self) -> usize {
+        self.exports.len()
+    }
+
+    /// Builds an export directory located at `rva` whose entries all forward to
+    /// `forward_to`, by name or, for unnamed exports, by ordinal.
+    fn build_export_directory(&self, rva: u32) -> Vec<u8> {
+        const DIRECTORY_SIZE: usize = 40;
+
+        let function_count = self
+            .exports
+            .iter()
+            .map(|(ordinal, _)| ordinal - self.ordinal_base + 1)
+            .max()
+            .unwrap_or(0) as usize;
+        let mut names: Vec<(&[u8], u32)> = self
+            .exports
+            .iter()
+            .filter_map(|(ordinal, name)| Some((name.as_deref()?, *ordinal)))
+            .collect();
+        // The loader binary-searches the name table
+        names.sort();
+
+        let functions_offset = DIRECTORY_SIZE;
+        let names_offset = functions_offset + 4 * function_count;
+        let name_ordinals_offset = names_offset + 4 * names.len();
+        let strings_offset = name_ordinals_offset + 2 * names.len();
+
+        let mut strings = Vec::new();
+        let mut add_string = |string: &[u8]| {
+            let string_rva = rva + (strings_offset + strings.len()) as u32;
+            strings.extend_from_slice(string);
+            strings.push(0);
+            string_rva
+        };
+
+        let dll_name_rva = add_string(&self.dll_name);
+        let mut functions = vec![0u32; function_count];
+        for (ordinal, name) in &self.exports {
+            let mut forwarder = format!("{}.", self.forward_to).into_bytes();
+            match name {
+                Some(name) => forwarder.extend_from_slice(name),
+                None => forwarder.extend_from_slice(format!("#{ordinal}").as_bytes()),
+            }
+            functions[(ordinal - self.ordinal_base) as usize] = add_string(&forwarder);
+        }
+        let name_rvas: Vec<u32> = names.iter().map(|(name, _)| add_string(name)).collect();
+
+        let mut out = Vec::with_capacity(strings_offset + strings.len());
+        // Characteristics, TimeDateStamp, MajorVersion and MinorVersion
+        out.extend_from_slice(&[0; 12]);
+        for field in [
+            dll_name_rva,
+            self.ordinal_base,
+            function_count as u32,
+            names.len() as u32,
+            rva + functions_offset as u32,
+            rva + names_offset as u32,
+            rva + name_ordinals_offset as u32,
+        ] {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+        for function in functions {
+            out.extend_from_slice(&function.to_le_bytes());
+        }
+        for name_rva in name_rvas {
+            out.extend_from_slice(&name_rva.to_le_bytes());
+        }
+        for (_, ordinal) in &names {
+            out.extend_from_slice(&((ordinal - self.ordinal_base) as u16).to_le_bytes());
+        }
+        out.extend_from_slice(&strings);
+        out
+    }
 }
 
 impl BinaryProcessor {
     pub fn new(data: Vec<u8>) -> Result<Self> {
+        if let Ok(object::FileKind::MachOFat32 | object::FileKind::MachOFat64) =
+            object::FileKind::parse(data.as_slice())
+        {
+            anyhow::bail!(
+                "Fat (universal) Mach-O binaries are not supported, thin your dylib first (e.g. lipo -thin arm64 -output thin.dylib universal.dylib)"
+            );
+        }
         let format = match object::read::File::parse(data.as_slice())? {
             object::read::File::Elf32(_) | object::read::File::Elf64(_) => ObjectFormat::Elf,
             object::read::File::Pe32(_) | object::read::File::Pe64(_) => ObjectFormat::Pe,
-            _ => anyhow::bail!("Invalid ELF/PE binary"),
+            object::read::File::MachO64(file) if file.is_little_endian() => ObjectFormat::MachO,
+            object::read::File::MachO32(_) | object::read::File::MachO64(_) => {
+                anyhow::bail!("Only 64-bit little-endian Mach-O binaries are supported")
+            }
+            _ => anyhow::bail!("Invalid ELF/PE/Mach-O binary"),
         };
 
-        Ok(Self { data, format })
+        Ok(Self {
+            data,
+            format,
+            pe_section_name: None,
+            embedded_version: 1,
+            proxy_exports: None,
+            base64_encoding: false,
+            embed_vaddr: None,
+            embed_auto_align: true,
+            anti_detection_keywords: Vec::new(),
+        })
+    }
+
+    /// Sets the name of the section holding the embedded data in PE files.
+    /// Defaults to a random name.
+    pub fn set_pe_section_name(&mut self, name: [u8; 8]) {
+        self.pe_section_name = Some(name);
+    }
+
+    /// Replaces the exports of a PE file with forwarders to the DLL it proxies.
+    pub fn set_proxy_exports(&mut self, proxy_exports: ProxyExports) {
+        self.proxy_exports = Some(proxy_exports);
+    }
+
+    /// Stores the embedded data base64-encoded, for transports that mangle raw bytes.
+    pub fn set_base64_encoding(&mut self, base64_encoding: bool) {
+        self.base64_encoding = base64_encoding;
+    }
+
+    /// Places the ELF segment holding the embedded data at `vaddr` instead of
+    /// after the highest mapped address.
+    pub fn set_embed_vaddr(&mut self, vaddr: u64) {
+        self.embed_vaddr = Some(vaddr);
+    }
+
+    /// Adds keywords for [`Self::anti_anti_frida`] to replace, in `.dynstr` and
+    /// `.rodata`, before the built-in ones.
+    pub fn set_anti_detection_keywords(&mut self, keywords: &[String]) -> Result<()> {
+        for keyword in keywords {
+            validate_anti_detection_keyword(keyword)?;
+        }
+        let mut keywords = keywords.to_vec();
+        // Longer keywords first, so none is broken up by a shorter one it contains
+        keywords.sort_by_key(|keyword| std::cmp::Reverse(keyword.len()));
+        keywords.dedup();
+        self.anti_detection_keywords = keywords;
+        Ok(())
+    }
+
+    /// Whether the embedded data's address is rounded up to the page size.
+    /// Defaults to true. Unaligned addresses keep the file offset congruent.
+    pub fn set_embed_auto_align(&mut self, auto_align: bool) {
+        self.embed_auto_align = auto_align;
     }
 
-    pub fn find_embedded_config(&self) -> Option<usize> {
+    /// Sets the `EmbeddedConfig` version to emit, see [`EMBEDDED_CONFIG_VERSIONS`].
+    /// Defaults to 1.
+    pub fn set_embedded_version(&mut self, version: i32) -> Result<()> {
+        if !EMBEDDED_CONFIG_VERSIONS.contains(&version) {
+            anyhow::bail!(
+                "Unsupported embedded config version {version} (supported: {}..={})",
+                EMBEDDED_CONFIG_VERSIONS.start(),
+                EMBEDDED_CONFIG_VERSIONS.end()
+            );
+        }
+        self.embedded_version = version;
+        Ok(())
+    }
+
+    /// Finds the unpopulated config placeholder, returning its offset and the
+    /// version the prebuilt loader declares.
+    pub fn find_embedded_config(&self) -> Option<(usize, i32)> {
         let magic1_bytes = (0x0d000721i32).to_le_bytes();
         let magic2_bytes = (0x1f8a4e2bi32).to_le_bytes();
 
-        (0..self
-            .data
-            .len()
-            .saturating_sub(std::mem::size_of::<EmbeddedConfig>()))
-            .find(|&i| {
-                self.data[i..i + 4] == magic1_bytes
-                    && self.data[i + 4..i + 8] == magic2_bytes
-                    && self.data[i + 8..i + 12] == (1i32).to_le_bytes()
-                    && self.data[i + 12..i + 16] == [0, 0, 0, 0]
-                    && self.data[i + 16..i + 20] == [0, 0, 0, 0]
-            })
+        (0..self.data.len().saturating_sub(EmbeddedConfig::size(1))).find_map(|i| {
+            let version = i32::from_le_bytes(self.data[i + 8..i + 12].try_into().ok()?);
+            (self.data[i..i + 4] == magic1_bytes
+                && self.data[i + 4..i + 8] == magic2_bytes
+                && EMBEDDED_CONFIG_VERSIONS.contains(&version)
+                && self.data[i + 12..i + 16] == [0, 0, 0, 0]
+                && self.data[i + 16..i + 20] == [0, 0, 0, 0])
+            .then_some((i, version))
+        })
+    }
+
+    /// Explains why [`Self::find_embedded_config`] found no placeholder, see
+    /// [`diagnose_embedded_config`].
+    pub fn diagnose_embedded_config(&self) -> String {
+        diagnose_embedded_config(&self.data)
     }
 }
 
+/// Describes the first config carrying the fripack magic values, ignoring its
+/// version and fields, to tell an already populated or unsupported config
+/// apart from a binary that isn't a fripack build at all.
+pub fn diagnose_embedded_config(data: &[u8]) -> String {
+    let magic = [0x0d000721i32.to_le_bytes(), 0x1f8a4e2bi32.to_le_bytes()].concat();
+
+    let found = memchr::memmem::find_iter(data, &magic).find_map(|offset| {
+        EmbeddedConfig::from_bytes(data.get(offset..)?).map(|config| (offset, config))
+    });
+    let Some((offset, config)) = found else {
+        return "no fripack magic found, this is not a fripack binary".to_string();
+    };
+    let (version, data_size, data_offset) = (config.version, config.data_size, config.data_offset);
+    let mut diagnostic = format!(
+        "found fripack magic at {offset:#x} but version={version}/data_size={data_size}/data_offset={data_offset}"
+    );
+    if !EMBEDDED_CONFIG_VERSIONS.contains(&version) {
+        diagnostic.push_str(&format!(
+            " (supported versions: {}..={})",
+            EMBEDDED_CONFIG_VERSIONS.start(),
+            EMBEDDED_CONFIG_VERSIONS.end()
+        ));
+    }
+    diagnostic
+}
+
+/// Makes the library at `path` load `lib_name`: a `DT_NEEDED` entry for ELF, an
+/// import descriptor for PE.
 pub fn add_needed_library_to_file(path: &std::path::Path, lib_name: &str) -> Result<()> {
+    let data = std::fs::read(path)?;
+    let out_data = match object::FileKind::parse(data.as_slice())? {
+        object::FileKind::Pe32 => add_pe_import::<pe::ImageNtHeaders32>(&data, lib_name)?,
+        object::FileKind::Pe64 => add_pe_import::<pe::ImageNtHeaders64>(&data, lib_name)?,
+        _ => return add_elf_needed_library(path, lib_name),
+    };
+    if let Some(out_data) = out_data {
+        std::fs::write(path, out_data)?;
+        info!("Added needed library '{}' to the import table", lib_name);
+    }
+    Ok(())
+}
+
+fn add_elf_needed_library(path: &std::path::Path, lib_name: &str) -> Result<()> {
     let mut file = std::fs::File::open(path)?;
     if let Some(lief::Binary::ELF(mut elf)) = lief::Binary::from(&mut file) {
         elf.add_library(lib_name);
@@ -112,35 +495,537 @@ pub fn add_needed_library_to_file(path: &std::path::Path, lib_name: &str) -> Res
     }
 }
 
-impl BinaryProcessor {
-    pub fn add_embedded_config_data(&mut self, config_data: &[u8], use_xz: bool) -> Result<()> {
-        let data = if use_xz {
-            self.compress_xz(config_data)?
+/// Locates a populated embedded config in a fripack-built binary and returns it
+/// together with the (still compressed) embedded data.
+pub fn read_embedded_data(data: &[u8]) -> Result<(EmbeddedConfig, Vec<u8>)> {
+    let (_, config, data_range) = locate_embedded_data(data)?;
+    Ok((config, data[data_range].to_vec()))
+}
+
+/// Returns the file offset of the populated embedded config, the config and
+/// the file range of its data.
+fn locate_embedded_data(data: &[u8]) -> Result<(usize, EmbeddedConfig, Range<usize>)> {
+    let magic = [0x0d000721i32.to_le_bytes(), 0x1f8a4e2bi32.to_le_bytes()].concat();
+
+    let (config_offset, config) = memchr::memmem::find_iter(data, &magic)
+        .filter_map(|offset| {
+            let config = EmbeddedConfig::from_bytes(data.get(offset..)?)?;
+            let version = config.version;
+            (config.data_size > 0 && EMBEDDED_CONFIG_VERSIONS.contains(&version))
+                .then_some((offset, config))
+        })
+        .next()
+        .with_context(|| {
+            format!(
+                "No populated fripack config found ({}), is this a fripack-built binary?",
+                diagnose_embedded_config(data)
+            )
+        })?;
+
+    // `data_offset` is relative to the config's virtual address, map it back to the file
+    let file = object::read::File::parse(data)?;
+    let config_address = file
+        .segments()
+        .find_map(|seg| {
+            let (start, size) = seg.file_range();
+            let offset = config_offset as u64;
+            (start <= offset && offset < start + size).then(|| seg.address() + offset - start)
+        })
+        .context("Embedded config is not mapped by any segment")?;
+    let data_address = config_address.wrapping_add_signed(config.data_offset as i64);
+    let data_file_offset = file
+        .segments()
+        .find_map(|seg| {
+            let (start, size) = seg.file_range();
+            (seg.address() <= data_address && data_address < seg.address() + size)
+                .then(|| start + data_address - seg.address())
+        })
+        .context("Embedded data is not mapped by any segment")? as usize;
+
+    let data_range = data_file_offset..data_file_offset + config.data_size as usize;
+    if data_range.end > data.len() {
+        anyhow::bail!("Embedded data lies outside of the file");
+    }
+
+    Ok((config_offset, config, data_range))
+}
+
+/// Reverses the compression (and encoding) applied by `add_embedded_config_data`.
+pub fn decompress_embedded_data(
+    compression: u8,
+    data: &[u8],
+    zstd_dict: Option<&[u8]>,
+) -> Result<Vec<u8>> {
+    use std::io::Read;
+
+    if compression & BASE64_ENCODING_FLAG != 0 {
+        use base64::Engine;
+
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .context("Failed to decode base64 embedded data")?;
+        return decompress_embedded_data(compression & !BASE64_ENCODING_FLAG, &decoded, zstd_dict);
+    }
+
+    match compression {
+        0 => Ok(data.to_vec()),
+        1 => {
+            let mut out = Vec::new();
+            xz2::read::XzDecoder::new(data).read_to_end(&mut out)?;
+            Ok(out)
+        }
+        2 => {
+            let mut decoder = match zstd_dict {
+                Some(dict) => zstd::stream::read::Decoder::with_dictionary(data, dict)?,
+                None => zstd::stream::read::Decoder::with_buffer(data)?,
+            };
+            let mut out = Vec::new();
+            decoder
+                .read_to_end(&mut out)
+                .context("Failed to decompress zstd data (was it built with a dictionary?)")?;
+            Ok(out)
+        }
+        3 => lzma2_raw(data, false).context("Failed to decompress raw LZMA2 data"),
+        other => anyhow::bail!("Unknown embedded data compression: {other}"),
+    }
+}
+
+/// Encodes or decodes a raw LZMA2 stream with the preset 6 options (8 MiB
+/// dictionary), which the loader's raw decoder must match.
+fn lzma2_raw(data: &[u8], encode: bool) -> Result<Vec<u8>> {
+    use lzma_sys::*;
+
+    // SAFETY: the options, filter chain and stream outlive every liblzma call,
+    // and `next_out` always points at `avail_out` spare bytes of `out`
+    unsafe {
+        let mut options: lzma_options_lzma = std::mem::zeroed();
+        if lzma_lzma_preset(&mut options, LZMA_PRESET_DEFAULT) != 0 {
+            anyhow::bail!("Unsupported LZMA2 preset");
+        }
+        let filters = [
+            lzma_filter {
+                id: LZMA_FILTER_LZMA2,
+                options: &mut options as *mut lzma_options_lzma as *mut std::ffi::c_void,
+            },
+            lzma_filter {
+                id: LZMA_VLI_UNKNOWN,
+                options: std::ptr::null_mut(),
+            },
+        ];
+        let mut stream: lzma_stream = std::mem::zeroed();
+        let ret = if encode {
+            lzma_raw_encoder(&mut stream, filters.as_ptr())
+        } else {
+            lzma_raw_decoder(&mut stream, filters.as_ptr())
+        };
+        if ret != LZMA_OK {
+            anyhow::bail!("Failed to initialize the LZMA2 coder (error {ret})");
+        }
+
+        stream.next_in = data.as_ptr();
+        stream.avail_in = data.len();
+        let mut out: Vec<u8> = Vec::with_capacity(data.len() + 64);
+        let ret = loop {
+            if out.len() == out.capacity() {
+                out.reserve(out.capacity());
+            }
+            let spare = out.capacity() - out.len();
+            stream.next_out = out.as_mut_ptr().add(out.len());
+            stream.avail_out = spare;
+            let ret = lzma_code(&mut stream, LZMA_FINISH);
+            out.set_len(out.len() + spare - stream.avail_out);
+            if ret != LZMA_OK {
+                break ret;
+            }
+        };
+        lzma_end(&mut stream);
+        if ret != LZMA_STREAM_END {
+            anyhow::bail!("LZMA2 stream error {ret} (truncated or corrupt data?)");
+        }
+        Ok(out)
+    }
+}
+
+/// Offsets of the `__LINKEDIT` file offset fields in a load command of type `cmd`.
+fn linkedit_offset_fields(cmd: u32) -> &'static [usize] {
+    use std::mem::offset_of;
+
+    match cmd {
+        macho::LC_SYMTAB => &[
+            offset_of!(macho::SymtabCommand<LE>, symoff),
+            offset_of!(macho::SymtabCommand<LE>, stroff),
+        ],
+        macho::LC_DYSYMTAB => &[
+            offset_of!(macho::DysymtabCommand<LE>, tocoff),
+            offset_of!(macho::DysymtabCommand<LE>, modtaboff),
+            offset_of!(macho::DysymtabCommand<LE>, extrefsymoff),
+            offset_of!(macho::DysymtabCommand<LE>, indirectsymoff),
+            offset_of!(macho::DysymtabCommand<LE>, extreloff),
+            offset_of!(macho::DysymtabCommand<LE>, locreloff),
+        ],
+        macho::LC_DYLD_INFO | macho::LC_DYLD_INFO_ONLY => &[
+            offset_of!(macho::DyldInfoCommand<LE>, rebase_off),
+            offset_of!(macho::DyldInfoCommand<LE>, bind_off),
+            offset_of!(macho::DyldInfoCommand<LE>, weak_bind_off),
+            offset_of!(macho::DyldInfoCommand<LE>, lazy_bind_off),
+            offset_of!(macho::DyldInfoCommand<LE>, export_off),
+        ],
+        macho::LC_CODE_SIGNATURE
+        | macho::LC_SEGMENT_SPLIT_INFO
+        | macho::LC_FUNCTION_STARTS
+        | macho::LC_DATA_IN_CODE
+        | macho::LC_DYLIB_CODE_SIGN_DRS
+        | macho::LC_LINKER_OPTIMIZATION_HINT
+        | macho::LC_DYLD_EXPORTS_TRIE
+        | macho::LC_DYLD_CHAINED_FIXUPS => &[offset_of!(macho::LinkeditDataCommand<LE>, dataoff)],
+        _ => &[],
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .context("Truncated Mach-O header")?;
+    Ok(u32::from_le_bytes(bytes.try_into()?))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    let bytes = data
+        .get(offset..offset + 8)
+        .context("Truncated Mach-O header")?;
+    Ok(u64::from_le_bytes(bytes.try_into()?))
+}
+
+/// Returns the `DT_NEEDED` entries of an ELF shared library, or the imported
+/// DLLs of a PE file.
+pub fn needed_libraries(data: &[u8]) -> Result<Vec<String>> {
+    let descriptors = match object::FileKind::parse(data)? {
+        object::FileKind::Pe32 => Some(pe_import_descriptors::<pe::ImageNtHeaders32>(data)?),
+        object::FileKind::Pe64 => Some(pe_import_descriptors::<pe::ImageNtHeaders64>(data)?),
+        _ => None,
+    };
+    if let Some(descriptors) = descriptors {
+        return Ok(descriptors.into_iter().map(|(_, name)| name).collect());
+    }
+
+    let elf = object::build::elf::Builder::read(data)?;
+    let mut needed = Vec::new();
+    for section in elf.sections.iter() {
+        if let object::build::elf::SectionData::Dynamic(dynamics) = &section.data {
+            for dynamic in dynamics {
+                if let Dynamic::String { tag, val } = dynamic {
+                    if *tag == DT_NEEDED {
+                        needed.push(val.to_string());
+                    }
+                }
+            }
+        }
+    }
+    Ok(needed)
+}
+
+/// The import descriptors of a PE file with the names of their DLLs. Names are
+/// looked up by RVA, as `object`'s import table only finds them in the section of
+/// the descriptors, which isn't where they are after [`add_pe_import`].
+fn pe_import_descriptors<Pe: ImageNtHeaders>(
+    data: &[u8],
+) -> Result<Vec<(&pe::ImageImportDescriptor, String)>> {
+    let dos_header = pe::ImageDosHeader::parse(data)?;
+    let mut offset = dos_header.nt_headers_offset().into();
+    let (nt_headers, data_directories) = Pe::parse(data, &mut offset)?;
+    let sections = nt_headers.file_header().sections(data, offset)?;
+
+    let mut descriptors = Vec::new();
+    let Some(import_table) = data_directories.import_table(data, &sections)? else {
+        return Ok(descriptors);
+    };
+    let mut iter = import_table.descriptors()?;
+    while let Some(descriptor) = iter.next()? {
+        let name = sections
+            .pe_data_at(data, descriptor.name.get(LE))
+            .and_then(|name| name.split(|&byte| byte == 0).next())
+            .context("Invalid PE import descriptor name")?;
+        descriptors.push((descriptor, String::from_utf8_lossy(name).into_owned()));
+    }
+    Ok(descriptors)
+}
+
+/// Ordinal the import descriptor added by [`add_pe_import`] imports. The loader
+/// only maps a DLL something is imported from, and ordinal 1 is the first export
+/// of any DLL with the default ordinal base.
+const PE_IMPORT_ORDINAL: u16 = 1;
+
+/// Adds an import descriptor for `dll_name` to a PE file, in a new section that
+/// also holds a copy of the existing descriptors, and points the import
+/// directory at it. Returns `None` if the DLL is already imported.
+fn add_pe_import<Pe: ImageNtHeaders>(in_data: &[u8], dll_name: &str) -> Result<Option<Vec<u8>>> {
+    let in_dos_header = pe::ImageDosHeader::parse(in_data)?;
+    let mut offset = in_dos_header.nt_headers_offset().into();
+    let in_rich_header = object::read::pe::RichHeaderInfo::parse(in_data, offset);
+    let (in_nt_headers, in_data_directories) = Pe::parse(in_data, &mut offset)?;
+    let in_file_header = in_nt_headers.file_header();
+    let in_optional_header = in_nt_headers.optional_header();
+    let in_sections = in_file_header.sections(in_data, offset)?;
+
+    // The existing descriptors keep pointing at their thunks and names
+    let mut descriptors = Vec::new();
+    for (descriptor, name) in pe_import_descriptors::<Pe>(in_data)? {
+        if name.eq_ignore_ascii_case(dll_name) {
+            info!("'{dll_name}' is already imported");
+            return Ok(None);
+        }
+        descriptors.extend_from_slice(object::pod::bytes_of(descriptor));
+    }
+
+    // Layout of the new section: the descriptors (with ours and the null
+    // terminator), then our lookup table, import address table and DLL name
+    let is_64 = in_nt_headers.is_type_64();
+    let thunk_size = if is_64 { 8 } else { 4 };
+    let descriptor_size = std::mem::size_of::<pe::ImageImportDescriptor>();
+    let directory_size = descriptors.len() + 2 * descriptor_size;
+    let lookup_offset = directory_size.next_multiple_of(thunk_size);
+    let address_offset = lookup_offset + 2 * thunk_size;
+    let name_offset = address_offset + 2 * thunk_size;
+    let section_size = (name_offset + dll_name.len() + 1) as u32;
+
+    let mut out_data = Vec::new();
+    let mut writer = object::write::pe::Writer::new(
+        is_64,
+        in_optional_header.section_alignment(),
+        in_optional_header.file_alignment(),
+        &mut out_data,
+    );
+
+    writer.reserve_dos_header_and_stub();
+    if let Some(in_rich_header) = in_rich_header.as_ref() {
+        writer.reserve(in_rich_header.length as u32 + 8, 4);
+    }
+    writer.reserve_nt_headers(in_data_directories.len());
+
+    // Bound imports describe the old import table, the loader resolves
+    // everything itself without them
+    let cert_dir = in_data_directories
+        .get(pe::IMAGE_DIRECTORY_ENTRY_SECURITY)
+        .map(pe::ImageDataDirectory::address_range);
+    let reloc_dir = in_data_directories
+        .get(pe::IMAGE_DIRECTORY_ENTRY_BASERELOC)
+        .map(pe::ImageDataDirectory::address_range);
+    for (i, dir) in in_data_directories.iter().enumerate() {
+        if dir.virtual_address.get(LE) == 0
+            || i == pe::IMAGE_DIRECTORY_ENTRY_SECURITY
+            || i == pe::IMAGE_DIRECTORY_ENTRY_BASERELOC
+            || i == pe::IMAGE_DIRECTORY_ENTRY_IMPORT
+            || i == pe::IMAGE_DIRECTORY_ENTRY_BOUND_IMPORT
+        {
+            continue;
+        }
+        writer.set_data_directory(i, dir.virtual_address.get(LE), dir.size.get(LE));
+    }
+
+    // The ".reloc" section is recreated
+    let mut in_sections_index = Vec::new();
+    for (index, in_section) in in_sections.enumerate() {
+        if reloc_dir == Some(in_section.pe_address_range()) {
+            continue;
+        }
+        in_sections_index.push(index);
+    }
+    let mut out_sections_len = in_sections_index.len() + 1;
+    if reloc_dir.is_some() {
+        out_sections_len += 1;
+    }
+    writer.reserve_section_headers(out_sections_len as u16);
+
+    let mut in_sections_data = Vec::new();
+    for index in &in_sections_index {
+        let in_section = in_sections.section(*index)?;
+        let range = writer.reserve_section(
+            in_section.name,
+            in_section.characteristics.get(LE),
+            in_section.virtual_size.get(LE),
+            in_section.size_of_raw_data.get(LE),
+        );
+        debug_assert_eq!(range.virtual_address, in_section.virtual_address.get(LE));
+        in_sections_data.push((range.file_offset, in_section.pe_data(in_data)?));
+    }
+
+    // The loader writes the import address table, so the section is writable
+    let import_range = writer.reserve_section(
+        *b".fpidata",
+        pe::IMAGE_SCN_CNT_INITIALIZED_DATA | pe::IMAGE_SCN_MEM_READ | pe::IMAGE_SCN_MEM_WRITE,
+        section_size,
+        section_size,
+    );
+    let rva = import_range.virtual_address;
+    writer.set_data_directory(pe::IMAGE_DIRECTORY_ENTRY_IMPORT, rva, directory_size as u32);
+
+    let mut section = descriptors;
+    let descriptor = pe::ImageImportDescriptor {
+        original_first_thunk: object::U32Bytes::new(LE, rva + lookup_offset as u32),
+        time_date_stamp: object::U32Bytes::new(LE, 0),
+        forwarder_chain: object::U32Bytes::new(LE, 0),
+        name: object::U32Bytes::new(LE, rva + name_offset as u32),
+        first_thunk: object::U32Bytes::new(LE, rva + address_offset as u32),
+    };
+    section.extend_from_slice(object::pod::bytes_of(&descriptor));
+    section.resize(lookup_offset, 0);
+    // The lookup table and the import address table start out the same
+    for _ in 0..2 {
+        if is_64 {
+            let thunk = pe::IMAGE_ORDINAL_FLAG64 | u64::from(PE_IMPORT_ORDINAL);
+            section.extend_from_slice(&thunk.to_le_bytes());
+            section.extend_from_slice(&0u64.to_le_bytes());
         } else {
-            config_data.to_vec()
+            let thunk = pe::IMAGE_ORDINAL_FLAG32 | u32::from(PE_IMPORT_ORDINAL);
+            section.extend_from_slice(&thunk.to_le_bytes());
+            section.extend_from_slice(&0u32.to_le_bytes());
+        }
+    }
+    section.extend_from_slice(dll_name.as_bytes());
+    section.push(0);
+    debug_assert_eq!(section.len(), section_size as usize);
+
+    if reloc_dir.is_some() {
+        let mut blocks = in_data_directories
+            .relocation_blocks(in_data, &in_sections)?
+            .unwrap();
+        while let Some(block) = blocks.next()? {
+            for reloc in block {
+                writer.add_reloc(reloc.virtual_address, reloc.typ);
+            }
+        }
+        writer.reserve_reloc_section();
+    }
+    if let Some((_, size)) = cert_dir {
+        warn!("The PE file is signed, adding an import invalidates its signature");
+        writer.reserve_certificate_table(size);
+    }
+
+    writer.write_dos_header_and_stub()?;
+    if let Some(in_rich_header) = in_rich_header.as_ref() {
+        writer.write_align(4);
+        writer.write(&in_data[in_rich_header.offset..][..in_rich_header.length + 8]);
+    }
+    writer.write_nt_headers(object::write::pe::NtHeaders {
+        machine: in_file_header.machine.get(LE),
+        time_date_stamp: in_file_header.time_date_stamp.get(LE),
+        characteristics: in_file_header.characteristics.get(LE),
+        major_linker_version: in_optional_header.major_linker_version(),
+        minor_linker_version: in_optional_header.minor_linker_version(),
+        address_of_entry_point: in_optional_header.address_of_entry_point(),
+        image_base: in_optional_header.image_base(),
+        major_operating_system_version: in_optional_header.major_operating_system_version(),
+        minor_operating_system_version: in_optional_header.minor_operating_system_version(),
+        major_image_version: in_optional_header.major_image_version(),
+        minor_image_version: in_optional_header.minor_image_version(),
+        major_subsystem_version: in_optional_header.major_subsystem_version(),
+        minor_subsystem_version: in_optional_header.minor_subsystem_version(),
+        subsystem: in_optional_header.subsystem(),
+        dll_characteristics: in_optional_header.dll_characteristics(),
+        size_of_stack_reserve: in_optional_header.size_of_stack_reserve(),
+        size_of_stack_commit: in_optional_header.size_of_stack_commit(),
+        size_of_heap_reserve: in_optional_header.size_of_heap_reserve(),
+        size_of_heap_commit: in_optional_header.size_of_heap_commit(),
+    });
+    writer.write_section_headers();
+    for (offset, data) in in_sections_data {
+        writer.write_section(offset, data);
+    }
+    writer.write_section(import_range.file_offset, &section);
+    writer.write_reloc_section();
+    if let Some((address, size)) = cert_dir {
+        writer.write_certificate_table(&in_data[address as usize..][..size as usize]);
+    }
+    debug_assert_eq!(writer.reserved_len() as usize, writer.len());
+
+    Ok(Some(out_data))
+}
+
+impl BinaryProcessor {
+    pub fn add_embedded_config_data(
+        &mut self,
+        config_data: &[u8],
+        compression: &Compression,
+    ) -> Result<()> {
+        // Check before any surgery, a missing placeholder would only be noticed
+        // after the data has been appended
+        let Some((_, loader_version)) = self.find_embedded_config() else {
+            anyhow::bail!(
+                "prebuilt does not contain a fripack config placeholder: {} - expected a zeroed placeholder",
+                self.diagnose_embedded_config()
+            );
         };
-        let mut embedded_config = EmbeddedConfig::new(data.len() as i32, 0, use_xz);
+        if loader_version != self.embedded_version {
+            warn!(
+                "Emitting embedded config version {} for a prebuilt that declares version {}",
+                self.embedded_version, loader_version
+            );
+        }
+        if self.base64_encoding && (self.embedded_version < 3 || loader_version < 3) {
+            anyhow::bail!(
+                "embedEncoding \"base64\" requires a loader with embeddedVersion 3 (prebuilt declares {loader_version})"
+            );
+        }
+        // A version 4 config is larger and would overwrite what follows an older placeholder
+        if self.embedded_version >= 4 && loader_version < 4 {
+            anyhow::bail!(
+                "embeddedVersion 4 requires a loader declaring version 4 (prebuilt declares {loader_version})"
+            );
+        }
+        let zstd_dict_id = compression_dict_id(compression)?;
+        if zstd_dict_id != 0 && self.embedded_version < 4 {
+            anyhow::bail!(
+                "A zstd dictionary requires a loader with embeddedVersion 4, which records the dictionary id (prebuilt declares {loader_version})"
+            );
+        }
 
+        let (data, compression_id) = self.encode_config_data(config_data, compression)?;
+        let mut embedded_config =
+            EmbeddedConfig::new(self.embedded_version, data.len() as i32, 0, compression_id);
+        embedded_config.zstd_dict_id = zstd_dict_id;
         match self.format {
             ObjectFormat::Elf => {
-                let data_cloned = self.data.clone();
-                let elf = object::build::elf::Builder::read(data_cloned.as_slice())?;
-                self.data = vec![];
+                // The builder borrows the input while writing the output, so the
+                // buffer is moved out instead of cloned
+                let input = std::mem::take(&mut self.data);
+                let elf = object::build::elf::Builder::read(input.as_slice())?;
                 // re-write ELF to ensure segments are properly aligned
                 elf.write(&mut self.data)?;
-                let data_cloned = self.data.clone();
-                let mut elf = object::build::elf::Builder::read(data_cloned.as_slice())?;
-                let vaddr_spare_area = elf
-                    .segments
-                    .iter()
-                    .map(|seg| seg.p_vaddr + seg.p_memsz)
-                    .max()
-                    .unwrap_or(0);
-
-                let vaddr_spare_area = (vaddr_spare_area + 0xfff) & !0xfff;
-                info!("vaddr_spare_area: {vaddr_spare_area:#x}");
+                drop(input);
+                let aligned = std::mem::take(&mut self.data);
+                let mut elf = object::build::elf::Builder::read(aligned.as_slice())?;
+                let (vaddr_spare_area, vaddr_source) = match self.embed_vaddr {
+                    Some(vaddr) => (vaddr, "embedVaddr"),
+                    None => (
+                        elf.segments
+                            .iter()
+                            .map(|seg| seg.p_vaddr + seg.p_memsz)
+                            .max()
+                            .unwrap_or(0),
+                        "auto",
+                    ),
+                };
+                let vaddr_spare_area = if self.embed_auto_align {
+                    (vaddr_spare_area + 0xfff) & !0xfff
+                } else {
+                    vaddr_spare_area
+                };
+                // Segments are mapped in whole pages
+                let spare_area_end = vaddr_spare_area + data.len() as u64;
+                if let Some(seg) = elf.segments.iter().find(|seg| {
+                    seg.p_type == PT_LOAD
+                        && (seg.p_vaddr & !0xfff) < spare_area_end
+                        && vaddr_spare_area & !0xfff < (seg.p_vaddr + seg.p_memsz + 0xfff) & !0xfff
+                }) {
+                    anyhow::bail!(
+                        "Embedded data at {vaddr_spare_area:#x}..{spare_area_end:#x} ({vaddr_source}) overlaps the pages of the segment at {:#x}..{:#x}",
+                        seg.p_vaddr,
+                        seg.p_vaddr + seg.p_memsz
+                    );
+                }
+                info!("→ Embedded data vaddr: {vaddr_spare_area:#x} ({vaddr_source})");
 
-                let mut offset_spare_area = self.data.len() as u64;
+                let mut offset_spare_area = aligned.len() as u64;
 
                 let fripack_section_id = {
                     let new_segment = elf.segments.add_load_segment(PF_R | PF_W, 4096);
@@ -159,6 +1044,13 @@ impl BinaryProcessor {
                     new_segment.append_section(new_section);
                     new_section.sh_addr = vaddr_spare_area;
                     new_segment.p_vaddr = vaddr_spare_area;
+                    // The file offset must match an unaligned address modulo the page size
+                    let page_offset = vaddr_spare_area & 0xfff;
+                    if page_offset != 0 {
+                        new_section.sh_addralign = 1;
+                        new_section.sh_offset += page_offset;
+                        new_segment.p_offset += page_offset;
+                    }
                     offset_spare_area += new_section.sh_size;
                     offset_spare_area = (offset_spare_area + 0xfff) & !0xfff;
 
@@ -197,12 +1089,11 @@ impl BinaryProcessor {
                 header_load_segment.p_filesz += size_diff;
                 header_load_segment.p_memsz += size_diff;
 
-                self.data = vec![];
                 elf.write(&mut self.data)?;
-                let data_cloned = self.data.clone();
-                let elf = object::build::elf::Builder::read(data_cloned.as_slice())?;
+                drop(aligned);
+                let elf = object::build::elf::Builder::read(self.data.as_slice())?;
                 // update embedded config offset
-                let embedded_config_offset = self
+                let (embedded_config_offset, _) = self
                     .find_embedded_config()
                     .context("Failed to find embedded config after adding data")?;
 
@@ -221,18 +1112,24 @@ impl BinaryProcessor {
                     .find(|seg| seg.sections.contains(&fripack_section_id))
                     .context("Failed to find fripack_config segment")?;
 
-                embedded_config.data_offset = (fripack_section_segment.p_offset as i32
-                    - embedded_config_offset as i32)
-                    - (fripack_section_segment.p_offset as i32
-                        - data_section_segment.p_offset as i32)
-                    + (fripack_section_segment.p_vaddr as i32
-                        - data_section_segment.p_vaddr as i32);
+                let data_offset = (fripack_section_segment.p_offset as i64
+                    - embedded_config_offset as i64)
+                    - (fripack_section_segment.p_offset as i64
+                        - data_section_segment.p_offset as i64)
+                    + (fripack_section_segment.p_vaddr as i64
+                        - data_section_segment.p_vaddr as i64);
+                embedded_config.data_offset = i32::try_from(data_offset).with_context(|| {
+                    format!("Embedded data at {vaddr_spare_area:#x} is too far from the embedded config")
+                })?;
                 let embedded_config_bytes = embedded_config.as_bytes();
                 self.data
                     [embedded_config_offset..embedded_config_offset + embedded_config_bytes.len()]
                     .copy_from_slice(&embedded_config_bytes);
             }
             ObjectFormat::Pe => {
+                if self.embed_vaddr.is_some() {
+                    anyhow::bail!("embedVaddr is only supported for ELF binaries");
+                }
                 // Parse the PE file
                 let kind = object::FileKind::parse(self.data.as_slice())?;
                 let out_data = match kind {
@@ -246,10 +1143,196 @@ impl BinaryProcessor {
                 };
                 self.data = out_data;
             }
+            ObjectFormat::MachO => {
+                if self.embed_vaddr.is_some() {
+                    anyhow::bail!("embedVaddr is only supported for ELF binaries");
+                }
+                let data_address = self.add_macho_segment(&data)?;
+                info!("→ Embedded data vaddr: {data_address:#x}");
+
+                let (embedded_config_offset, _) = self
+                    .find_embedded_config()
+                    .context("Failed to find embedded config after adding data")?;
+                let file = object::read::File::parse(self.data.as_slice())?;
+                let config_address = file
+                    .segments()
+                    .find_map(|seg| {
+                        let (start, size) = seg.file_range();
+                        let offset = embedded_config_offset as u64;
+                        (start <= offset && offset < start + size)
+                            .then(|| seg.address() + offset - start)
+                    })
+                    .context("Embedded config is not mapped by any segment")?;
+                embedded_config.data_offset =
+                    i32::try_from(data_address as i64 - config_address as i64)
+                        .context("Embedded data is too far from the embedded config")?;
+                let embedded_config_bytes = embedded_config.as_bytes();
+                self.data
+                    [embedded_config_offset..embedded_config_offset + embedded_config_bytes.len()]
+                    .copy_from_slice(&embedded_config_bytes);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compresses (and encodes) config data, returning it with the id recorded
+    /// in `EmbeddedConfig::compression`.
+    fn encode_config_data(
+        &self,
+        config_data: &[u8],
+        compression: &Compression,
+    ) -> Result<(Vec<u8>, u8)> {
+        let mut data = match compression {
+            Compression::None => config_data.to_vec(),
+            Compression::Xz => self.compress_xz(config_data)?,
+            Compression::Zstd(dict) => self.compress_zstd(config_data, dict.as_deref())?,
+            Compression::Lzma => self.compress_lzma(config_data)?,
+        };
+        let mut compression_id = compression.id();
+        if self.base64_encoding {
+            use base64::Engine;
+
+            data = base64::engine::general_purpose::STANDARD
+                .encode(&data)
+                .into_bytes();
+            compression_id |= BASE64_ENCODING_FLAG;
+        }
+        Ok((data, compression_id))
+    }
+
+    /// Replaces the data embedded in a fripack-built binary, keeping its
+    /// config version. New data that fits is written over the old one. Larger
+    /// data is moved to the end of the file when the old data is the last
+    /// ELF segment, which is where `add_embedded_config_data` puts it.
+    pub fn replace_embedded_config_data(
+        &mut self,
+        config_data: &[u8],
+        compression: &Compression,
+    ) -> Result<()> {
+        let (config_offset, config, old_range) = locate_embedded_data(&self.data)?;
+        let zstd_dict_id = compression_dict_id(compression)?;
+        let version = config.version;
+        if zstd_dict_id != 0 && version < 4 {
+            anyhow::bail!(
+                "A zstd dictionary requires embedded config version 4, the binary has version {version}"
+            );
+        }
+        let (data, compression_id) = self.encode_config_data(config_data, compression)?;
+        self.data[old_range.clone()].fill(0);
+
+        if data.len() <= old_range.len() {
+            self.data[old_range.start..old_range.start + data.len()].copy_from_slice(&data);
+        } else {
+            let ObjectFormat::Elf = self.format else {
+                anyhow::bail!(
+                    "New data ({} bytes) doesn't fit the embedded data of this PE/Mach-O binary ({} bytes), rebuild it instead",
+                    data.len(),
+                    old_range.len()
+                );
+            };
+            self.move_elf_data_segment(&old_range, &data)?;
+        }
+
+        let mut embedded_config = config;
+        embedded_config.data_size = data.len() as i32;
+        embedded_config.compression = compression_id;
+        embedded_config.zstd_dict_id = zstd_dict_id;
+        let embedded_config_bytes = embedded_config.as_bytes();
+        self.data[config_offset..config_offset + embedded_config_bytes.len()]
+            .copy_from_slice(&embedded_config_bytes);
+        Ok(())
+    }
+
+    /// Appends `data` to the file and points the segment (and section) that
+    /// held `old_range` at it. The segment keeps its address, so it must be the
+    /// last one in memory to be able to grow.
+    fn move_elf_data_segment(&mut self, old_range: &Range<usize>, data: &[u8]) -> Result<()> {
+        let file = object::read::File::parse(self.data.as_slice())?;
+        if !file.is_little_endian() {
+            anyhow::bail!("Only little-endian ELF binaries are supported");
+        }
+        let is_64 = file.is_64();
+        let (segment_address, segment_end) = file
+            .segments()
+            .find(|seg| seg.file_range().0 == old_range.start as u64)
+            .map(|seg| (seg.address(), seg.address() + seg.size()))
+            .context("Embedded data is not the start of a segment")?;
+        if file
+            .segments()
+            .any(|seg| seg.address() >= segment_end && seg.size() > 0)
+        {
+            anyhow::bail!("Embedded data segment is not the last one in memory, rebuild instead");
         }
 
+        // Keep the file offset congruent to the address modulo the page size
+        let file_end = (self.data.len() as u64 + 0xfff) & !0xfff;
+        let new_offset = file_end + (segment_address & 0xfff);
+        let new_size = data.len() as u64;
+        self.data.resize(new_offset as usize, 0);
+        self.data.extend_from_slice(data);
+
+        let field = |data: &[u8], offset: usize, wide: bool| -> u64 {
+            if wide {
+                u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+            } else {
+                u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as u64
+            }
+        };
+        let set_field = |data: &mut [u8], offset: usize, wide: bool, value: u64| {
+            if wide {
+                data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            } else {
+                data[offset..offset + 4].copy_from_slice(&(value as u32).to_le_bytes());
+            }
+        };
+
+        // (table offset, entry size, count) from the ELF header
+        let (phoff, phentsize, phnum, shoff, shentsize, shnum) = if is_64 {
+            (
+                field(&self.data, 0x20, true),
+                field(&self.data, 0x36, false) & 0xffff,
+                field(&self.data, 0x38, false) & 0xffff,
+                field(&self.data, 0x28, true),
+                field(&self.data, 0x3a, false) & 0xffff,
+                field(&self.data, 0x3c, false) & 0xffff,
+            )
+        } else {
+            (
+                field(&self.data, 0x1c, false),
+                field(&self.data, 0x2a, false) & 0xffff,
+                field(&self.data, 0x2c, false) & 0xffff,
+                field(&self.data, 0x20, false),
+                field(&self.data, 0x2e, false) & 0xffff,
+                field(&self.data, 0x30, false) & 0xffff,
+            )
+        };
+        // p_offset, p_filesz, p_memsz / sh_offset, sh_size
+        let (p_offset, p_filesz, p_memsz) = if is_64 { (8, 32, 40) } else { (4, 16, 20) };
+        let (sh_offset, sh_size) = if is_64 { (24, 32) } else { (16, 20) };
+
+        for i in 0..phnum {
+            let entry = (phoff + i * phentsize) as usize;
+            if field(&self.data, entry, false) == PT_LOAD as u64
+                && field(&self.data, entry + p_offset, is_64) == old_range.start as u64
+            {
+                set_field(&mut self.data, entry + p_offset, is_64, new_offset);
+                set_field(&mut self.data, entry + p_filesz, is_64, new_size);
+                set_field(&mut self.data, entry + p_memsz, is_64, new_size);
+            }
+        }
+        for i in 0..shnum {
+            let entry = (shoff + i * shentsize) as usize;
+            if field(&self.data, entry + sh_offset, is_64) == old_range.start as u64
+                && field(&self.data, entry + sh_size, is_64) == old_range.len() as u64
+            {
+                set_field(&mut self.data, entry + sh_offset, is_64, new_offset);
+                set_field(&mut self.data, entry + sh_size, is_64, new_size);
+            }
+        }
         Ok(())
     }
+
     fn generate_random_string(len: usize) -> String {
         rand::thread_rng()
             .sample_iter(&rand::distributions::Alphanumeric)
@@ -258,10 +1341,11 @@ impl BinaryProcessor {
             .collect()
     }
 
-    pub fn anti_anti_frida(&mut self) -> Result<()> {
+    /// Replaces telltale frida strings, returning how often each keyword was
+    /// replaced, or `None` if the format isn't supported.
+    pub fn anti_anti_frida(&mut self) -> Result<Option<Vec<(String, usize)>>> {
         if let ObjectFormat::Elf = self.format {
-            let cloned_data = self.data.clone();
-            let obj = object::build::elf::Builder::read(cloned_data.as_slice())?;
+            let obj = object::build::elf::Builder::read(self.data.as_slice())?;
             let rodata_section_range = {
                 let rodata_section = obj
                     .sections
@@ -283,15 +1367,22 @@ impl BinaryProcessor {
             };
 
             let mut replacements = 0;
-
-            let kwd = |s: &'static str| (s.as_bytes(), Self::generate_random_string(s.len()));
-
-            // Define keywords to replace
-            let keywords = [
+            let mut keyword_counts = Vec::new();
+
+            let kwd = |s: &str| (s.to_string(), Self::generate_random_string(s.len()));
+
+            // Define keywords to replace, user-defined ones first so the
+            // built-in ones don't break them up
+            let mut keywords: Vec<_> = self
+                .anti_detection_keywords
+                .iter()
+                .map(|s| kwd(s))
+                .collect();
+            keywords.extend([
                 kwd("frida"),
-                (b"GMainLoop", "pool-6-th".to_string()),
-                (b"gum-js-loop", "pool-6-thre".to_string()),
-                (b"gmain", "Timer".to_string()),
+                ("GMainLoop".to_string(), "pool-6-th".to_string()),
+                ("gum-js-loop".to_string(), "pool-6-thre".to_string()),
+                ("gmain".to_string(), "Timer".to_string()),
                 kwd("gum-js"),
                 kwd("gum"),
                 kwd("gdbus"),
@@ -307,7 +1398,7 @@ impl BinaryProcessor {
                 kwd("GLib"),
                 kwd("agent"),
                 kwd("_Worker"),
-            ];
+            ]);
 
             let keywords_rodata = [
                 "frida",
@@ -327,16 +1418,18 @@ impl BinaryProcessor {
                 "_Worker",
             ];
 
-            for (keyword_bytes, replacement_str) in &keywords {
+            for (keyword, replacement_str) in keywords {
+                let keyword_bytes = keyword.as_bytes();
                 let replace_bytes = replacement_str.as_bytes();
+                let in_rodata = keywords_rodata.contains(&keyword.as_str())
+                    || self.anti_detection_keywords.contains(&keyword);
+                let mut count = 0;
 
                 // Use a sliding window approach with memchr for faster searching
                 let mut pos = 0;
                 while let Some(offset) = memchr::memmem::find(&self.data[pos..], keyword_bytes) {
                     if !dynstr_section_range.contains(&(pos + offset))
-                        && !(rodata_section_range.contains(&(pos + offset))
-                            && keywords_rodata
-                                .contains(&std::str::from_utf8(keyword_bytes).unwrap()))
+                        && !(rodata_section_range.contains(&(pos + offset)) && in_rodata)
                     {
                         pos += offset + keyword_bytes.len();
                         continue;
@@ -344,24 +1437,27 @@ impl BinaryProcessor {
 
                     let i = pos + offset;
                     self.data[i..i + keyword_bytes.len()].copy_from_slice(replace_bytes);
-                    replacements += 1;
+                    count += 1;
                     pos = i + keyword_bytes.len();
                 }
+                debug!("Replaced {count} occurrences of {keyword:?}");
+                replacements += count;
+                keyword_counts.push((keyword, count));
             }
 
             info!("Replaced {} occurrences of keywords", replacements);
 
             // Fix GNU_HASH as we changed the string table
-            let cloned_data = self.data.clone();
-            let mut obj = object::build::elf::Builder::read(cloned_data.as_slice())?;
+            let input = std::mem::take(&mut self.data);
+            let mut obj = object::build::elf::Builder::read(input.as_slice())?;
             obj.delete_orphan_dynamics();
             obj.delete_orphan_symbols();
             obj.set_section_sizes();
-            self.data = vec![];
             obj.write(&mut self.data)?;
+            return Ok(Some(keyword_counts));
         }
 
-        Ok(())
+        Ok(None)
     }
 
     fn copy_pe_file<Pe: ImageNtHeaders>(
@@ -425,8 +1521,11 @@ impl BinaryProcessor {
             out_sections_len += 1;
         }
 
-        // Add one more section for our embedded data
+        // Add one more section for our embedded data, and one for proxy exports
         out_sections_len += 1;
+        if self.proxy_exports.is_some() {
+            out_sections_len += 1;
+        }
 
         writer.reserve_section_headers(out_sections_len as u16);
 
@@ -446,8 +1545,13 @@ impl BinaryProcessor {
         }
 
         // Add our new section for embedded data
-        let mut new_section_name = [0u8; 8];
-        new_section_name[..8].copy_from_slice(&b".fripac\0"[..8]);
+        let new_section_name = match self.pe_section_name {
+            Some(name) => name,
+            None => pe_section_name(&format!(
+                ".{}",
+                Self::generate_random_string(5).to_lowercase()
+            ))?,
+        };
         let new_section_characteristics =
             pe::IMAGE_SCN_CNT_INITIALIZED_DATA | pe::IMAGE_SCN_MEM_READ | pe::IMAGE_SCN_MEM_WRITE;
         let new_section_range = writer.reserve_section(
@@ -457,6 +1561,28 @@ impl BinaryProcessor {
             data.len() as u32,
         );
 
+        // Forwarder strings must lie inside the export directory, so it gets its
+        // own section. Its size doesn't depend on where it is placed.
+        let proxy_export_section = match &self.proxy_exports {
+            Some(proxy_exports) => {
+                let size = proxy_exports.build_export_directory(0).len() as u32;
+                let range = writer.reserve_section(
+                    *b".edata\0\0",
+                    pe::IMAGE_SCN_CNT_INITIALIZED_DATA | pe::IMAGE_SCN_MEM_READ,
+                    size,
+                    size,
+                );
+                let export_directory = proxy_exports.build_export_directory(range.virtual_address);
+                writer.set_data_directory(
+                    pe::IMAGE_DIRECTORY_ENTRY_EXPORT,
+                    range.virtual_address,
+                    size,
+                );
+                Some((range.file_offset, export_directory))
+            }
+            None => None,
+        };
+
         if reloc_dir.is_some() {
             let mut blocks = in_data_directories
                 .relocation_blocks(in_data, &in_sections)?
@@ -509,6 +1635,9 @@ impl BinaryProcessor {
 
         // Write our new section with embedded data
         writer.write_section(new_section_range.file_offset, data);
+        if let Some((offset, export_directory)) = &proxy_export_section {
+            writer.write_section(*offset, export_directory);
+        }
 
         writer.write_reloc_section();
         if let Some((address, size)) = cert_dir {
@@ -519,7 +1648,7 @@ impl BinaryProcessor {
         debug_assert_eq!(writer.reserved_len() as usize, writer.len());
 
         // Now update the embedded config offset
-        let embedded_config_offset = self
+        let (embedded_config_offset, _) = self
             .find_embedded_config()
             .context("Failed to find embedded config after adding data")?;
 
@@ -553,6 +1682,324 @@ impl BinaryProcessor {
         Ok(final_out_data)
     }
 
+    /// Adds a `__FRIPACK` segment holding `data` right before `__LINKEDIT`, which
+    /// moves up in the file and in memory, like `ctf_insert` adds `__CTF`. Chained
+    /// fixups get an empty entry for it, as dyld requires one per segment.
+    /// Returns the address of the data.
+    fn add_macho_segment(&mut self, data: &[u8]) -> Result<u64> {
+        use std::mem::{offset_of, size_of};
+        type Header = macho::MachHeader64<LE>;
+        type Segment = macho::SegmentCommand64<LE>;
+        type Section = macho::Section64<LE>;
+
+        let header_size = size_of::<Header>();
+        let ncmds = read_u32(&self.data, offset_of!(Header, ncmds))?;
+        let sizeofcmds = read_u32(&self.data, offset_of!(Header, sizeofcmds))? as usize;
+        let cmds_end = header_size + sizeofcmds;
+        let page_size = match read_u32(&self.data, offset_of!(Header, cputype))? {
+            macho::CPU_TYPE_ARM64 => 0x4000,
+            _ => 0x1000,
+        };
+
+        // (command offset, vmaddr, fileoff) of __LINKEDIT
+        let mut linkedit = None;
+        // Index of __LINKEDIT among the segments, which the new segment takes
+        let mut linkedit_index = 0;
+        let mut segments_end = 0;
+        let mut first_section_offset = self.data.len();
+        let mut signed = false;
+        let mut offset = header_size;
+        for _ in 0..ncmds {
+            let cmd = read_u32(&self.data, offset)?;
+            let cmdsize = read_u32(&self.data, offset + 4)? as usize;
+            if cmdsize < 8 || offset + cmdsize > cmds_end {
+                anyhow::bail!("Invalid Mach-O load command at {offset:#x}");
+            }
+            match cmd {
+                macho::LC_SEGMENT_64 => {
+                    let name = &self.data[offset + offset_of!(Segment, segname)..][..16];
+                    let vmaddr = read_u64(&self.data, offset + offset_of!(Segment, vmaddr))?;
+                    let vmsize = read_u64(&self.data, offset + offset_of!(Segment, vmsize))?;
+                    if name.split(|&b| b == 0).next() == Some(b"__LINKEDIT") {
+                        let fileoff = read_u64(&self.data, offset + offset_of!(Segment, fileoff))?;
+                        linkedit = Some((offset, vmaddr, fileoff));
+                    } else {
+                        segments_end = segments_end.max(vmaddr + vmsize);
+                        if linkedit.is_none() {
+                            linkedit_index += 1;
+                        }
+                    }
+                    let nsects = read_u32(&self.data, offset + offset_of!(Segment, nsects))?;
+                    for i in 0..nsects as usize {
+                        let section = offset + size_of::<Segment>() + i * size_of::<Section>();
+                        let section_offset =
+                            read_u32(&self.data, section + offset_of!(Section, offset))?;
+                        // Zero-fill sections have no file offset
+                        if section_offset != 0 {
+                            first_section_offset =
+                                first_section_offset.min(section_offset as usize);
+                        }
+                    }
+                }
+                macho::LC_CODE_SIGNATURE => signed = true,
+                _ => {}
+            }
+            offset += cmdsize;
+        }
+
+        let (linkedit_cmd, linkedit_vmaddr, linkedit_fileoff) =
+            linkedit.context("Mach-O binary has no __LINKEDIT segment")?;
+        if segments_end > linkedit_vmaddr {
+            anyhow::bail!("__LINKEDIT is not the last segment in memory of this Mach-O binary");
+        }
+        let cmd_size = size_of::<Segment>() + size_of::<Section>();
+        if cmds_end + cmd_size > first_section_offset
+            || self.data[cmds_end..cmds_end + cmd_size]
+                .iter()
+                .any(|&b| b != 0)
+        {
+            anyhow::bail!(
+                "No room for another load command in the Mach-O header, relink the dylib with a larger -headerpad"
+            );
+        }
+
+        // Shift everything in __LINKEDIT by the new segment's size
+        let shift = (data.len() as u64 + page_size - 1) & !(page_size - 1);
+        let linkedit_fileoff_u32 = u32::try_from(linkedit_fileoff)?;
+        let mut offset = header_size;
+        for _ in 0..ncmds {
+            let cmd = read_u32(&self.data, offset)?;
+            let cmdsize = read_u32(&self.data, offset + 4)? as usize;
+            for field in linkedit_offset_fields(cmd) {
+                let value = read_u32(&self.data, offset + field)?;
+                if value >= linkedit_fileoff_u32 {
+                    let value = u32::try_from(value as u64 + shift)?;
+                    self.data[offset + field..][..4].copy_from_slice(&value.to_le_bytes());
+                }
+            }
+            offset += cmdsize;
+        }
+        for (field, value) in [
+            (offset_of!(Segment, vmaddr), linkedit_vmaddr + shift),
+            (offset_of!(Segment, fileoff), linkedit_fileoff + shift),
+        ] {
+            self.data[linkedit_cmd + field..][..8].copy_from_slice(&value.to_le_bytes());
+        }
+
+        let mut command = vec![0u8; cmd_size];
+        let name = |name: &[u8]| {
+            let mut padded = [0u8; 16];
+            padded[..name.len()].copy_from_slice(name);
+            padded
+        };
+        let mut put = |offset: usize, bytes: &[u8]| {
+            command[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        put(
+            offset_of!(Segment, cmd),
+            &macho::LC_SEGMENT_64.to_le_bytes(),
+        );
+        put(
+            offset_of!(Segment, cmdsize),
+            &(cmd_size as u32).to_le_bytes(),
+        );
+        put(offset_of!(Segment, segname), &name(MACHO_SEGMENT_NAME));
+        put(offset_of!(Segment, vmaddr), &linkedit_vmaddr.to_le_bytes());
+        put(offset_of!(Segment, vmsize), &shift.to_le_bytes());
+        put(
+            offset_of!(Segment, fileoff),
+            &linkedit_fileoff.to_le_bytes(),
+        );
+        put(offset_of!(Segment, filesize), &shift.to_le_bytes());
+        put(
+            offset_of!(Segment, maxprot),
+            &macho::VM_PROT_READ.to_le_bytes(),
+        );
+        put(
+            offset_of!(Segment, initprot),
+            &macho::VM_PROT_READ.to_le_bytes(),
+        );
+        put(offset_of!(Segment, nsects), &1u32.to_le_bytes());
+        let section = size_of::<Segment>();
+        put(
+            section + offset_of!(Section, sectname),
+            &name(MACHO_SECTION_NAME),
+        );
+        put(
+            section + offset_of!(Section, segname),
+            &name(MACHO_SEGMENT_NAME),
+        );
+        put(
+            section + offset_of!(Section, addr),
+            &linkedit_vmaddr.to_le_bytes(),
+        );
+        put(
+            section + offset_of!(Section, size),
+            &(data.len() as u64).to_le_bytes(),
+        );
+        put(
+            section + offset_of!(Section, offset),
+            &linkedit_fileoff_u32.to_le_bytes(),
+        );
+
+        // The new command takes __LINKEDIT's place, using up header padding
+        self.data
+            .copy_within(linkedit_cmd..cmds_end, linkedit_cmd + cmd_size);
+        self.data[linkedit_cmd..linkedit_cmd + cmd_size].copy_from_slice(&command);
+        for (field, value) in [
+            (offset_of!(Header, ncmds), ncmds + 1),
+            (
+                offset_of!(Header, sizeofcmds),
+                (sizeofcmds + cmd_size) as u32,
+            ),
+        ] {
+            self.data[field..field + 4].copy_from_slice(&value.to_le_bytes());
+        }
+
+        let mut segment_data = data.to_vec();
+        segment_data.resize(shift as usize, 0);
+        let linkedit_fileoff = linkedit_fileoff as usize;
+        self.data
+            .splice(linkedit_fileoff..linkedit_fileoff, segment_data);
+        self.add_chained_fixups_segment(linkedit_index, page_size)?;
+
+        if signed {
+            warn!(
+                "The dylib's code signature is invalid now, re-sign it before loading it (e.g. codesign --force --sign - <dylib>)"
+            );
+        }
+        Ok(linkedit_vmaddr)
+    }
+
+    /// Adds an entry without fixups for the new segment at `segment_index` to the
+    /// `LC_DYLD_CHAINED_FIXUPS` segment starts, if the binary has chained fixups.
+    /// The grown fixups don't fit in place, so they're moved to the end of
+    /// `__LINKEDIT`, before the code signature if any.
+    fn add_chained_fixups_segment(&mut self, segment_index: usize, page_size: u64) -> Result<()> {
+        use std::mem::{offset_of, size_of};
+        type Header = macho::MachHeader64<LE>;
+        type Segment = macho::SegmentCommand64<LE>;
+        type LinkeditData = macho::LinkeditDataCommand<LE>;
+
+        let ncmds = read_u32(&self.data, offset_of!(Header, ncmds))?;
+        let mut fixups_cmd = None;
+        let mut linkedit_cmd = None;
+        let mut code_signature_cmd = None;
+        let mut offset = size_of::<Header>();
+        for _ in 0..ncmds {
+            match read_u32(&self.data, offset)? {
+                macho::LC_DYLD_CHAINED_FIXUPS => fixups_cmd = Some(offset),
+                macho::LC_CODE_SIGNATURE => code_signature_cmd = Some(offset),
+                macho::LC_SEGMENT_64
+                    if self.data[offset + offset_of!(Segment, segname)..][..16]
+                        .split(|&b| b == 0)
+                        .next()
+                        == Some(b"__LINKEDIT") =>
+                {
+                    linkedit_cmd = Some(offset)
+                }
+                _ => {}
+            }
+            offset += read_u32(&self.data, offset + 4)? as usize;
+        }
+        let Some(fixups_cmd) = fixups_cmd else {
+            return Ok(());
+        };
+        let linkedit_cmd = linkedit_cmd.context("Mach-O binary has no __LINKEDIT segment")?;
+
+        let dataoff = read_u32(&self.data, fixups_cmd + offset_of!(LinkeditData, dataoff))?;
+        let datasize = read_u32(&self.data, fixups_cmd + offset_of!(LinkeditData, datasize))?;
+        let fixups = self
+            .data
+            .get(dataoff as usize..(dataoff + datasize) as usize)
+            .context("Truncated Mach-O chained fixups")?;
+        let starts_offset = read_u32(fixups, CHAINED_FIXUPS_STARTS_OFFSET)? as usize;
+        let seg_count = read_u32(fixups, starts_offset)? as usize;
+        if segment_index > seg_count {
+            anyhow::bail!(
+                "Mach-O chained fixups cover {seg_count} segments, expected at least {segment_index}"
+            );
+        }
+
+        // The new `seg_info_offset[]` entry, plus padding that keeps the
+        // segment starts after it 8-byte aligned
+        const GROWTH: u32 = 8;
+        let array_end = starts_offset + 4 + 4 * seg_count;
+        let mut grown = fixups[..array_end].to_vec();
+        grown.extend([0; GROWTH as usize]);
+        grown.extend(&fixups[array_end..]);
+        for field in [CHAINED_FIXUPS_IMPORTS_OFFSET, CHAINED_FIXUPS_SYMBOLS_OFFSET] {
+            let value = read_u32(fixups, field)?;
+            if value as usize >= array_end {
+                grown[field..field + 4].copy_from_slice(&(value + GROWTH).to_le_bytes());
+            }
+        }
+        let mut seg_info_offsets = (0..seg_count)
+            .map(|i| {
+                // Offsets are relative to the starts, 0 means no fixups
+                let seg_info_offset = read_u32(fixups, starts_offset + 4 + 4 * i)?;
+                Ok(if seg_info_offset == 0 {
+                    0
+                } else {
+                    seg_info_offset + GROWTH
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        seg_info_offsets.insert(segment_index, 0);
+        grown[starts_offset..starts_offset + 4]
+            .copy_from_slice(&(seg_count as u32 + 1).to_le_bytes());
+        for (i, seg_info_offset) in seg_info_offsets.iter().enumerate() {
+            let entry = starts_offset + 4 + 4 * i;
+            grown[entry..entry + 4].copy_from_slice(&seg_info_offset.to_le_bytes());
+        }
+        let grown_size = grown.len() as u32;
+        // The code signature must stay 16-byte aligned
+        grown.resize(grown.len().next_multiple_of(16), 0);
+        let added = grown.len() as u64;
+
+        let linkedit_fileoff = read_u64(&self.data, linkedit_cmd + offset_of!(Segment, fileoff))?;
+        let linkedit_filesize = read_u64(&self.data, linkedit_cmd + offset_of!(Segment, filesize))?;
+        let insert_at = match code_signature_cmd {
+            Some(cmd) => read_u32(&self.data, cmd + offset_of!(LinkeditData, dataoff))?,
+            None => u32::try_from(linkedit_fileoff + linkedit_filesize)?,
+        };
+        if insert_at as usize > self.data.len() {
+            anyhow::bail!("Mach-O __LINKEDIT extends past the end of the file");
+        }
+
+        let mut offset = size_of::<Header>();
+        for _ in 0..ncmds {
+            let cmd = read_u32(&self.data, offset)?;
+            for field in linkedit_offset_fields(cmd) {
+                let value = read_u32(&self.data, offset + field)?;
+                if value >= insert_at && value != 0 {
+                    let value = u32::try_from(value as u64 + added)?;
+                    self.data[offset + field..][..4].copy_from_slice(&value.to_le_bytes());
+                }
+            }
+            offset += read_u32(&self.data, offset + 4)? as usize;
+        }
+        for (field, value) in [
+            (offset_of!(LinkeditData, dataoff), insert_at),
+            (offset_of!(LinkeditData, datasize), grown_size),
+        ] {
+            self.data[fixups_cmd + field..][..4].copy_from_slice(&value.to_le_bytes());
+        }
+        let linkedit_filesize = linkedit_filesize + added;
+        let linkedit_vmsize = read_u64(&self.data, linkedit_cmd + offset_of!(Segment, vmsize))?
+            .max((linkedit_filesize + page_size - 1) & !(page_size - 1));
+        for (field, value) in [
+            (offset_of!(Segment, filesize), linkedit_filesize),
+            (offset_of!(Segment, vmsize), linkedit_vmsize),
+        ] {
+            self.data[linkedit_cmd + field..][..8].copy_from_slice(&value.to_le_bytes());
+        }
+
+        let insert_at = insert_at as usize;
+        self.data.splice(insert_at..insert_at, grown);
+        Ok(())
+    }
+
     fn compress_xz(&self, data: &[u8]) -> Result<Vec<u8>> {
         use std::io::Write;
         use xz2::write::XzEncoder;
@@ -562,7 +2009,653 @@ impl BinaryProcessor {
         Ok(encoder.finish()?)
     }
 
+    /// Like `compress_xz`, but the bare LZMA2 stream without the xz container's
+    /// headers, index and check.
+    fn compress_lzma(&self, data: &[u8]) -> Result<Vec<u8>> {
+        lzma2_raw(data, true)
+    }
+
+    fn compress_zstd(&self, data: &[u8], dict: Option<&[u8]>) -> Result<Vec<u8>> {
+        let mut compressor = match dict {
+            Some(dict) => {
+                let dict_id = zstd_dict_id(dict)?;
+                info!("→ Compressing with zstd dictionary id {dict_id}");
+                zstd::bulk::Compressor::with_dictionary(19, dict)?
+            }
+            None => zstd::bulk::Compressor::new(19)?,
+        };
+        Ok(compressor.compress(data)?)
+    }
+
     pub fn into_data(self) -> Vec<u8> {
         self.data
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object::ObjectSection;
+
+    const PLACEHOLDER_SO: &[u8] = include_bytes!("../tests/fixtures/libplaceholder.so");
+
+    /// The placeholder fixture, declaring loader `version`.
+    fn placeholder_so(version: i32) -> Vec<u8> {
+        let mut data = PLACEHOLDER_SO.to_vec();
+        let magic = [0x0d000721i32.to_le_bytes(), 0x1f8a4e2bi32.to_le_bytes()].concat();
+        let offset = memchr::memmem::find(&data, &magic).unwrap();
+        data[offset + 8..offset + 12].copy_from_slice(&version.to_le_bytes());
+        data
+    }
+
+    /// A minimal PE DLL with a version 1 config placeholder in `.data` and, with
+    /// `import`, an `.idata` section importing `GetTickCount` from that DLL.
+    fn pe_fixture(is_64: bool, import: Option<&str>) -> Vec<u8> {
+        let mut data = EmbeddedConfig::new(1, 0, 0, 0).as_bytes();
+        data.resize(64, 0);
+
+        let mut out = Vec::new();
+        let mut writer = object::write::pe::Writer::new(is_64, 0x1000, 0x200, &mut out);
+        writer.reserve_dos_header_and_stub();
+        writer.reserve_nt_headers(pe::IMAGE_NUMBEROF_DIRECTORY_ENTRIES);
+        writer.reserve_section_headers(1 + import.is_some() as u16);
+        let data_range = writer.reserve_data_section(data.len() as u32, data.len() as u32);
+
+        // Descriptor and terminator, lookup table, import address table, hint/name, DLL name
+        let thunk_size = if is_64 { 8 } else { 4 };
+        let lookup_offset = 40;
+        let address_offset = lookup_offset + 2 * thunk_size;
+        let hint_offset = address_offset + 2 * thunk_size;
+        let name_offset = hint_offset + 2 + b"GetTickCount\0".len();
+        let idata = import.map(|dll_name| {
+            let size = (name_offset + dll_name.len() + 1) as u32;
+            let range = writer.reserve_section(
+                *b".idata\0\0",
+                pe::IMAGE_SCN_CNT_INITIALIZED_DATA
+                    | pe::IMAGE_SCN_MEM_READ
+                    | pe::IMAGE_SCN_MEM_WRITE,
+                size,
+                size,
+            );
+            let rva = range.virtual_address;
+            let mut idata = vec![0u8; size as usize];
+            for (offset, value) in [
+                (0, rva + lookup_offset as u32),
+                (12, rva + name_offset as u32),
+                (16, rva + address_offset as u32),
+            ] {
+                idata[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            }
+            for thunks in [lookup_offset, address_offset] {
+                idata[thunks..thunks + 4]
+                    .copy_from_slice(&(rva + hint_offset as u32).to_le_bytes());
+            }
+            idata[hint_offset + 2..name_offset - 1].copy_from_slice(b"GetTickCount");
+            idata[name_offset..name_offset + dll_name.len()].copy_from_slice(dll_name.as_bytes());
+            writer.set_data_directory(pe::IMAGE_DIRECTORY_ENTRY_IMPORT, rva, 40);
+            (range.file_offset, idata)
+        });
+
+        writer.write_dos_header_and_stub().unwrap();
+        writer.write_nt_headers(object::write::pe::NtHeaders {
+            machine: if is_64 {
+                pe::IMAGE_FILE_MACHINE_AMD64
+            } else {
+                pe::IMAGE_FILE_MACHINE_I386
+            },
+            time_date_stamp: 0,
+            characteristics: pe::IMAGE_FILE_EXECUTABLE_IMAGE | pe::IMAGE_FILE_DLL,
+            major_linker_version: 14,
+            minor_linker_version: 0,
+            address_of_entry_point: 0,
+            image_base: 0x1000_0000,
+            major_operating_system_version: 6,
+            minor_operating_system_version: 0,
+            major_image_version: 0,
+            minor_image_version: 0,
+            major_subsystem_version: 6,
+            minor_subsystem_version: 0,
+            subsystem: pe::IMAGE_SUBSYSTEM_WINDOWS_GUI,
+            dll_characteristics: 0,
+            size_of_stack_reserve: 0x10_0000,
+            size_of_stack_commit: 0x1000,
+            size_of_heap_reserve: 0x10_0000,
+            size_of_heap_commit: 0x1000,
+        });
+        writer.write_section_headers();
+        writer.write_section(data_range.file_offset, &data);
+        if let Some((offset, idata)) = &idata {
+            writer.write_section(*offset, idata);
+        }
+        out
+    }
+
+    /// Checks that `data` imports `GetTickCount` from `KERNEL32.dll` and, through
+    /// the added descriptor, ordinal [`PE_IMPORT_ORDINAL`] from `payload.dll`.
+    fn check_pe_imports<Pe: ImageNtHeaders>(data: &[u8]) {
+        let file = object::read::pe::PeFile::<Pe>::parse(data).unwrap();
+        let sections = file.section_table();
+        let import_table = file
+            .data_directories()
+            .import_table(data, &sections)
+            .unwrap()
+            .unwrap();
+
+        let mut descriptors = import_table.descriptors().unwrap();
+        let kernel32 = descriptors.next().unwrap().unwrap();
+        let payload = descriptors.next().unwrap().unwrap();
+        assert!(descriptors.next().unwrap().is_none());
+
+        // The existing descriptor still points at the thunks and names in `.idata`
+        let name = sections.pe_data_at(data, kernel32.name.get(LE)).unwrap();
+        assert!(name.starts_with(b"KERNEL32.dll\0"));
+        let thunk = sections
+            .pe_data_at(data, kernel32.original_first_thunk.get(LE))
+            .unwrap();
+        let hint_name = u32::from_le_bytes(thunk[..4].try_into().unwrap());
+        let hint_name = sections.pe_data_at(data, hint_name).unwrap();
+        assert!(hint_name[2..].starts_with(b"GetTickCount\0"));
+
+        // Ours is entirely in the new section, which `object` reads imports from
+        assert_eq!(
+            import_table.name(payload.name.get(LE)).unwrap(),
+            b"payload.dll"
+        );
+        for thunks in [payload.original_first_thunk, payload.first_thunk] {
+            let mut thunks = import_table.thunks(thunks.get(LE)).unwrap();
+            let thunk = thunks.next::<Pe>().unwrap().unwrap();
+            assert!(matches!(
+                import_table.import::<Pe>(thunk).unwrap(),
+                object::read::pe::Import::Ordinal(PE_IMPORT_ORDINAL)
+            ));
+            assert!(thunks.next::<Pe>().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn pe_gets_an_import_descriptor() {
+        let dir = tempfile::tempdir().unwrap();
+        for is_64 in [false, true] {
+            let path = dir.path().join("target.dll");
+            std::fs::write(&path, pe_fixture(is_64, Some("KERNEL32.dll"))).unwrap();
+
+            add_needed_library_to_file(&path, "payload.dll").unwrap();
+            let data = std::fs::read(&path).unwrap();
+            assert_eq!(
+                needed_libraries(&data).unwrap(),
+                ["KERNEL32.dll", "payload.dll"]
+            );
+            if is_64 {
+                check_pe_imports::<pe::ImageNtHeaders64>(&data);
+            } else {
+                check_pe_imports::<pe::ImageNtHeaders32>(&data);
+            }
+
+            // Already imported, case-insensitively
+            add_needed_library_to_file(&path, "PAYLOAD.DLL").unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn pe_section_name_must_fit_the_header() {
+        assert_eq!(&pe_section_name(".rsrc2").unwrap(), b".rsrc2\0\0");
+        assert_eq!(&pe_section_name(".abcdefg").unwrap(), b".abcdefg");
+        assert!(pe_section_name("").is_err());
+        assert!(pe_section_name(".toolong1").is_err());
+        assert!(pe_section_name(".dätä").is_err());
+    }
+
+    #[test]
+    fn pe_gets_the_configured_section_name() {
+        for is_64 in [false, true] {
+            let mut processor = BinaryProcessor::new(pe_fixture(is_64, None)).unwrap();
+            processor.set_pe_section_name(pe_section_name(".rsrc2").unwrap());
+            processor
+                .add_embedded_config_data(b"{}", &Compression::None)
+                .unwrap();
+            let data = processor.into_data();
+
+            let file = object::read::File::parse(data.as_slice()).unwrap();
+            let names: Vec<_> = file
+                .sections()
+                .map(|section| section.name().unwrap().to_string())
+                .collect();
+            assert!(names.iter().any(|name| name == ".rsrc2"), "{names:?}");
+            let (_, embedded) = read_embedded_data(&data).unwrap();
+            assert_eq!(embedded, b"{}");
+        }
+    }
+
+    #[test]
+    fn pe_section_name_is_random_by_default() {
+        let mut processor = BinaryProcessor::new(pe_fixture(true, None)).unwrap();
+        processor
+            .add_embedded_config_data(b"{}", &Compression::None)
+            .unwrap();
+        let data = processor.into_data();
+
+        let file = object::read::File::parse(data.as_slice()).unwrap();
+        let names: Vec<_> = file
+            .sections()
+            .map(|section| section.name().unwrap().to_string())
+            .collect();
+        assert_eq!(names.len(), 2, "{names:?}");
+        assert!(!names.iter().any(|name| name == ".fripac"), "{names:?}");
+    }
+
+    #[test]
+    fn finds_a_version_2_placeholder() {
+        let processor = BinaryProcessor::new(placeholder_so(2)).unwrap();
+        let (offset, version) = processor.find_embedded_config().unwrap();
+        assert_eq!(version, 2);
+        assert_eq!(
+            &PLACEHOLDER_SO[offset..offset + 4],
+            &0x0d000721i32.to_le_bytes()
+        );
+
+        let unsupported = EMBEDDED_CONFIG_VERSIONS.end() + 1;
+        let processor = BinaryProcessor::new(placeholder_so(unsupported)).unwrap();
+        assert!(processor.find_embedded_config().is_none());
+        assert!(processor
+            .diagnose_embedded_config()
+            .contains("supported versions"));
+    }
+
+    #[test]
+    fn emits_the_configured_version() {
+        let mut processor = BinaryProcessor::new(placeholder_so(2)).unwrap();
+        processor.set_embedded_version(2).unwrap();
+        processor
+            .add_embedded_config_data(b"{}", &Compression::None)
+            .unwrap();
+
+        let (config, embedded) = read_embedded_data(&processor.into_data()).unwrap();
+        let version = config.version;
+        assert_eq!(version, 2);
+        assert_eq!(embedded, b"{}");
+    }
+
+    #[test]
+    fn rejects_a_prebuilt_without_a_placeholder() {
+        let vanilla = include_bytes!("../tests/fixtures/libvanilla.so");
+        let mut processor = BinaryProcessor::new(vanilla.to_vec()).unwrap();
+        assert!(processor.find_embedded_config().is_none());
+
+        let err = processor
+            .add_embedded_config_data(b"{}", &Compression::None)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("prebuilt does not contain a fripack config placeholder"),
+            "{err}"
+        );
+        // Nothing was appended before the check
+        assert_eq!(processor.into_data(), vanilla);
+    }
+
+    /// A minimal arm64 dylib with a version 1 config placeholder in `__TEXT`, a
+    /// string table in `__LINKEDIT` and, with `chained_fixups`, fixups with
+    /// segment starts for `__TEXT` and symbol names.
+    fn macho_fixture(chained_fixups: bool) -> Vec<u8> {
+        use std::mem::{offset_of, size_of};
+        type Header = macho::MachHeader64<LE>;
+        type Segment = macho::SegmentCommand64<LE>;
+        type Section = macho::Section64<LE>;
+        type LinkeditData = macho::LinkeditDataCommand<LE>;
+        type Symtab = macho::SymtabCommand<LE>;
+
+        fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        fn segment_name(name: &[u8]) -> [u8; 16] {
+            let mut padded = [0u8; 16];
+            padded[..name.len()].copy_from_slice(name);
+            padded
+        }
+
+        let mut fixups = vec![0u8; 80];
+        put(
+            &mut fixups,
+            CHAINED_FIXUPS_STARTS_OFFSET,
+            &32u32.to_le_bytes(),
+        );
+        put(
+            &mut fixups,
+            CHAINED_FIXUPS_IMPORTS_OFFSET,
+            &72u32.to_le_bytes(),
+        );
+        put(
+            &mut fixups,
+            CHAINED_FIXUPS_SYMBOLS_OFFSET,
+            &72u32.to_le_bytes(),
+        );
+        put(&mut fixups, 20, &1u32.to_le_bytes()); // DYLD_CHAINED_IMPORT
+                                                   // Starts in image: 2 segments, __TEXT's starts 16 bytes after them
+        put(&mut fixups, 32, &2u32.to_le_bytes());
+        put(&mut fixups, 36, &16u32.to_le_bytes());
+        // Starts in __TEXT: size, page size, DYLD_CHAINED_PTR_64_OFFSET, no fixups
+        put(&mut fixups, 48, &24u32.to_le_bytes());
+        put(&mut fixups, 52, &0x4000u16.to_le_bytes());
+        put(&mut fixups, 54, &6u16.to_le_bytes());
+        put(&mut fixups, 68, &1u16.to_le_bytes());
+        put(&mut fixups, 70, &0xffffu16.to_le_bytes());
+        put(&mut fixups, 72, b"\0_dummy\0");
+        let strtab = b"\0_strtab_marker\0";
+
+        let linkedit_fileoff = 0x4000;
+        let fixups_size = if chained_fixups { fixups.len() } else { 0 };
+        let linkedit_size = fixups_size + strtab.len();
+        let mut data = vec![0u8; linkedit_fileoff + linkedit_size];
+
+        let mut commands = Vec::new();
+        let text_size = size_of::<Segment>() + size_of::<Section>();
+        let mut text = vec![0u8; text_size];
+        put(
+            &mut text,
+            offset_of!(Segment, cmd),
+            &macho::LC_SEGMENT_64.to_le_bytes(),
+        );
+        put(
+            &mut text,
+            offset_of!(Segment, cmdsize),
+            &(text_size as u32).to_le_bytes(),
+        );
+        put(
+            &mut text,
+            offset_of!(Segment, segname),
+            &segment_name(b"__TEXT"),
+        );
+        put(
+            &mut text,
+            offset_of!(Segment, vmsize),
+            &0x4000u64.to_le_bytes(),
+        );
+        put(
+            &mut text,
+            offset_of!(Segment, filesize),
+            &0x4000u64.to_le_bytes(),
+        );
+        put(&mut text, offset_of!(Segment, maxprot), &5u32.to_le_bytes());
+        put(
+            &mut text,
+            offset_of!(Segment, initprot),
+            &5u32.to_le_bytes(),
+        );
+        put(&mut text, offset_of!(Segment, nsects), &1u32.to_le_bytes());
+        let section = size_of::<Segment>();
+        put(
+            &mut text,
+            section + offset_of!(Section, sectname),
+            &segment_name(b"__const"),
+        );
+        put(
+            &mut text,
+            section + offset_of!(Section, segname),
+            &segment_name(b"__TEXT"),
+        );
+        put(
+            &mut text,
+            section + offset_of!(Section, addr),
+            &0x1000u64.to_le_bytes(),
+        );
+        put(
+            &mut text,
+            section + offset_of!(Section, size),
+            &64u64.to_le_bytes(),
+        );
+        put(
+            &mut text,
+            section + offset_of!(Section, offset),
+            &0x1000u32.to_le_bytes(),
+        );
+        commands.push(text);
+
+        let mut linkedit = vec![0u8; size_of::<Segment>()];
+        put(
+            &mut linkedit,
+            offset_of!(Segment, cmd),
+            &macho::LC_SEGMENT_64.to_le_bytes(),
+        );
+        put(
+            &mut linkedit,
+            offset_of!(Segment, cmdsize),
+            &(size_of::<Segment>() as u32).to_le_bytes(),
+        );
+        put(
+            &mut linkedit,
+            offset_of!(Segment, segname),
+            &segment_name(b"__LINKEDIT"),
+        );
+        put(
+            &mut linkedit,
+            offset_of!(Segment, vmaddr),
+            &0x4000u64.to_le_bytes(),
+        );
+        put(
+            &mut linkedit,
+            offset_of!(Segment, vmsize),
+            &0x4000u64.to_le_bytes(),
+        );
+        put(
+            &mut linkedit,
+            offset_of!(Segment, fileoff),
+            &(linkedit_fileoff as u64).to_le_bytes(),
+        );
+        put(
+            &mut linkedit,
+            offset_of!(Segment, filesize),
+            &(linkedit_size as u64).to_le_bytes(),
+        );
+        put(
+            &mut linkedit,
+            offset_of!(Segment, maxprot),
+            &1u32.to_le_bytes(),
+        );
+        put(
+            &mut linkedit,
+            offset_of!(Segment, initprot),
+            &1u32.to_le_bytes(),
+        );
+        commands.push(linkedit);
+
+        if chained_fixups {
+            let mut command = vec![0u8; size_of::<LinkeditData>()];
+            put(
+                &mut command,
+                offset_of!(LinkeditData, cmd),
+                &macho::LC_DYLD_CHAINED_FIXUPS.to_le_bytes(),
+            );
+            put(
+                &mut command,
+                offset_of!(LinkeditData, cmdsize),
+                &(size_of::<LinkeditData>() as u32).to_le_bytes(),
+            );
+            put(
+                &mut command,
+                offset_of!(LinkeditData, dataoff),
+                &(linkedit_fileoff as u32).to_le_bytes(),
+            );
+            put(
+                &mut command,
+                offset_of!(LinkeditData, datasize),
+                &(fixups.len() as u32).to_le_bytes(),
+            );
+            commands.push(command);
+            put(&mut data, linkedit_fileoff, &fixups);
+        }
+
+        let stroff = (linkedit_fileoff + fixups_size) as u32;
+        let mut symtab = vec![0u8; size_of::<Symtab>()];
+        put(
+            &mut symtab,
+            offset_of!(Symtab, cmd),
+            &macho::LC_SYMTAB.to_le_bytes(),
+        );
+        put(
+            &mut symtab,
+            offset_of!(Symtab, cmdsize),
+            &(size_of::<Symtab>() as u32).to_le_bytes(),
+        );
+        put(
+            &mut symtab,
+            offset_of!(Symtab, symoff),
+            &stroff.to_le_bytes(),
+        );
+        put(
+            &mut symtab,
+            offset_of!(Symtab, stroff),
+            &stroff.to_le_bytes(),
+        );
+        put(
+            &mut symtab,
+            offset_of!(Symtab, strsize),
+            &(strtab.len() as u32).to_le_bytes(),
+        );
+        commands.push(symtab);
+        put(&mut data, stroff as usize, strtab);
+
+        put(
+            &mut data,
+            offset_of!(Header, magic),
+            &macho::MH_MAGIC_64.to_le_bytes(),
+        );
+        put(
+            &mut data,
+            offset_of!(Header, cputype),
+            &macho::CPU_TYPE_ARM64.to_le_bytes(),
+        );
+        put(
+            &mut data,
+            offset_of!(Header, filetype),
+            &macho::MH_DYLIB.to_le_bytes(),
+        );
+        put(
+            &mut data,
+            offset_of!(Header, ncmds),
+            &(commands.len() as u32).to_le_bytes(),
+        );
+        let sizeofcmds: usize = commands.iter().map(Vec::len).sum();
+        put(
+            &mut data,
+            offset_of!(Header, sizeofcmds),
+            &(sizeofcmds as u32).to_le_bytes(),
+        );
+        put(&mut data, size_of::<Header>(), &commands.concat());
+        put(
+            &mut data,
+            0x1000,
+            &EmbeddedConfig::new(1, 0, 0, 0).as_bytes(),
+        );
+        data
+    }
+
+    /// `(dataoff, datasize)` of the `__LINKEDIT` data of the load command `cmd`.
+    fn linkedit_data(data: &[u8], cmd: u32) -> Option<(usize, usize)> {
+        let file = object::read::macho::MachOFile64::<LE>::parse(data).unwrap();
+        let mut commands = file.macho_load_commands().unwrap();
+        while let Some(command) = commands.next().unwrap() {
+            if command.cmd() == cmd {
+                let command: &macho::LinkeditDataCommand<LE> = command.data().unwrap();
+                return Some((
+                    command.dataoff.get(LE) as usize,
+                    command.datasize.get(LE) as usize,
+                ));
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn macho_gets_a_segment_covered_by_the_chained_fixups() {
+        for chained_fixups in [false, true] {
+            let script = b"console.log('macho');";
+            let mut processor = BinaryProcessor::new(macho_fixture(chained_fixups)).unwrap();
+            processor
+                .add_embedded_config_data(script, &Compression::None)
+                .unwrap();
+            let data = processor.into_data();
+
+            let file = object::read::File::parse(data.as_slice()).unwrap();
+            let segments: Vec<_> = file
+                .segments()
+                .map(|segment| segment.name().unwrap().unwrap().to_string())
+                .collect();
+            assert_eq!(segments, ["__TEXT", "__FRIPACK", "__LINKEDIT"]);
+            let (_, embedded) = read_embedded_data(&data).unwrap();
+            assert_eq!(embedded, script);
+
+            // The string table moved along with __LINKEDIT
+            let file = object::read::macho::MachOFile64::<LE>::parse(data.as_slice()).unwrap();
+            let symtab = file.macho_symbol_table();
+            assert_eq!(symtab.strings().get(1).unwrap(), b"_strtab_marker");
+
+            let Some((dataoff, datasize)) = linkedit_data(&data, macho::LC_DYLD_CHAINED_FIXUPS)
+            else {
+                assert!(!chained_fixups);
+                continue;
+            };
+            let fixups = &data[dataoff..dataoff + datasize];
+            let starts = read_u32(fixups, CHAINED_FIXUPS_STARTS_OFFSET).unwrap() as usize;
+            let seg_info_offsets: Vec<_> = (0..read_u32(fixups, starts).unwrap() as usize)
+                .map(|i| read_u32(fixups, starts + 4 + 4 * i).unwrap() as usize)
+                .collect();
+            assert_eq!(seg_info_offsets.len(), segments.len());
+            assert_eq!(seg_info_offsets[1..], [0, 0]);
+            // __TEXT's starts are still found: their size and page size
+            let text_starts = starts + seg_info_offsets[0];
+            assert_eq!(read_u32(fixups, text_starts).unwrap(), 24);
+            assert_eq!(
+                fixups[text_starts + 4..text_starts + 6],
+                0x4000u16.to_le_bytes()
+            );
+            let symbols = read_u32(fixups, CHAINED_FIXUPS_SYMBOLS_OFFSET).unwrap() as usize;
+            assert_eq!(&fixups[symbols..symbols + 8], b"\0_dummy\0");
+            // Moved to the end of __LINKEDIT, which now covers it
+            let linkedit = file.segments().last().unwrap();
+            let (fileoff, filesize) = linkedit.file_range();
+            assert!(dataoff as u64 >= fileoff && (dataoff + datasize) as u64 <= fileoff + filesize);
+        }
+    }
+
+    #[test]
+    fn base64_encoded_data_round_trips() {
+        let script = b"console.log('\x00\xff binary-safe');";
+        let mut processor = BinaryProcessor::new(placeholder_so(3)).unwrap();
+        processor.set_embedded_version(3).unwrap();
+        processor.set_base64_encoding(true);
+        processor
+            .add_embedded_config_data(script, &Compression::Xz)
+            .unwrap();
+
+        let (config, embedded) = read_embedded_data(&processor.into_data()).unwrap();
+        let compression = config.compression;
+        assert_eq!(compression, Compression::Xz.id() | BASE64_ENCODING_FLAG);
+        assert!(embedded.iter().all(u8::is_ascii_graphic));
+        let decoded = decompress_embedded_data(compression, &embedded, None).unwrap();
+        assert_eq!(decoded, script);
+    }
+
+    #[test]
+    fn raw_lzma2_is_smaller_than_the_xz_container() {
+        let processor = BinaryProcessor::new(PLACEHOLDER_SO.to_vec()).unwrap();
+        let script = b"Java.perform(() => { console.log('hello from fripack'); });";
+
+        let xz = processor.compress_xz(script).unwrap();
+        let lzma2 = processor.compress_lzma(script).unwrap();
+        assert!(
+            lzma2.len() + 50 < xz.len(),
+            "{} vs {}",
+            lzma2.len(),
+            xz.len()
+        );
+        // No `.xz` or `.lzma` header in front of the LZMA2 chunks
+        assert!(!lzma2.starts_with(&[0xfd, b'7', b'z', b'X', b'Z']));
+        assert_ne!(lzma2[0], 0x5d);
+
+        for (compression, data) in [(Compression::Xz, xz), (Compression::Lzma, lzma2)] {
+            let decoded = decompress_embedded_data(compression.id(), &data, None).unwrap();
+            assert_eq!(decoded, script);
+        }
+    }
+}
@@ -1,30 +1,99 @@
-use crate::binary::{add_needed_library_to_file, BinaryProcessor};
-use crate::config::{Platform, ResolvedConfig, ResolvedTarget, TargetConfig};
-use crate::downloader::Downloader;
+use crate::adb::{self, adb_output_with_retry};
+use crate::binary::{
+    add_needed_library_to_file, needed_libraries, pe_section_name, zstd_dict_id, BinaryProcessor,
+    Compression, ProxyExports,
+};
+use crate::config::{
+    parse_size, CompressionKind, EmbedEncoding, InjectApkConfig, InjectMode, Platform,
+    PlatformConfig, PreferSplit, ResolvedConfig, ResolvedTarget, Runtime, SignConfig, TargetConfig,
+    XposedConfig, XzFormat,
+};
+use crate::dex;
+use crate::doctor::probe_tool_version;
+use crate::downloader::{Downloader, APK_CACHE_DIR};
+use crate::explain::Explain;
+use crate::format_bytes;
+use crate::smali;
 use anyhow::{Context, Result};
 use log::{info, warn};
 use rand::Rng;
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use tokio::{fs, process::Command};
 use which::which;
+#[derive(Clone)]
 pub struct Builder {
     downloader: Downloader,
+    strict: bool,
+    buildinfo: bool,
+    check_smali: bool,
+    refresh_decompile: bool,
+    /// Prebuilts used by the target being built, for its build info
+    prebuilts: Vec<PrebuiltInfo>,
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-enum Mode {
+/// A prebuilt library an artifact was made from, as recorded in `.buildinfo`.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PrebuiltInfo {
+    platform: String,
+    frida_version: String,
+    origin: String,
+    sha256: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub enum Mode {
     EmbedJs = 1,
     WatchPath = 2,
 }
+/// Version of the `EmbeddedConfigData` schema.
+/// 2: added `runtime`
+/// 3: added `script_sha256`
+/// 4: added `runtime_config`
+/// 5: added `scripts`
+pub const EMBEDDED_DATA_VERSION: u32 = 5;
+
+/// Default `minSdkVersion` / `targetSdkVersion` of generated Xposed modules.
+const DEFAULT_XPOSED_MIN_SDK: u32 = 24;
+const DEFAULT_XPOSED_TARGET_SDK: u32 = 26;
+/// Lowest Android API level the Frida gadget supports.
+const GADGET_MIN_SDK: u32 = 21;
+
 #[derive(serde::Serialize, serde::Deserialize)]
-struct EmbeddedConfigData {
-    mode: Mode,
-    js_filepath: Option<String>,
-    js_content: Option<String>,
-    watch_path: Option<String>,
+pub struct EmbeddedConfigData {
+    #[serde(default)]
+    pub version: u32,
+    pub mode: Mode,
+    pub js_filepath: Option<String>,
+    pub js_content: Option<String>,
+    pub watch_path: Option<String>,
+    /// Loaders without runtime selection ignore this and use their default runtime.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime: Option<Runtime>,
+    /// SHA-256 (hex) of the uncompressed `js_content`, for integrity checks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script_sha256: Option<String>,
+    /// Environment-specific key/value pairs from `embedEnv`, for the agent to read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime_config: Option<BTreeMap<String, serde_json::Value>>,
+    /// Named scripts from `embedScripts`, for loaders that pick one at load time.
+    /// `js_content` remains the default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scripts: Option<BTreeMap<String, String>>,
+}
+
+/// Hex-encoded SHA-256 digest of `data`.
+pub fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
 }
 
-fn find_sdk_binary(bin_name: &str) -> Result<PathBuf> {
+pub fn find_sdk_binary(bin_name: &str) -> Result<PathBuf> {
     if let Ok(path) = which::which(bin_name) {
         return Ok(path);
     }
@@ -67,23 +136,85 @@ fn find_sdk_binary(bin_name: &str) -> Result<PathBuf> {
 
 impl Builder {
     pub fn new() -> Self {
+        Self::with_downloader(Downloader::new())
+    }
+
+    /// Creates a builder sharing `downloader` (and its download limit) with others.
+    pub fn with_downloader(downloader: Downloader) -> Self {
         Self {
-            downloader: Downloader::new(),
+            downloader,
+            strict: false,
+            buildinfo: false,
+            check_smali: false,
+            refresh_decompile: false,
+            prebuilts: Vec::new(),
         }
     }
 
+    /// Records the prebuilt files it downloads from now on as used by `project`.
+    pub fn with_project(mut self, project: String) -> Self {
+        self.downloader = self.downloader.with_project(project);
+        self
+    }
+
+    /// Turns warnings such as an exceeded `maxOutputSize` into errors.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Writes a `{artifact}.buildinfo` file next to each artifact.
+    pub fn with_buildinfo(mut self, buildinfo: bool) -> Self {
+        self.buildinfo = buildinfo;
+        self
+    }
+
+    /// Assembles generated smali with the `smali` tool (if installed) before apktool.
+    pub fn with_check_smali(mut self, check_smali: bool) -> Self {
+        self.check_smali = check_smali;
+        self
+    }
+
+    /// Decompiles source APKs with apktool again instead of reusing the cached
+    /// trees, and replaces the cached ones.
+    pub fn with_refresh_decompile(mut self, refresh_decompile: bool) -> Self {
+        self.refresh_decompile = refresh_decompile;
+        self
+    }
+
+    /// Builds `target`, returning its artifacts: one per platform for `shared`
+    /// targets, one for the others and none for targets without a type.
     pub async fn build_target(
         &mut self,
         target_name: &str,
         target: &ResolvedTarget,
-    ) -> Result<Option<String>> {
+    ) -> Result<Vec<PathBuf>> {
+        target.validate()?;
+
+        // Shared targets build one library per platform, each like its own target
+        if target.target_type.as_deref() == Some("shared") && target.platforms.len() > 1 {
+            let mut artifacts = Vec::new();
+            for platform in &target.platforms {
+                let mut platform_target = target.clone();
+                platform_target.platform = Some(platform.clone());
+                platform_target.platforms = vec![platform.clone()];
+                artifacts.extend(Box::pin(self.build_target(target_name, &platform_target)).await?);
+            }
+            return Ok(artifacts);
+        }
+
         // Run beforeBuild hook
         if let Some(cmd) = &target.before_build {
             self.run_hook(cmd).await?;
         }
+        self.prebuilts.clear();
+
+        if target.bundle.is_some() {
+            self.bundle(target).await?;
+        }
 
         let build_result = match target.target_type.as_deref() {
-            Some("shared") => Some(self.build_shared(target_name, target).await?),
+            Some("shared" | "android-so") => Some(self.build_shared(target_name, target).await?),
             Some("xposed") => Some(self.build_xposed(target_name, target).await?),
             Some("inject-apk") => Some(self.build_inject_apk(target_name, target).await?),
             Some("zygisk") => Some(self.build_zygisk(target_name, target).await?),
@@ -94,6 +225,22 @@ impl Builder {
             }
         };
 
+        if let Some(output_path) = &build_result {
+            if let Some(post_process) = &target.post_process {
+                self.post_process(post_process, output_path).await?;
+            }
+            self.check_output_size(target, output_path).await?;
+            let store_path = if target.output_store.unwrap_or(false) {
+                Some(store_artifact(target, output_path).await?)
+            } else {
+                None
+            };
+            if self.buildinfo {
+                self.write_buildinfo(target_name, target, output_path, store_path.as_deref())
+                    .await?;
+            }
+        }
+
         // Run afterBuild hook if build succeeded
         if build_result.is_some() {
             if let Some(cmd) = &target.after_build {
@@ -101,16 +248,172 @@ impl Builder {
             }
         }
 
-        Ok(build_result)
+        Ok(build_result.into_iter().collect())
     }
 
-    async fn run_hook(&self, cmd: &str) -> Result<()> {
-        info!("→ Running build hook: {}", cmd);
+    async fn check_output_size(&self, target: &ResolvedTarget, output_path: &Path) -> Result<()> {
+        let size = fs::metadata(output_path).await?.len();
+        info!(
+            "→ Artifact size: {} ({})",
+            format_bytes(size),
+            output_path.display()
+        );
+
+        if let Some(max_output_size) = &target.max_output_size {
+            let max_size = parse_size(max_output_size)?;
+            if size > max_size {
+                let message = format!(
+                    "Artifact {} is {}, exceeding maxOutputSize of {}",
+                    output_path.display(),
+                    format_bytes(size),
+                    format_bytes(max_size)
+                );
+                if self.strict {
+                    anyhow::bail!(message);
+                }
+                warn!("{message}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Warns when `signed_apk` isn't signed by the certificate `source_apk` was,
+    /// or fails the build with `strict`. Apps checking their own signature at
+    /// runtime notice the change, even though the APK installs fine.
+    async fn check_signer_kept(&self, source_apk: &Path, signed_apk: &Path) -> Result<()> {
+        let Some(original) = apk_signer_digests(source_apk).await? else {
+            info!("→ Source APK has no valid signature, skipping signer check");
+            return Ok(());
+        };
+        let Some(signed) = apk_signer_digests(signed_apk).await? else {
+            anyhow::bail!("apksigner could not verify the signed APK");
+        };
+        if original == signed {
+            info!("✓ Signed APK keeps the original signer");
+            return Ok(());
+        }
+
+        let message = format!(
+            "The injected APK is signed with a different certificate than {} (SHA-256 {} → {}). \
+             Apps that verify their own signature (integrity checks, anti-tampering, Play Integrity) \
+             may refuse to run or crash at startup, even though the APK installs",
+            source_apk.display(),
+            original.join(", "),
+            signed.join(", ")
+        );
+        if self.strict {
+            anyhow::bail!(message);
+        }
+        warn!("{message}");
+        Ok(())
+    }
+
+    /// Records how an artifact was produced: fripack and tool versions, the
+    /// prebuilts it was made from and the host. It has no timestamps, so two
+    /// build info files only differ where the builds did.
+    async fn write_buildinfo(
+        &mut self,
+        target_name: &str,
+        target: &ResolvedTarget,
+        artifact: &Path,
+        store_path: Option<&Path>,
+    ) -> Result<()> {
+        let mut tools = BTreeMap::new();
+        for tool in required_tools(target) {
+            let version = probe_tool_version(&tool).await;
+            tools.insert(tool, version);
+        }
+        let buildinfo = serde_json::json!({
+            "fripackVersion": env!("CARGO_PKG_VERSION"),
+            "target": target_name,
+            "type": target.target_type,
+            "fridaVersion": target.frida_version,
+            "artifact": artifact.file_name().map(|name| name.to_string_lossy()),
+            "artifactSha256": sha256_hex(&fs::read(artifact).await?),
+            "store": store_path.map(|path| path.to_string_lossy()),
+            "prebuilts": std::mem::take(&mut self.prebuilts),
+            "tools": tools,
+            "host": {
+                "os": std::env::consts::OS,
+                "arch": std::env::consts::ARCH,
+            },
+        });
+
+        let mut buildinfo_path = artifact.as_os_str().to_owned();
+        buildinfo_path.push(".buildinfo");
+        let buildinfo_path = PathBuf::from(buildinfo_path);
+        write_atomic(
+            &buildinfo_path,
+            serde_json::to_string_pretty(&buildinfo)?.as_bytes(),
+        )
+        .await?;
+        info!("✓ Wrote build info: {}", buildinfo_path.display());
+        Ok(())
+    }
+
+    /// Runs the `postProcess` command on the artifact. When the command uses
+    /// `{output}`, the file it writes there replaces the artifact.
+    async fn post_process(&self, template: &str, artifact_path: &Path) -> Result<()> {
+        let output_path = artifact_path.with_file_name(format!(
+            "{}.post{}",
+            artifact_path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy(),
+            artifact_path
+                .extension()
+                .map(|ext| format!(".{}", ext.to_string_lossy()))
+                .unwrap_or_default()
+        ));
+        let uses_output = template.contains("{output}");
+        if uses_output && output_path.exists() {
+            fs::remove_file(&output_path).await?;
+        }
+
+        let cmd = template
+            .replace("{artifact}", &shell_quote(&artifact_path.to_string_lossy()))
+            .replace("{output}", &shell_quote(&output_path.to_string_lossy()));
+        info!("→ Post-processing artifact: {cmd}");
         let output = if cfg!(target_os = "windows") {
-            Command::new("cmd").arg("/C").arg(cmd).output().await
+            Command::new("cmd").arg("/C").arg(&cmd).output().await
         } else {
-            Command::new("sh").arg("-c").arg(cmd).output().await
+            Command::new("sh").arg("-c").arg(&cmd).output().await
         }?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Post-process command failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        if uses_output {
+            if !output_path.exists() {
+                anyhow::bail!(
+                    "Post-process command did not write its output: {}",
+                    output_path.display()
+                );
+            }
+            fs::rename(&output_path, artifact_path).await?;
+        }
+        info!("✓ Post-processed artifact: {}", artifact_path.display());
+        Ok(())
+    }
+
+    async fn run_hook(&self, cmd: &str) -> Result<()> {
+        info!("→ Running build hook: {}", cmd);
+        let mut command = if cfg!(target_os = "windows") {
+            let mut command = Command::new("cmd");
+            command.arg("/C");
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.arg("-c");
+            command
+        };
+        // A build cancelled by --fail-fast stops its hook as well
+        let output = command.arg(cmd).kill_on_drop(true).output().await?;
 
         if !output.status.success() {
             anyhow::bail!(
@@ -122,6 +425,109 @@ impl Builder {
         Ok(())
     }
 
+    /// Decompiles `apk` with apktool into `output_dir`. The tree is cached in
+    /// `~/.fripack/decompiled/{apk-sha256}/` and copied from there while the APK
+    /// is unchanged, so injecting never modifies the cached tree.
+    async fn decompile_apk(
+        &self,
+        apk: &Path,
+        decode_resources: bool,
+        output_dir: &Path,
+        target: &ResolvedTarget,
+    ) -> Result<()> {
+        let apk_sha256 = sha256_hex(&fs::read(apk).await?);
+        let cache_dir = self
+            .downloader
+            .decompiled_dir()
+            .join(&apk_sha256)
+            .join(if decode_resources { "res" } else { "no-res" });
+
+        if cache_dir.exists() && !self.refresh_decompile {
+            info!("→ Using decompiled APK from cache: {}", cache_dir.display());
+            copy_dir(&cache_dir, output_dir).await?;
+            return Ok(());
+        }
+
+        info!("→ Decompiling APK with apktool...");
+        let parent = cache_dir.parent().context("Invalid cache directory")?;
+        fs::create_dir_all(parent).await?;
+        // Decompiled next to the cached tree and renamed into place when done, so
+        // an interrupted run never leaves a partial tree in the cache
+        let partial_dir = tempfile::Builder::new()
+            .prefix(".partial-")
+            .tempdir_in(parent)?;
+        let decompiled_dir = partial_dir.path().join("decompiled");
+        let mut cmd = apktool_command(target)?;
+        cmd.arg("d").arg("-f");
+        if !decode_resources {
+            cmd.arg("-r");
+        }
+        cmd.arg("-s").arg(apk).arg("-o").arg(&decompiled_dir);
+        let output = tool_output_with_retry(&mut cmd, "apktool d", target.tool_retries()).await?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "apktool decompile failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        info!("✓ APK decompiled successfully");
+
+        if cache_dir.exists() {
+            fs::remove_dir_all(&cache_dir).await?;
+        }
+        // Another build may have cached the same APK in the meantime, either tree will do
+        if let Err(e) = fs::rename(&decompiled_dir, &cache_dir).await {
+            if !cache_dir.exists() {
+                return Err(e.into());
+            }
+        } else {
+            info!("→ Cached decompiled APK: {}", cache_dir.display());
+        }
+        copy_dir(&cache_dir, output_dir).await
+    }
+
+    /// Runs the configured bundler (frida-compile by default), writing the
+    /// compiled agent to the target's `entry`.
+    pub async fn bundle(&self, target: &ResolvedTarget) -> Result<()> {
+        let Some(bundle) = &target.bundle else {
+            return Ok(());
+        };
+        let input = bundle
+            .input
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Missing required field: bundle.input"))?;
+        let entry = target
+            .entry
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Missing required field: entry (bundle output)"))?;
+        let command = bundle.command.as_deref().unwrap_or("frida-compile");
+
+        // frida-compile is usually a dev dependency of the agent project
+        let local_command = Path::new("node_modules").join(".bin").join(command);
+        let program = which(command).or_else(|_| which(&local_command))?;
+
+        info!("→ Bundling agent: {input} -> {entry}");
+        let output = Command::new(program)
+            .arg(input)
+            .arg("-o")
+            .arg(entry)
+            .explain()
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "{command} failed: {}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        info!("✓ Bundled agent to: {entry}");
+
+        Ok(())
+    }
+
     async fn generate_binary(&mut self, target: &ResolvedTarget) -> Result<Vec<u8>> {
         // Get required fields
         let platform = target
@@ -132,13 +538,73 @@ impl Builder {
             .frida_version
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("Missing required field: fridaVersion"))?;
-        let mode = if target.watch_mode {
+        // Without a push path there's nothing to watch, so the script is embedded
+        let mode = if target.watch_mode && target.push_path().is_some() {
             "watchpath"
         } else {
             "embedjs"
         };
         let use_xz = target.xz.unwrap_or(false);
+        let platform_compression = match &target.compression {
+            Some(compression) => compression.for_platform(platform)?,
+            None => None,
+        };
+        let compression_kind = match platform_compression {
+            Some(kind) => kind,
+            None if target.zstd_dict.is_some() => {
+                if use_xz {
+                    anyhow::bail!("xz and zstdDict cannot be used together");
+                }
+                CompressionKind::Zstd
+            }
+            None if use_xz && target.xz_format == Some(XzFormat::Lzma) => CompressionKind::Lzma,
+            None if use_xz => CompressionKind::Xz,
+            None => CompressionKind::None,
+        };
+        let compression = match compression_kind {
+            CompressionKind::None => Compression::None,
+            CompressionKind::Xz => Compression::Xz,
+            CompressionKind::Lzma => {
+                // Older loaders only know the xz container
+                if target.embedded_version.unwrap_or(1) < 2 {
+                    anyhow::bail!("lzma compression requires a loader with embeddedVersion 2");
+                }
+                Compression::Lzma
+            }
+            CompressionKind::Zstd => match &target.zstd_dict {
+                Some(dict_path) => {
+                    let dict = fs::read(dict_path)
+                        .await
+                        .with_context(|| format!("Failed to read zstd dictionary: {dict_path}"))?;
+                    zstd_dict_id(&dict)
+                        .with_context(|| format!("Invalid zstd dictionary: {dict_path}"))?;
+                    Compression::Zstd(Some(dict))
+                }
+                None => Compression::Zstd(None),
+            },
+        };
+        if platform_compression.is_some() {
+            info!("→ Embedded data compression for {platform}: {compression_kind:?}");
+        }
 
+        let mut downloader = match &target.prebuilt_base_url {
+            Some(prebuilt_base_url) => self
+                .downloader
+                .clone()
+                .with_prebuilt_base_url(prebuilt_base_url.clone()),
+            None => self.downloader.clone(),
+        };
+        if let Some(verify_command) = &target.verify_command {
+            downloader = downloader.with_verify_command(verify_command.clone());
+        }
+        let frida_version = &match &target.override_prebuild_file {
+            Some(_) => frida_version.clone(),
+            None => {
+                downloader
+                    .resolve_frida_version(platform, frida_version)
+                    .await?
+            }
+        };
         // Get prebuilt file data
         let prebuilt_data = if let Some(override_file) = &target.override_prebuild_file {
             info!("→ Using override prebuilt file: {override_file}");
@@ -154,14 +620,73 @@ impl Builder {
             fs::read(override_file).await?
         } else {
             info!("→ Downloading prebuilt file for platform: {platform:?}");
-            self.downloader
-                .download_prebuilt_file(platform, frida_version)
+            downloader
+                .download_prebuilt_file(platform, frida_version, &target.download_headers()?)
                 .await?
         };
+        if self.buildinfo {
+            self.prebuilts.push(PrebuiltInfo {
+                platform: platform.to_string(),
+                frida_version: frida_version.clone(),
+                origin: match &target.override_prebuild_file {
+                    Some(override_file) => override_file.clone(),
+                    None => downloader.get_prebuilt_file_url(platform, frida_version),
+                },
+                sha256: sha256_hex(&prebuilt_data),
+            });
+        }
 
         // Process the binary
         info!("→ Processing binary...");
         let mut processor = BinaryProcessor::new(prebuilt_data)?;
+        // The binary must carry the zeroed EmbeddedConfig the data is hooked up to
+        if processor.find_embedded_config().is_none() {
+            let diagnostic = processor.diagnose_embedded_config();
+            match &target.override_prebuild_file {
+                Some(override_file) => anyhow::bail!(
+                    "override file has no fripack config placeholder: {override_file}: {diagnostic} - expected an EmbeddedConfig with zeroed size/offset"
+                ),
+                None => anyhow::bail!(
+                    "prebuilt does not contain a fripack config placeholder: {diagnostic} - expected a zeroed placeholder; is this a fripack-inject build?"
+                ),
+            }
+        }
+        if let Some(name) = &target.pe_section_name {
+            processor.set_pe_section_name(pe_section_name(name)?);
+        }
+        if let Some(version) = target.embedded_version {
+            processor.set_embedded_version(version)?;
+        }
+        processor.set_base64_encoding(target.embed_encoding == Some(EmbedEncoding::Base64));
+        if let Some(vaddr) = target.embed_vaddr {
+            processor.set_embed_vaddr(vaddr);
+        }
+        processor.set_embed_auto_align(target.embed_auto_align.unwrap_or(true));
+        if let Some(proxy_dll) = &target.proxy_dll {
+            if platform.platform != Platform::Windows {
+                anyhow::bail!("proxyDll is only supported for Windows targets");
+            }
+            let forward_to = match &proxy_dll.forward_to {
+                Some(forward_to) => forward_to.clone(),
+                None => format!(
+                    "{}_orig",
+                    Path::new(&proxy_dll.proxy_target)
+                        .file_stem()
+                        .context("Invalid proxyTarget path")?
+                        .to_string_lossy()
+                ),
+            };
+            let original = fs::read(&proxy_dll.proxy_target).await.with_context(|| {
+                format!("Failed to read proxyTarget {}", proxy_dll.proxy_target)
+            })?;
+            let proxy_exports = ProxyExports::read(&original, forward_to.clone())?;
+            info!(
+                "→ Forwarding {} exports of {} to {forward_to}",
+                proxy_exports.export_count(),
+                proxy_dll.proxy_target
+            );
+            processor.set_proxy_exports(proxy_exports);
+        }
 
         let config_data = match mode {
             "embedjs" => {
@@ -172,24 +697,35 @@ impl Builder {
                 // Read entry file
                 info!("→ Reading entry file: {entry}");
                 let entry_data = fs::read(entry).await?;
+                let js_content = String::from_utf8_lossy(&entry_data).to_string();
 
                 EmbeddedConfigData {
+                    version: EMBEDDED_DATA_VERSION,
                     mode: Mode::EmbedJs,
                     js_filepath: Some(entry.clone()),
-                    js_content: Some(String::from_utf8_lossy(&entry_data).to_string()),
+                    script_sha256: Some(sha256_hex(js_content.as_bytes())),
+                    js_content: Some(js_content),
                     watch_path: None,
+                    runtime: target.runtime.clone(),
+                    runtime_config: target.runtime_config()?,
+                    scripts: read_embed_scripts(target).await?,
                 }
             }
             "watchpath" => {
-                let push_path = target.push_path.as_ref().ok_or_else(|| {
+                let push_path = target.push_path().ok_or_else(|| {
                     anyhow::anyhow!("Missing required field: pushPath for watchpath mode")
                 })?;
 
                 EmbeddedConfigData {
+                    version: EMBEDDED_DATA_VERSION,
                     mode: Mode::WatchPath,
                     js_filepath: None,
                     js_content: None,
-                    watch_path: Some(push_path.clone()),
+                    watch_path: Some(push_path.to_string()),
+                    runtime: target.runtime.clone(),
+                    script_sha256: None,
+                    runtime_config: target.runtime_config()?,
+                    scripts: None,
                 }
             }
             _ => anyhow::bail!("Unsupported mode: {mode}"),
@@ -198,42 +734,105 @@ impl Builder {
         let config_data = serde_json::to_string(&config_data)?;
 
         // Add embedded config section
-        processor
-            .add_embedded_config_data(config_data.as_bytes(), use_xz)
-            .unwrap();
+        processor.add_embedded_config_data(config_data.as_bytes(), &compression)?;
 
-        processor.anti_anti_frida()?;
+        if !target.anti_detection.unwrap_or(true) {
+            info!("→ Skipping anti-detection (antiDetection is disabled)");
+            return Ok(processor.into_data());
+        }
+        if let Some(keywords) = &target.anti_detection_keywords {
+            processor.set_anti_detection_keywords(keywords)?;
+        }
+        let keyword_counts = processor.anti_anti_frida()?;
+        if target.require_anti_detection.unwrap_or(false) {
+            let Some(keyword_counts) = keyword_counts else {
+                anyhow::bail!(
+                    "requireAntiDetection is set, but anti-detection only supports ELF binaries"
+                );
+            };
+            for core_keyword in ["frida", "gum"] {
+                if !keyword_counts
+                    .iter()
+                    .any(|(keyword, count)| keyword == core_keyword && *count > 0)
+                {
+                    anyhow::bail!(
+                        "Anti-detection replaced no occurrences of {core_keyword:?}, the prebuilt layout may have changed"
+                    );
+                }
+            }
+        }
 
         let output_data = processor.into_data();
 
         Ok(output_data)
     }
 
-    async fn build_shared(&mut self, target_name: &str, target: &ResolvedTarget) -> Result<String> {
+    async fn build_shared(
+        &mut self,
+        target_name: &str,
+        target: &ResolvedTarget,
+    ) -> Result<PathBuf> {
         let base_name = target.target_base_name.as_deref().unwrap_or(target_name);
         info!("→ Building Shared Library target: {target_name} (base name: {base_name})");
 
-        let output_dir = target.output_dir.as_deref().unwrap_or("./fripack");
-
         let output_data = self.generate_binary(target).await?;
         let platform = target
             .platform
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("Missing required field: platform"))?;
-        let output_filename = format!("{base_name}-{platform}.{}", platform.platform.binary_ext());
-        let output_file_path = std::path::Path::new(output_dir).join(&output_filename);
-        std::fs::create_dir_all(output_dir)?;
-        fs::write(&output_file_path, output_data).await?;
+        let output_dir = target.artifact_dir(target_name, &platform.to_string());
+        let output_filename = if target.target_type.as_deref() == Some("android-so") {
+            android_library_name(base_name)
+        } else {
+            format!("{base_name}-{platform}.{}", platform.platform.binary_ext())
+        };
+        let output_file_path = output_dir.join(&output_filename);
+        std::fs::create_dir_all(&output_dir)?;
+        write_atomic(&output_file_path, &output_data).await?;
 
         info!(
             "✓ Successfully built shared library: {}",
             output_file_path.display()
         );
 
-        Ok(output_file_path.to_string_lossy().to_string())
+        Ok(output_file_path)
+    }
+
+    /// Generates the .so of every platform of `target` into `lib/{abi}/{so_name}`
+    /// under `apk_dir`, next to copies of `native_libs` named `native_lib_names`.
+    async fn write_xposed_libs(
+        &mut self,
+        target: &ResolvedTarget,
+        apk_dir: &Path,
+        so_name: &str,
+        native_libs: &[String],
+        native_lib_names: &[String],
+    ) -> Result<()> {
+        for abi_platform in &target.platforms {
+            let mut abi_target = target.clone();
+            abi_target.platform = Some(abi_platform.clone());
+            let binary_data = self.generate_binary(&abi_target).await?;
+
+            let lib_dir = apk_dir.join("lib").join(abi_platform.android_abi()?);
+            fs::create_dir_all(&lib_dir).await?;
+            let dest_so_path = lib_dir.join(so_name);
+            fs::write(&dest_so_path, &binary_data).await?;
+            info!("→ Copied .so to: {}", dest_so_path.display());
+
+            for (native_lib, file_name) in native_libs.iter().zip(native_lib_names) {
+                let dest_lib_path = lib_dir.join(file_name);
+                fs::copy(native_lib, &dest_lib_path).await?;
+                info!("→ Copied native library to: {}", dest_lib_path.display());
+            }
+        }
+        Ok(())
     }
 
-    async fn build_xposed(&mut self, target_name: &str, target: &ResolvedTarget) -> Result<String> {
+    async fn build_xposed(
+        &mut self,
+        target_name: &str,
+        target: &ResolvedTarget,
+    ) -> Result<PathBuf> {
         let base_name = target.target_base_name.as_deref().unwrap_or(target_name);
         info!("→ Building Xposed target: {target_name} (base name: {base_name})");
 
@@ -255,13 +854,24 @@ impl Builder {
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("Missing required field: name"))?;
 
-        if platform.platform != Platform::Android {
+        if target
+            .platforms
+            .iter()
+            .any(|platform| platform.platform != Platform::Android)
+        {
             anyhow::bail!("Xposed target only supports Android platform");
         }
 
+        // Several platforms produce one fat APK with a lib/{abi}/ dir per platform
+        let fat = target.platforms.len() > 1;
+        let platform_name = if fat {
+            "android-universal".to_string()
+        } else {
+            platform.to_string()
+        };
+
         let sign = target.sign.is_some();
-        let output_dir = target.output_dir.as_deref().unwrap_or("./fripack");
-        let binary_data = self.generate_binary(target).await?;
+        let output_dir = target.artifact_dir(target_name, &platform_name);
 
         let random_so_name = format!("lib{}.so", generate_random_string(8));
 
@@ -270,17 +880,35 @@ impl Builder {
         let temp_path = temp_dir.path();
         info!("→ Created temporary directory: {}", temp_path.display());
 
-        // Move the generated .so file to the temporary directory for now
-        let temp_so_path = temp_path.join(&random_so_name);
-        fs::write(&temp_so_path, &binary_data).await?;
-
-        // 4. Create assets/native_init and assets/xposed_init files
+        // 4. Create assets/native_init (unless disabled) and assets/xposed_init files
         let assets_dir = temp_path.join("assets");
         fs::create_dir_all(&assets_dir).await?;
 
-        let native_init_path = assets_dir.join("native_init");
-        fs::write(&native_init_path, &random_so_name).await?;
-        info!("→ Created native_init: {}", native_init_path.display());
+        // Additional helper libraries are loaded after the gadget, in the listed order
+        let native_libs = xposed_config.native_libs.as_deref().unwrap_or_default();
+        if fat && !native_libs.is_empty() {
+            anyhow::bail!("xposed.nativeLibs is not supported for multi-platform Xposed modules");
+        }
+        let mut native_lib_names = vec![random_so_name.clone()];
+        for native_lib in native_libs {
+            let native_lib_path = Path::new(native_lib);
+            if !native_lib_path.is_file() {
+                anyhow::bail!("Native library not found: {native_lib}");
+            }
+            let file_name = native_lib_path
+                .file_name()
+                .context("Invalid native library path")?
+                .to_string_lossy()
+                .to_string();
+            native_lib_names.push(file_name);
+        }
+
+        // Frameworks that only read xposed_init rely on the smali loader alone
+        if xposed_config.emit_native_init.unwrap_or(true) {
+            let native_init_path = assets_dir.join("native_init");
+            fs::write(&native_init_path, native_lib_names.join("\n")).await?;
+            info!("→ Created native_init: {}", native_init_path.display());
+        }
 
         // 5. Generate a random class name for the smali file
         let random_class_name =
@@ -292,12 +920,14 @@ impl Builder {
         info!("→ Created xposed_init: {}", xposed_init_path.display());
 
         // 6. Copy the generated .so file to lib/架构/libxxxx.so within the temporary directory.
-
-        let lib_dir = temp_path.join("lib").join(platform.android_abi()?);
-        fs::create_dir_all(&lib_dir).await?;
-        let dest_so_path = lib_dir.join(&random_so_name);
-        fs::copy(&temp_so_path, &dest_so_path).await?;
-        info!("→ Copied .so to: {}", dest_so_path.display());
+        self.write_xposed_libs(
+            target,
+            temp_path,
+            &random_so_name,
+            native_libs,
+            &native_lib_names[1..],
+        )
+        .await?;
 
         info!("✓ Successfully built Xposed module: {target_name}");
 
@@ -307,6 +937,55 @@ impl Builder {
 
         let smali_file_path = smali_dir_path.join(format!("{random_class_name}.smali"));
 
+        // Extracted native libs live in lib/{arch}/ next to the module APK. A fat module
+        // only gets the device's ABI extracted, so load whichever of them exists.
+        let load_library = if fat {
+            let mut code = String::new();
+            for (i, abi_platform) in target.platforms.iter().enumerate() {
+                code += &format!(
+                    r#"    new-instance v1, Ljava/lang/StringBuilder;
+    invoke-direct {{v1}}, Ljava/lang/StringBuilder;-><init>()V
+    invoke-virtual {{v1, v0}}, Ljava/lang/StringBuilder;->append(Ljava/lang/String;)Ljava/lang/StringBuilder;
+    const-string v2, "/lib/{}/{}"
+    invoke-virtual {{v1, v2}}, Ljava/lang/StringBuilder;->append(Ljava/lang/String;)Ljava/lang/StringBuilder;
+    invoke-virtual {{v1}}, Ljava/lang/StringBuilder;->toString()Ljava/lang/String;
+    move-result-object v1
+
+    new-instance v2, Ljava/io/File;
+    invoke-direct {{v2, v1}}, Ljava/io/File;-><init>(Ljava/lang/String;)V
+    invoke-virtual {{v2}}, Ljava/io/File;->exists()Z
+    move-result v2
+    if-eqz v2, :try_{}
+
+    invoke-static {{v1}}, Ljava/lang/System;->load(Ljava/lang/String;)V
+    return-void
+
+    :try_{}
+"#,
+                    abi_platform.frida_arch()?,
+                    random_so_name,
+                    i + 1,
+                    i + 1
+                );
+            }
+            code
+        } else {
+            format!(
+                r#"    new-instance v1, Ljava/lang/StringBuilder;
+    invoke-direct {{v1}}, Ljava/lang/StringBuilder;-><init>()V
+    invoke-virtual {{v1, v0}}, Ljava/lang/StringBuilder;->append(Ljava/lang/String;)Ljava/lang/StringBuilder;
+    const-string v2, "/lib/{}/{}"
+    invoke-virtual {{v1, v2}}, Ljava/lang/StringBuilder;->append(Ljava/lang/String;)Ljava/lang/StringBuilder;
+    invoke-virtual {{v1}}, Ljava/lang/StringBuilder;->toString()Ljava/lang/String;
+    move-result-object v1
+    
+    invoke-static {{v1}}, Ljava/lang/System;->load(Ljava/lang/String;)V
+"#,
+                platform.frida_arch()?,
+                random_so_name
+            )
+        };
+
         let smali_content = format!(
             r#".class public L{}/{};
 .super Ljava/lang/Object;
@@ -328,16 +1007,7 @@ impl Builder {
     invoke-virtual {{v1}}, Ljava/io/File;->getParent()Ljava/lang/String;
     move-result-object v0
 
-    new-instance v1, Ljava/lang/StringBuilder;
-    invoke-direct {{v1}}, Ljava/lang/StringBuilder;-><init>()V
-    invoke-virtual {{v1, v0}}, Ljava/lang/StringBuilder;->append(Ljava/lang/String;)Ljava/lang/StringBuilder;
-    const-string v2, "/lib/{}/{}"
-    invoke-virtual {{v1, v2}}, Ljava/lang/StringBuilder;->append(Ljava/lang/String;)Ljava/lang/StringBuilder;
-    invoke-virtual {{v1}}, Ljava/lang/StringBuilder;->toString()Ljava/lang/String;
-    move-result-object v1
-    
-    invoke-static {{v1}}, Ljava/lang/System;->load(Ljava/lang/String;)V
-    return-void
+{}    return-void
 .end method
 
 .method public handleLoadPackage(Lde/robv/android/xposed/callbacks/XC_LoadPackage$LoadPackageParam;)V
@@ -347,12 +1017,12 @@ impl Builder {
 "#,
             package_name.replace(".", "/"),
             random_class_name,
-            platform.frida_arch()?,
-            random_so_name
+            load_library
         );
 
         fs::write(&smali_file_path, smali_content.as_bytes()).await?;
         info!("→ Created smali file: {}", smali_file_path.display());
+        smali::check(&smali_file_path, &smali_content, self.check_smali).await?;
 
         // 8. Copy ic_launcher.webp and ic_launcher_round.webp if specified in the config.
         if let Some(icon_path_str) = xposed_config.icon.as_ref() {
@@ -390,76 +1060,32 @@ impl Builder {
 
         // 9. Modify AndroidManifest.xml based on the configuration.
         let manifest_path = temp_path.join("AndroidManifest.xml");
-
-        let icon_attributes = if xposed_config.icon.is_some() {
-            r#"android:icon="@mipmap/ic_launcher" android:roundIcon="@mipmap/ic_launcher_round""#
-                .to_string()
-        } else {
-            "".to_string()
-        };
-
-        let xposed_description = xposed_config
-            .description
-            .as_deref()
-            .unwrap_or("Easy example which makes the status bar clock red and adds a smiley");
-        let xposed_scope = xposed_config
-            .scope
-            .as_deref()
-            .unwrap_or("com.example.a;com.example.b");
-
-        let manifest_content = format!(
-            r#"<?xml version="1.0" encoding="utf-8" standalone="no"?>
-<manifest xmlns:android="http://schemas.android.com/apk/res/android" android:compileSdkVersion="36" android:compileSdkVersionCodename="16" package="{package_name}" platformBuildVersionCode="36" platformBuildVersionName="16">
-    <application android:debuggable="true" android:extractNativeLibs="true"
-                {icon_attributes} android:label="{name}">
-        <meta-data android:name="xposedmodule" android:value="true"/>
-        <meta-data android:name="xposeddescription" android:value="{xposed_description}"/>
-        <meta-data android:name="xposedminversion" android:value="53"/>
-        <meta-data android:name="xposedscope" android:value="{xposed_scope}"/>
-    </application>
-</manifest>"#
-        );
+        // minSdk/targetSdk go into both the manifest and apktool.yml
+        let sdk_versions = xposed_sdk_versions(xposed_config)?;
+        let manifest_content =
+            render_xposed_manifest(package_name, name, xposed_config, sdk_versions);
 
         fs::write(&manifest_path, manifest_content.as_bytes()).await?;
         info!("→ Created AndroidManifest.xml: {}", manifest_path.display());
 
         // 10. Create apktool.yml with the specified content.
         let apktool_yml_path = temp_path.join("apktool.yml");
-        let apktool_yml_content = r#"apkFileName: app-debug.apk
-isFrameworkApk: false
-usesFramework:
-  ids:
-  - 1
-  tag: null
-sdkInfo:
-  minSdkVersion: 24
-  targetSdkVersion: 26
-packageInfo:
-  forcedPackageId: 127
-  renameManifestPackage: null
-versionInfo:
-  versionCode: 1
-  versionName: 1.0
-resourcesAreCompressed: false
-sharedLibrary: false
-sparseResources: true
-unknownFiles:
-doNotCompress:
-- resources.arsc
-- webp"#;
+        let do_not_compress = xposed_do_not_compress(&target.platforms, &native_lib_names)?;
+        let apktool_yml_content = render_xposed_apktool_yml(sdk_versions, &do_not_compress);
 
         fs::write(&apktool_yml_path, apktool_yml_content.as_bytes()).await?;
         info!("→ Created apktool.yml: {}", apktool_yml_path.display());
+        let built_apk_path =
+            apktool_dist_apk(temp_path, &serde_yaml::from_str(&apktool_yml_content)?);
 
         // 11. Build the APK using apktool b.
         info!("→ Building APK with apktool b...");
-        let output = tokio::process::Command::new(which::which("apktool")?)
-            .arg("b")
+        let mut cmd = apktool_command(target)?;
+        cmd.arg("b")
             .arg(temp_path.to_str().unwrap())
             .arg("-o")
-            .arg(temp_path.join("dist").join("app-debug.apk"))
-            .output()
-            .await?;
+            .arg(&built_apk_path);
+        let output = tool_output_with_retry(&mut cmd, "apktool b", target.tool_retries()).await?;
 
         if !output.status.success() {
             anyhow::bail!(
@@ -469,68 +1095,69 @@ doNotCompress:
         }
         info!("✓ APK built successfully with apktool b.");
 
-        // 12. Sign the APK using apksigner.
+        // 12. Align the APK, then sign it using apksigner.
+        let emit_both = sign && target.emit_both.unwrap_or(false);
+        info!("→ Aligning APK with zipalign...");
+        let aligned_apk_path = temp_path.join("dist").join("app-aligned.apk");
+        let output = zipalign(&built_apk_path, &aligned_apk_path).await?;
+        let unsigned_apk_path = if output.status.success() {
+            info!("✓ APK aligned successfully");
+            aligned_apk_path
+        } else if !sign || emit_both {
+            // Nothing after us could align an APK meant to be signed elsewhere
+            anyhow::bail!(
+                "zipalign failed, refusing to produce an unaligned unsigned APK: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        } else {
+            warn!(
+                "zipalign failed: {}, proceeding with unaligned APK",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            built_apk_path
+        };
+
         if sign {
             info!("→ Signing APK with apksigner...");
-            let unsigned_apk_path = temp_path.join("dist").join("app-debug.apk");
             let signed_apk_path = temp_path
                 .join("dist")
-                .join(format!("{base_name}-{platform}-signed.apk"));
+                .join(format!("{base_name}-{platform_name}-signed.apk"));
 
             let sign_config = target.sign.as_ref().unwrap();
-            let keystore = &sign_config.keystore;
-            let keystore_pass = &sign_config.keystore_pass;
-            let keystore_alias = &sign_config.keystore_alias;
-            let key_pass = &sign_config.key_pass.as_deref().unwrap_or(keystore_pass);
-            let keystore_path = if PathBuf::from(keystore).is_absolute() {
-                PathBuf::from(keystore)
-            } else {
-                std::env::current_dir()?.join(keystore)
-            };
-
-            let mut command = Command::new(find_sdk_binary("apksigner")?);
-            command
-                .arg("sign")
-                .arg("--ks")
-                .arg(&keystore_path)
-                .arg("--ks-key-alias")
-                .arg(keystore_alias)
-                .arg("--ks-pass")
-                .arg(format!("pass:{keystore_pass}"))
-                .arg("--key-pass")
-                .arg(format!("pass:{key_pass}"));
-
-            let output = command
-                .arg("--out")
-                .arg(&signed_apk_path)
-                .arg(&unsigned_apk_path)
-                .output()
-                .await?;
-
-            if !output.status.success() {
-                anyhow::bail!(
-                    "apksigner failed: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                );
-            }
+            sign_apk(
+                sign_config,
+                &unsigned_apk_path,
+                &signed_apk_path,
+                target.tool_retries(),
+            )
+            .await?;
             info!("✓ APK signed successfully with apksigner.");
 
             // 13. Copy the signed APK back to the desired location.
-            let final_apk_name = format!("{base_name}-{platform}.apk");
-            let final_apk_path = std::path::Path::new(&output_dir).join(&final_apk_name);
-            std::fs::create_dir_all(output_dir)?;
-            fs::copy(&signed_apk_path, &final_apk_path).await?;
+            let final_apk_name = format!("{base_name}-{platform_name}.apk");
+            let final_apk_path = output_dir.join(&final_apk_name);
+            std::fs::create_dir_all(&output_dir)?;
+            let unsigned_final_apk_path =
+                output_dir.join(format!("{base_name}-{platform_name}-unsigned.apk"));
+            emit_signed_apk(
+                &signed_apk_path,
+                &final_apk_path,
+                emit_both.then_some((
+                    unsigned_apk_path.as_path(),
+                    unsigned_final_apk_path.as_path(),
+                )),
+            )
+            .await?;
             info!("✓ Copied signed APK to: {}", final_apk_path.display());
-            Ok(final_apk_path.to_string_lossy().to_string())
+            Ok(final_apk_path)
         } else {
             // If not signing, just copy the unsigned APK
-            let unsigned_apk_path = temp_path.join("dist").join("app-debug.apk");
-            let final_apk_name = format!("{base_name}-{platform}.apk");
-            let final_apk_path = std::path::Path::new(&output_dir).join(&final_apk_name);
-            std::fs::create_dir_all(output_dir)?;
-            fs::copy(&unsigned_apk_path, &final_apk_path).await?;
+            let final_apk_name = format!("{base_name}-{platform_name}.apk");
+            let final_apk_path = output_dir.join(&final_apk_name);
+            std::fs::create_dir_all(&output_dir)?;
+            copy_atomic(&unsigned_apk_path, &final_apk_path).await?;
             info!("✓ Copied APK to: {}", final_apk_path.display());
-            Ok(final_apk_path.to_string_lossy().to_string())
+            Ok(final_apk_path)
         }
     }
 
@@ -538,7 +1165,7 @@ doNotCompress:
         &mut self,
         target_name: &str,
         target: &ResolvedTarget,
-    ) -> Result<String> {
+    ) -> Result<PathBuf> {
         let base_name = target.target_base_name.as_deref().unwrap_or(target_name);
         info!("→ Building Inject APK target: {target_name} (base name: {base_name})");
 
@@ -564,7 +1191,7 @@ doNotCompress:
             anyhow::bail!("Either sourceApkPath or sourceApkPackageName must be provided");
         }
 
-        let output_dir = target.output_dir.as_deref().unwrap_or("./fripack");
+        let output_dir = target.artifact_dir(target_name, &platform.to_string());
         let injected_binary_data = self.generate_binary(target).await?;
 
         // Get source APK path (either from path or extract from device)
@@ -574,7 +1201,17 @@ doNotCompress:
         } else {
             let package_name = inject_config.source_apk_package_name.as_ref().unwrap();
             info!("→ Extracting APK from device for package: {package_name}");
-            self.extract_apk_from_device(package_name).await?
+            let prefer_split = inject_config.prefer_split.unwrap_or(PreferSplit::Base);
+            if let Some(address) = &target.adb_connect {
+                adb::connect(address).await?;
+            }
+            self.extract_apk_from_device(
+                package_name,
+                prefer_split,
+                &platform.android_abi()?,
+                target.device.as_deref(),
+            )
+            .await?
         };
 
         // Create temporary directory for APK manipulation
@@ -582,120 +1219,146 @@ doNotCompress:
         let temp_path = temp_dir.keep();
         info!("→ Created temporary directory: {}", temp_path.display());
 
-        // Decompile APK using apktool
-        let decompiled_dir = temp_path.join("decompiled");
-        info!("→ Decompiling APK with apktool...");
-
-        // Apktool 3.x has an issue when using -r flag
-        // https://github.com/iBotPeaches/Apktool/issues/4103
-
-        let apktool = which::which("apktool")?;
-        let version = tokio::process::Command::new(&apktool).output().await?;
-        // Apktool 3.0.1 - a tool for reengineering Android apk files
-
-        let mut cmd = tokio::process::Command::new(&apktool);
-        cmd.arg("d").arg("-f");
+        let inject_lib_name = format!("lib{}.so", generate_random_string(8));
+        info!("→ Injecting library as: {}", inject_lib_name);
 
-        if !String::from_utf8_lossy(&version.stdout).contains("Apktool 3.") {
-            cmd.arg("-r");
+        let rebuilt_apk_path = if inject_config.repackages_as_zip() {
+            // Only the target library and our payload change, so the zip can be
+            // rewritten in place without decoding resources
+            info!("→ Repackaging APK without apktool...");
+            let abi = platform.android_abi()?;
+            let lib_dir = temp_path.join("lib").join(&abi);
+            let lib_prefix = format!("lib/{abi}/");
+            extract_apk_libraries(&source_apk_path, &lib_prefix, &lib_dir)?;
+
+            let target_lib_paths = self.find_target_libraries(&lib_dir, inject_config).await?;
+            inject_needed_libraries(&target_lib_paths, &inject_lib_name).await?;
+
+            let mut target_lib_entries = Vec::new();
+            for target_lib_path in &target_lib_paths {
+                let target_lib_entry = format!(
+                    "{lib_prefix}{}",
+                    target_lib_path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                );
+                target_lib_entries.push((target_lib_entry, fs::read(target_lib_path).await?));
+            }
+            let rebuilt_apk_path = temp_path.join(format!("{base_name}-{platform}-repackaged.apk"));
+            repackage_apk(
+                &source_apk_path,
+                &rebuilt_apk_path,
+                &target_lib_entries,
+                (
+                    &format!("{lib_prefix}{inject_lib_name}"),
+                    &injected_binary_data,
+                ),
+            )?;
+            info!("✓ APK repackaged successfully");
+            rebuilt_apk_path
         } else {
-            warn!("Detected apktool 3.x, skipping --no-res flag due to known issues.");
-        }
-        let output = cmd
-            .arg("-s")
-            .arg(&source_apk_path)
-            .arg("-o")
-            .arg(&decompiled_dir)
-            .output()
-            .await?;
+            // Decompile APK using apktool
+            let decompiled_dir = temp_path.join("decompiled");
 
-        if !output.status.success() {
-            anyhow::bail!(
-                "apktool decompile failed: {}",
-                String::from_utf8_lossy(&output.stderr)
-            );
-        }
-        info!("✓ APK decompiled successfully");
-
-        // Find target native library
-        let lib_dir = decompiled_dir.join("lib").join(platform.android_abi()?);
-        // Read the target library
-        let target_lib_path = self
-            .find_target_library(&lib_dir, &inject_config.target_lib)
-            .await?;
+            // Apktool 3.x has an issue when using -r flag
+            // https://github.com/iBotPeaches/Apktool/issues/4103
 
-        info!("→ Selected target library: {}", target_lib_path.display());
+            let version = apktool_command(target)?.explain().output().await?;
+            // Apktool 3.0.1 - a tool for reengineering Android apk files
 
-        // Inject our library using ELF manipulation
-        let inject_lib_name = format!("lib{}.so", generate_random_string(8));
-        info!("→ Injecting library as: {}", inject_lib_name);
-        add_needed_library_to_file(&target_lib_path, &inject_lib_name)?;
-
-        // Write the modified library back
-        fs::write(
-            Path::new(&target_lib_path)
-                .parent()
-                .unwrap()
-                .join(&inject_lib_name),
-            &injected_binary_data,
-        )
-        .await?;
-        info!("→ Modified library written back");
+            let stub_application = matches!(
+                inject_config.inject_mode,
+                InjectMode::ApplicationStub | InjectMode::LoaderDex
+            );
+            let decode_resources = if stub_application {
+                // The manifest has to be decoded to point it at the stub Application
+                true
+            } else if !String::from_utf8_lossy(&version.stdout).contains("Apktool 3.") {
+                false
+            } else {
+                warn!("Detected apktool 3.x, skipping --no-res flag due to known issues.");
+                true
+            };
+            self.decompile_apk(&source_apk_path, decode_resources, &decompiled_dir, target)
+                .await?;
 
-        // Add our native lib path into the do_not_compress list in apktool.yml
-        let apktool_yml_path = decompiled_dir.join("apktool.yml");
-        let apktool_yml_content = fs::read_to_string(&apktool_yml_path).await?;
-        let mut apktool_yml: serde_yaml::Value = serde_yaml::from_str(&apktool_yml_content)?;
+            let lib_dir = decompiled_dir.join("lib").join(platform.android_abi()?);
 
-        let inject_lib_relpath = format!("lib/{}/{}", platform.android_abi()?, inject_lib_name);
-        if let Some(do_not_compress) = apktool_yml
-            .get_mut("doNotCompress")
-            .and_then(|v| v.as_sequence_mut())
-        {
-            do_not_compress.push(serde_yaml::Value::String(inject_lib_relpath));
-        } else {
-            apktool_yml["doNotCompress"] =
-                serde_yaml::Value::Sequence(vec![serde_yaml::Value::String(inject_lib_relpath)]);
-        }
+            if stub_application {
+                // Leave every existing library byte-identical and bootstrap from the manifest
+                let as_dex = matches!(inject_config.inject_mode, InjectMode::LoaderDex);
+                self.add_application_stub(&decompiled_dir, &inject_lib_name, as_dex)
+                    .await?;
+                fs::create_dir_all(&lib_dir).await?;
+            } else {
+                // Find target native libraries
+                let target_lib_paths = self.find_target_libraries(&lib_dir, inject_config).await?;
+                inject_needed_libraries(&target_lib_paths, &inject_lib_name).await?;
+            }
 
-        let apktool_yml_serialized = serde_yaml::to_string(&apktool_yml)?;
-        fs::write(&apktool_yml_path, apktool_yml_serialized).await?;
-        info!("→ Updated apktool.yml to avoid compressing injected library");
+            // Write our library next to the app's own ones
+            fs::write(lib_dir.join(&inject_lib_name), &injected_binary_data).await?;
+            info!("→ Injected library written");
+
+            // Add our native lib path into the do_not_compress list in apktool.yml
+            let apktool_yml_path = decompiled_dir.join("apktool.yml");
+            let apktool_yml_content = fs::read_to_string(&apktool_yml_path).await?;
+            let mut apktool_yml: serde_yaml::Value = serde_yaml::from_str(&apktool_yml_content)?;
+
+            let inject_lib_relpath = format!("lib/{}/{}", platform.android_abi()?, inject_lib_name);
+            if let Some(do_not_compress) = apktool_yml
+                .get_mut("doNotCompress")
+                .and_then(|v| v.as_sequence_mut())
+            {
+                do_not_compress.push(serde_yaml::Value::String(inject_lib_relpath));
+            } else {
+                apktool_yml["doNotCompress"] =
+                    serde_yaml::Value::Sequence(vec![serde_yaml::Value::String(
+                        inject_lib_relpath,
+                    )]);
+            }
 
-        // Rebuild APK using apktool
-        info!("→ Rebuilding APK with apktool...");
-        let rebuilt_apk_path = decompiled_dir.join("dist").join("app-debug.apk");
-        let output = tokio::process::Command::new(which::which("apktool")?)
-            .arg("b")
-            .arg(&decompiled_dir)
-            .arg("-o")
-            .arg(&rebuilt_apk_path)
-            .output()
-            .await?;
+            let apktool_yml_serialized = serde_yaml::to_string(&apktool_yml)?;
+            fs::write(&apktool_yml_path, apktool_yml_serialized).await?;
+            info!("→ Updated apktool.yml to avoid compressing injected library");
+
+            // Rebuild APK using apktool
+            info!("→ Rebuilding APK with apktool...");
+            let rebuilt_apk_path = apktool_dist_apk(&decompiled_dir, &apktool_yml);
+            let mut cmd = apktool_command(target)?;
+            cmd.arg("b")
+                .arg(&decompiled_dir)
+                .arg("-o")
+                .arg(&rebuilt_apk_path);
+            let output =
+                tool_output_with_retry(&mut cmd, "apktool b", target.tool_retries()).await?;
 
-        if !output.status.success() {
-            anyhow::bail!(
-                "apktool build failed: {}",
-                String::from_utf8_lossy(&output.stderr)
-            );
-        }
-        info!("✓ APK rebuilt successfully with apktool");
+            if !output.status.success() {
+                anyhow::bail!(
+                    "apktool build failed: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+            info!("✓ APK rebuilt successfully with apktool");
+            rebuilt_apk_path
+        };
 
         // Run zipalign on the rebuilt APK
         info!("→ Aligning APK with zipalign...");
         let aligned_apk_path = temp_path.join(format!("{base_name}-{platform}-aligned.apk"));
 
-        let output = tokio::process::Command::new(find_sdk_binary("zipalign")?)
-            .arg("-v")
-            .arg("-p")
-            .arg("4")
-            .arg(&rebuilt_apk_path)
-            .arg(&aligned_apk_path)
-            .output()
-            .await?;
+        let output = zipalign(&rebuilt_apk_path, &aligned_apk_path).await?;
+        let emit_both = target.sign.is_some() && target.emit_both.unwrap_or(false);
         let rebuilt_apk_path = if output.status.success() {
             info!("✓ APK aligned successfully");
             aligned_apk_path
+        } else if target.sign.is_none() || emit_both {
+            // Nothing after us could align an APK meant to be signed elsewhere
+            anyhow::bail!(
+                "zipalign failed, refusing to produce an unaligned unsigned APK: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
         } else {
             warn!(
                 "zipalign failed: {}, proceeding with unaligned APK. Apk may not install with reason 'INSTALL_FAILED_INVALID_APK: Failed to extract native libraries' for some applications.",
@@ -705,61 +1368,59 @@ doNotCompress:
         };
 
         // Copy the rebuilt APK to output directory
-        let final_apk_name = format!("{base_name}-{platform}-injected.apk");
-        let final_apk_path = Path::new(output_dir).join(&final_apk_name);
-        std::fs::create_dir_all(output_dir)?;
+        let final_apk_name = if target.sign.is_some() {
+            format!("{base_name}-{platform}-injected.apk")
+        } else {
+            format!("{base_name}-{platform}-injected-unsigned.apk")
+        };
+        let final_apk_path = output_dir.join(&final_apk_name);
+        std::fs::create_dir_all(&output_dir)?;
 
         // Sign the APK if signing configuration is provided
         if let Some(sign_config) = &target.sign {
             info!("→ Signing APK...");
             let signed_apk_path = temp_path.join(format!("{base_name}-{platform}-signed.apk"));
 
-            let mut command = Command::new(find_sdk_binary("apksigner")?);
-
-            let output = command
-                .arg("sign")
-                .arg("--ks")
-                .arg(&sign_config.keystore)
-                .arg("--ks-key-alias")
-                .arg(&sign_config.keystore_alias)
-                .arg("--ks-pass")
-                .arg(format!("pass:{}", sign_config.keystore_pass))
-                .arg("--key-pass")
-                .arg(format!(
-                    "pass:{}",
-                    sign_config
-                        .key_pass
-                        .as_deref()
-                        .unwrap_or(&sign_config.keystore_pass)
-                ))
-                .arg("--out")
-                .arg(&signed_apk_path)
-                .arg(&rebuilt_apk_path)
-                .output()
+            sign_apk(
+                sign_config,
+                &rebuilt_apk_path,
+                &signed_apk_path,
+                target.tool_retries(),
+            )
+            .await?;
+            self.check_signer_kept(&source_apk_path, &signed_apk_path)
                 .await?;
 
-            if !output.status.success() {
-                anyhow::bail!(
-                    "apksigner failed: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                );
-            }
-
             // Copy signed APK to final location
-            fs::copy(&signed_apk_path, &final_apk_path).await?;
+            let unsigned_final_apk_path =
+                output_dir.join(format!("{base_name}-{platform}-injected-unsigned.apk"));
+            emit_signed_apk(
+                &signed_apk_path,
+                &final_apk_path,
+                emit_both.then_some((
+                    rebuilt_apk_path.as_path(),
+                    unsigned_final_apk_path.as_path(),
+                )),
+            )
+            .await?;
             info!("✓ APK signed successfully");
         } else {
-            fs::copy(&rebuilt_apk_path, &final_apk_path).await?;
+            copy_atomic(&rebuilt_apk_path, &final_apk_path).await?;
+            warn!("APK is unsigned; sign before installing (e.g. with apksigner, without re-aligning)");
         }
 
         info!(
             "✓ Successfully built inject APK: {}",
             final_apk_path.display()
         );
-        Ok(final_apk_path.to_string_lossy().to_string())
+        Ok(final_apk_path)
     }
 
-    async fn build_zygisk(&mut self, target_name: &str, target: &ResolvedTarget) -> Result<String> {
+    async fn build_zygisk(
+        &mut self,
+        target_name: &str,
+        target: &ResolvedTarget,
+    ) -> Result<PathBuf> {
         let base_name = target.target_base_name.as_deref().unwrap_or(target_name);
         info!("→ Building Zygisk target: {target_name} (base name: {base_name})");
 
@@ -792,39 +1453,18 @@ doNotCompress:
             anyhow::bail!("Zygisk target only supports Android platform");
         }
 
-        let output_dir = target.output_dir.as_deref().unwrap_or("./fripack");
+        let output_dir = target.artifact_dir(target_name, &platform.to_string());
         let binary_data = self.generate_binary(target).await?;
+        // Downloaded before anything is written to the output directory
+        let abi = platform.android_abi()?;
+        let loader = self.downloader.download_zygisk_loader(&abi).await?;
 
-        // Create the final zip file directly
-        let zip_filename = format!("{}-zygisk-{}.zip", base_name, platform);
-        let zip_path = Path::new(output_dir).join(&zip_filename);
-        std::fs::create_dir_all(output_dir)?;
-
-        // Create zip file
-        info!("→ Creating zygisk module zip: {}", zip_path.display());
-        let zip_file = std::fs::File::create(&zip_path)?;
-        let mut zip = zip::ZipWriter::new(zip_file);
-        let options = zip::write::FileOptions::default()
-            .compression_method(zip::CompressionMethod::Deflated)
-            .unix_permissions(0o755);
-
-        use std::io::Write;
-
-        // Add fripack/inject.so
-        zip.start_file("fripack/inject.so", options)?;
-        zip.write_all(&binary_data)?;
-
-        // Add fripack/scope
         let scope_content = scope
             .split(';')
             .map(|s| s.trim())
             .filter(|s| !s.is_empty())
             .collect::<Vec<_>>()
             .join("\n");
-        zip.start_file("fripack/scope", options)?;
-        zip.write_all(scope_content.as_bytes())?;
-
-        // Add module.prop
         let module_prop_content = format!(
             "id={}\nname={}\nversion={}\nversionCode={}\nauthor={}\ndescription={}\n",
             id,
@@ -837,45 +1477,55 @@ doNotCompress:
                 .as_deref()
                 .unwrap_or("A Zygisk module created by FriPack")
         );
-        zip.start_file("module.prop", options)?;
-        zip.write_all(module_prop_content.as_bytes())?;
-
-        zip.start_file(&format!("zygisk/{}.so", platform.android_abi()?), options)?;
-        let downloaded = self
-            .downloader
-            .download_zygisk_loader(&platform.android_abi()?)
-            .await?;
 
-        zip.write_all(&downloaded)?;
+        let zip_filename = format!("{}-zygisk-{}.zip", base_name, platform);
+        let zip_path = output_dir.join(&zip_filename);
+        std::fs::create_dir_all(&output_dir)?;
 
-        zip.finish()?;
+        info!("→ Creating zygisk module zip: {}", zip_path.display());
+        write_zip_atomic(
+            &zip_path,
+            &[
+                ("fripack/inject.so", &binary_data),
+                ("fripack/scope", scope_content.as_bytes()),
+                ("module.prop", module_prop_content.as_bytes()),
+                (&format!("zygisk/{abi}.so"), &loader),
+            ],
+        )?;
 
         info!("✓ Successfully built zygisk module: {}", zip_path.display());
 
-        Ok(zip_path.to_string_lossy().to_string())
+        Ok(zip_path)
     }
 
-    async fn extract_apk_from_device(&self, package_name: &str) -> Result<PathBuf> {
-        let cache_dir = Path::new("./fripack_cache").join("apks");
-        std::fs::create_dir_all(&cache_dir)?;
-
-        let cached_apk_path = cache_dir.join(format!("{}.apk", package_name.replace(":", "_")));
-
-        // Check if APK is already cached
+    pub async fn extract_apk_from_device(
+        &self,
+        package_name: &str,
+        prefer_split: PreferSplit,
+        abi: &str,
+        device: Option<&str>,
+    ) -> Result<PathBuf> {
+        let cache_dir = Path::new(APK_CACHE_DIR);
+        std::fs::create_dir_all(cache_dir)?;
+
+        let package_name_part = package_name.replace(":", "_");
+        let base_apk_path = cache_dir.join(format!("{package_name_part}.apk"));
+        let abi_split_path = cache_dir.join(format!("{package_name_part}-{}", abi_split_name(abi)));
+
+        // Check if APK is already cached. Whether an `auto` package is split is
+        // only known to the device, unless its split was pulled before.
+        let cached_apk_path = match prefer_split {
+            PreferSplit::Base => &base_apk_path,
+            PreferSplit::Abi | PreferSplit::Auto => &abi_split_path,
+        };
         if cached_apk_path.exists() {
             info!("→ Using cached APK: {}", cached_apk_path.display());
-            return Ok(cached_apk_path);
+            return Ok(cached_apk_path.clone());
         }
 
         // Get APK path from device
         info!("→ Getting APK path from device...");
-        let output = tokio::process::Command::new(find_sdk_binary("adb")?)
-            .arg("shell")
-            .arg("pm")
-            .arg("path")
-            .arg(package_name)
-            .output()
-            .await?;
+        let output = adb_output_with_retry(device, &["shell", "pm", "path", package_name]).await?;
 
         if !output.status.success() {
             anyhow::bail!(
@@ -885,36 +1535,169 @@ doNotCompress:
         }
 
         let stdout = String::from_utf8_lossy(&output.stdout);
-        let apk_path_line = stdout
-            .lines()
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("No APK path returned"))?;
-
-        let device_apk_path = apk_path_line
-            .strip_prefix("package:")
-            .ok_or_else(|| anyhow::anyhow!("Invalid APK path format"))?;
-
-        // Pull APK from device
-        info!("→ Pulling APK from device: {}", device_apk_path);
-        let output = tokio::process::Command::new(which::which("adb")?)
-            .arg("pull")
-            .arg(device_apk_path)
-            .arg(&cached_apk_path)
-            .output()
-            .await?;
+        let device_apk_paths = select_device_apk(&stdout, prefer_split, abi)?;
+        let (device_apk_path, splits) = device_apk_paths.split_last().unwrap();
+
+        // Only the split's contents tell whether it holds the ABI's libraries
+        for split in splits {
+            let tmp_split_path = tmp_path_for(&abi_split_path);
+            pull_device_apk(device, split, &tmp_split_path).await?;
+            let has_abi_libs = apk_has_abi_libs(&tmp_split_path, abi);
+            if !matches!(has_abi_libs, Ok(true)) {
+                fs::remove_file(&tmp_split_path).await?;
+                has_abi_libs.with_context(|| format!("Failed to read split APK {split}"))?;
+                continue;
+            }
+            fs::rename(&tmp_split_path, &abi_split_path).await?;
+            info!("✓ APK extracted and cached: {}", abi_split_path.display());
+            return Ok(abi_split_path);
+        }
 
-        if !output.status.success() {
-            anyhow::bail!(
-                "Failed to pull APK from device: {}",
-                String::from_utf8_lossy(&output.stderr)
-            );
+        let cached_apk_path = if prefer_split == PreferSplit::Abi {
+            abi_split_path
+        } else {
+            base_apk_path
+        };
+        if cached_apk_path.exists() {
+            info!("→ Using cached APK: {}", cached_apk_path.display());
+            return Ok(cached_apk_path);
         }
 
+        pull_device_apk(device, device_apk_path, &cached_apk_path).await?;
         info!("✓ APK extracted and cached: {}", cached_apk_path.display());
         Ok(cached_apk_path)
     }
 
-    async fn find_target_library(
+    /// Adds a new dex with an `Application` subclass whose static initializer loads
+    /// `inject_lib_name`, and declares it as the app's Application in the manifest.
+    /// The stub extends the app's original Application (if any) so it keeps working.
+    /// With `as_dex`, the dex is generated directly instead of assembled from smali.
+    async fn add_application_stub(
+        &self,
+        decompiled_dir: &Path,
+        inject_lib_name: &str,
+        as_dex: bool,
+    ) -> Result<()> {
+        let manifest_path = decompiled_dir.join("AndroidManifest.xml");
+        let manifest = fs::read_to_string(&manifest_path)
+            .await
+            .context("Failed to read decoded AndroidManifest.xml")?;
+
+        let stub_class = format!(
+            "x{}.A{}",
+            generate_random_string(6).to_lowercase(),
+            generate_random_string(8)
+        );
+        let (manifest, original_class) = set_application_class(&manifest, &stub_class)?;
+        fs::write(&manifest_path, manifest).await?;
+        info!(
+            "→ Declared stub Application {stub_class} (was: {})",
+            original_class
+                .as_deref()
+                .unwrap_or("android.app.Application")
+        );
+
+        let super_class = original_class.unwrap_or_else(|| "android.app.Application".to_string());
+        let lib = inject_lib_name
+            .trim_start_matches("lib")
+            .trim_end_matches(".so");
+
+        // apktool -s keeps the original dex files, so put the stub in a new one
+        let mut dex_count = 0;
+        let mut entries = fs::read_dir(decompiled_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if file_name.starts_with("classes") && file_name.ends_with(".dex") {
+                dex_count += 1;
+            }
+        }
+
+        if as_dex {
+            let dex_name = if dex_count == 0 {
+                "classes.dex".to_string()
+            } else {
+                format!("classes{}.dex", dex_count + 1)
+            };
+            let dex_path = decompiled_dir.join(dex_name);
+            fs::write(
+                &dex_path,
+                dex::library_loader_dex(&stub_class, &super_class, lib)?,
+            )
+            .await?;
+            info!("→ Created stub Application dex: {}", dex_path.display());
+            return Ok(());
+        }
+
+        let super_class = super_class.replace('.', "/");
+        let smali_content = format!(
+            r#".class public L{class};
+.super L{super_class};
+
+.method static constructor <clinit>()V
+    .locals 1
+    const-string v0, "{lib}"
+    invoke-static {{v0}}, Ljava/lang/System;->loadLibrary(Ljava/lang/String;)V
+    return-void
+.end method
+
+.method public constructor <init>()V
+    .locals 0
+    invoke-direct {{p0}}, L{super_class};-><init>()V
+    return-void
+.end method
+"#,
+            class = stub_class.replace('.', "/"),
+        );
+
+        let smali_root = if dex_count == 0 {
+            "smali".to_string()
+        } else {
+            format!("smali_classes{}", dex_count + 1)
+        };
+
+        let smali_path = decompiled_dir
+            .join(smali_root)
+            .join(format!("{}.smali", stub_class.replace('.', "/")));
+        fs::create_dir_all(smali_path.parent().unwrap()).await?;
+        fs::write(&smali_path, &smali_content).await?;
+        info!("→ Created stub Application smali: {}", smali_path.display());
+        smali::check(&smali_path, &smali_content, self.check_smali).await?;
+
+        Ok(())
+    }
+
+    /// Resolves every library of `targetLibs`, or the single one picked by
+    /// [`Self::find_target_library`].
+    async fn find_target_libraries(
+        &self,
+        lib_dir: &Path,
+        inject_config: &InjectApkConfig,
+    ) -> Result<Vec<PathBuf>> {
+        let target_lib_paths = match &inject_config.target_libs {
+            Some(target_libs) => {
+                let mut target_lib_paths = Vec::new();
+                for target_lib in target_libs {
+                    let target_lib_path = self
+                        .find_target_library(lib_dir, &Some(target_lib.clone()))
+                        .await?;
+                    if !target_lib_paths.contains(&target_lib_path) {
+                        target_lib_paths.push(target_lib_path);
+                    }
+                }
+                target_lib_paths
+            }
+            None => vec![
+                self.find_target_library(lib_dir, &inject_config.target_lib)
+                    .await?,
+            ],
+        };
+        for target_lib_path in &target_lib_paths {
+            info!("→ Selected target library: {}", target_lib_path.display());
+        }
+        Ok(target_lib_paths)
+    }
+
+    pub async fn find_target_library(
         &self,
         lib_dir: &Path,
         target_lib: &Option<String>,
@@ -976,6 +1759,657 @@ doNotCompress:
     }
 }
 
+/// File name of the config split holding the native libraries of `abi`, e.g.
+/// `split_config.arm64_v8a.apk`.
+fn abi_split_name(abi: &str) -> String {
+    format!("split_config.{}.apk", abi.replace('-', "_"))
+}
+
+/// The tool apktool is run with, `java` with `apktoolJar`.
+fn apktool_tool(target: &ResolvedTarget) -> &'static str {
+    match target.apktool_jar {
+        Some(_) => "java",
+        None => "apktool",
+    }
+}
+
+/// External tools building `target` runs, in the order they're used. Build
+/// hooks aren't included, as they're arbitrary shell commands.
+pub fn required_tools(target: &ResolvedTarget) -> Vec<String> {
+    let mut tools = Vec::new();
+    if let Some(bundle) = &target.bundle {
+        tools.push(
+            bundle
+                .command
+                .clone()
+                .unwrap_or_else(|| "frida-compile".to_string()),
+        );
+    }
+    let signs = target.sign.is_some();
+    match target.target_type.as_deref() {
+        Some("xposed") => {
+            tools.extend([apktool_tool(target), "zipalign"].map(String::from));
+            if signs {
+                tools.push("apksigner".to_string());
+            }
+        }
+        Some("inject-apk") => {
+            let Some(inject_config) = &target.inject_apk else {
+                return tools;
+            };
+            if inject_config.source_apk_path.is_none() {
+                tools.push("adb".to_string());
+            }
+            if !inject_config.repackages_as_zip() {
+                tools.push(apktool_tool(target).to_string());
+            }
+            tools.push("zipalign".to_string());
+            if signs {
+                tools.push("apksigner".to_string());
+            }
+        }
+        _ => {}
+    }
+    tools
+}
+
+/// File name Android's `System.loadLibrary` expects for `name`: `lib{name}.so`.
+fn android_library_name(name: &str) -> String {
+    let name = name.strip_prefix("lib").unwrap_or(name);
+    format!("lib{name}.so")
+}
+
+/// Picks the APKs to inject from the output of `pm path`, which lists `base.apk`
+/// and, for split-installed packages, one line per split. The last one is used
+/// unless, with `auto`, one of the splits before it holds `lib/{abi}/`.
+fn select_device_apk<'a>(
+    pm_path_output: &'a str,
+    prefer_split: PreferSplit,
+    abi: &str,
+) -> Result<Vec<&'a str>> {
+    let paths = pm_path_output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.strip_prefix("package:")
+                .ok_or_else(|| anyhow::anyhow!("Invalid APK path format: {line}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if paths.is_empty() {
+        anyhow::bail!("No APK path returned");
+    }
+
+    let base = *paths
+        .iter()
+        .find(|path| path.ends_with("/base.apk"))
+        .unwrap_or(&paths[0]);
+
+    match prefer_split {
+        PreferSplit::Base => Ok(vec![base]),
+        PreferSplit::Abi => {
+            let split_name = abi_split_name(abi);
+            let split = paths
+                .iter()
+                .find(|path| path.ends_with(&split_name))
+                .with_context(|| {
+                    format!(
+                        "Package has no {split_name} split (installed APKs: {})",
+                        paths.join(", ")
+                    )
+                })?;
+            Ok(vec![split])
+        }
+        PreferSplit::Auto => Ok(paths
+            .iter()
+            .copied()
+            .filter(|path| *path != base)
+            .chain([base])
+            .collect()),
+    }
+}
+
+/// Whether the APK at `path` has native libraries for `abi`.
+fn apk_has_abi_libs(path: &Path, abi: &str) -> Result<bool> {
+    let archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+    let lib_dir = format!("lib/{abi}/");
+    let has_abi_libs = archive.file_names().any(|name| name.starts_with(&lib_dir));
+    Ok(has_abi_libs)
+}
+
+/// Pulls `device_apk_path` from the device to `path`.
+async fn pull_device_apk(device: Option<&str>, device_apk_path: &str, path: &Path) -> Result<()> {
+    info!("→ Pulling APK from device: {}", device_apk_path);
+    let output =
+        adb_output_with_retry(device, &["pull", device_apk_path, &path.to_string_lossy()]).await?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to pull APK from device: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(())
+}
+
+/// The apktool of the target: `java {apktoolJavaArgs} -jar {apktoolJar}` with
+/// `apktoolJar`, otherwise the `apktool` wrapper on `PATH`.
+fn apktool_command(target: &ResolvedTarget) -> Result<Command> {
+    let Some(jar) = &target.apktool_jar else {
+        return Ok(Command::new(which("apktool")?));
+    };
+    let java = find_java()
+        .context("apktoolJar is set, but java was not found (install a JRE or set JAVA_HOME)")?;
+    let mut cmd = Command::new(java);
+    cmd.args(target.apktool_java_args.iter().flatten())
+        .arg("-jar")
+        .arg(jar);
+    Ok(cmd)
+}
+
+/// Where `apktool b` puts the APK of `project_dir` by default: `dist/` plus the
+/// `apkFileName` from its apktool.yml, which is the source APK's name for
+/// decompiled APKs.
+fn apktool_dist_apk(project_dir: &Path, apktool_yml: &serde_yaml::Value) -> PathBuf {
+    let apk_file_name = apktool_yml
+        .get("apkFileName")
+        .and_then(|v| v.as_str())
+        .and_then(|name| Path::new(name).file_name())
+        .unwrap_or("app-debug.apk".as_ref());
+    project_dir.join("dist").join(apk_file_name)
+}
+
+/// `java` of `JAVA_HOME`, or the one on `PATH`.
+pub fn find_java() -> Result<PathBuf> {
+    if let Some(java_home) = std::env::var_os("JAVA_HOME") {
+        let java =
+            Path::new(&java_home)
+                .join("bin")
+                .join(if cfg!(windows) { "java.exe" } else { "java" });
+        if java.is_file() {
+            return Ok(java);
+        }
+    }
+    Ok(which("java")?)
+}
+
+/// SHA-256 digests of the certificates `apk` is signed with, per `apksigner
+/// verify --print-certs`, or `None` if it has no valid signature.
+async fn apk_signer_digests(apk: &Path) -> Result<Option<Vec<String>>> {
+    let output = Command::new(find_sdk_binary("apksigner")?)
+        .arg("verify")
+        .arg("--print-certs")
+        .arg(apk)
+        .explain()
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let mut digests: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once("certificate SHA-256 digest:"))
+        .map(|(_, digest)| digest.trim().to_lowercase())
+        .collect();
+    if digests.is_empty() {
+        return Ok(None);
+    }
+    digests.sort();
+    digests.dedup();
+    Ok(Some(digests))
+}
+
+/// Page-aligns `input` into `output` (`zipalign -p 4`), keeping uncompressed
+/// native libraries loadable in place.
+async fn zipalign(input: &Path, output: &Path) -> Result<std::process::Output> {
+    Ok(Command::new(find_sdk_binary("zipalign")?)
+        .arg("-v")
+        .arg("-p")
+        .arg("4")
+        .arg(input)
+        .arg(output)
+        .explain()
+        .output()
+        .await?)
+}
+
+async fn file_sha256_hex(path: &Path) -> Result<String> {
+    Ok(sha256_hex(&fs::read(path).await?))
+}
+
+/// Resolves the `(minSdkVersion, targetSdkVersion)` of an Xposed module.
+fn xposed_sdk_versions(xposed_config: &XposedConfig) -> Result<(u32, u32)> {
+    let min_sdk = xposed_config.min_sdk.unwrap_or(DEFAULT_XPOSED_MIN_SDK);
+    let target_sdk = xposed_config
+        .target_sdk
+        .unwrap_or(DEFAULT_XPOSED_TARGET_SDK.max(min_sdk));
+    if target_sdk < min_sdk {
+        anyhow::bail!(
+            "xposed.targetSdk ({target_sdk}) must not be lower than xposed.minSdk ({min_sdk})"
+        );
+    }
+    if min_sdk < GADGET_MIN_SDK {
+        warn!(
+            "xposed.minSdk {min_sdk} is below the minimum API level of the Frida gadget ({GADGET_MIN_SDK}), the module may fail to load on older devices"
+        );
+    }
+    Ok((min_sdk, target_sdk))
+}
+
+/// Renders the AndroidManifest.xml of an Xposed module.
+fn render_xposed_manifest(
+    package_name: &str,
+    name: &str,
+    xposed_config: &XposedConfig,
+    (min_sdk, target_sdk): (u32, u32),
+) -> String {
+    let icon_attributes = if xposed_config.icon.is_some() {
+        r#"android:icon="@mipmap/ic_launcher" android:roundIcon="@mipmap/ic_launcher_round""#
+    } else {
+        ""
+    };
+    let xposed_description = xposed_config
+        .description
+        .as_deref()
+        .unwrap_or("Easy example which makes the status bar clock red and adds a smiley");
+    let xposed_scope = xposed_config
+        .scope
+        .as_deref()
+        .unwrap_or("com.example.a;com.example.b");
+
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8" standalone="no"?>
+<manifest xmlns:android="http://schemas.android.com/apk/res/android" android:compileSdkVersion="36" android:compileSdkVersionCodename="16" package="{package_name}" platformBuildVersionCode="36" platformBuildVersionName="16">
+    <uses-sdk android:minSdkVersion="{min_sdk}" android:targetSdkVersion="{target_sdk}"/>
+    <application android:debuggable="true" android:extractNativeLibs="true"
+                {icon_attributes} android:label="{name}">
+        <meta-data android:name="xposedmodule" android:value="true"/>
+        <meta-data android:name="xposeddescription" android:value="{xposed_description}"/>
+        <meta-data android:name="xposedminversion" android:value="53"/>
+        <meta-data android:name="xposedscope" android:value="{xposed_scope}"/>
+    </application>
+</manifest>"#
+    )
+}
+
+/// Paths apktool must store uncompressed in an Xposed module: our libraries, so
+/// they can be loaded straight from the APK.
+fn xposed_do_not_compress(
+    platforms: &[PlatformConfig],
+    native_lib_names: &[String],
+) -> Result<Vec<String>> {
+    let mut do_not_compress = vec!["resources.arsc".to_string(), "webp".to_string()];
+    for abi_platform in platforms {
+        let abi = abi_platform.android_abi()?;
+        do_not_compress.extend(
+            native_lib_names
+                .iter()
+                .map(|file_name| format!("lib/{abi}/{file_name}")),
+        );
+    }
+    Ok(do_not_compress)
+}
+
+/// Renders the apktool.yml of an Xposed module, keeping `do_not_compress` stored.
+fn render_xposed_apktool_yml(
+    (min_sdk, target_sdk): (u32, u32),
+    do_not_compress: &[String],
+) -> String {
+    let do_not_compress = do_not_compress
+        .iter()
+        .map(|path| format!("- {path}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        r#"apkFileName: app-debug.apk
+isFrameworkApk: false
+usesFramework:
+  ids:
+  - 1
+  tag: null
+sdkInfo:
+  minSdkVersion: {min_sdk}
+  targetSdkVersion: {target_sdk}
+packageInfo:
+  forcedPackageId: 127
+  renameManifestPackage: null
+versionInfo:
+  versionCode: 1
+  versionName: 1.0
+resourcesAreCompressed: false
+sharedLibrary: false
+sparseResources: true
+unknownFiles:
+doNotCompress:
+{do_not_compress}"#
+    )
+}
+
+/// Copies `signed_apk` to `final_apk`, and with `emitBoth` the `(unsigned_apk,
+/// unsigned_final_apk)` pair next to it.
+async fn emit_signed_apk(
+    signed_apk: &Path,
+    final_apk: &Path,
+    unsigned: Option<(&Path, &Path)>,
+) -> Result<()> {
+    copy_atomic(signed_apk, final_apk).await?;
+    if let Some((unsigned_apk, unsigned_final_apk)) = unsigned {
+        copy_atomic(unsigned_apk, unsigned_final_apk).await?;
+        log_emitted_apks(final_apk, unsigned_final_apk).await?;
+    }
+    Ok(())
+}
+
+/// Logs the signed and unsigned APKs written by `emitBoth`.
+async fn log_emitted_apks(signed: &Path, unsigned: &Path) -> Result<()> {
+    info!(
+        "✓ Signed APK: {} (sha256 {})",
+        signed.display(),
+        file_sha256_hex(signed).await?
+    );
+    info!(
+        "✓ Unsigned APK: {} (sha256 {})",
+        unsigned.display(),
+        file_sha256_hex(unsigned).await?
+    );
+    Ok(())
+}
+
+/// Reads the scripts of `embedScripts`, keyed by their names.
+async fn read_embed_scripts(target: &ResolvedTarget) -> Result<Option<BTreeMap<String, String>>> {
+    let Some(embed_scripts) = &target.embed_scripts else {
+        return Ok(None);
+    };
+    let mut scripts = BTreeMap::new();
+    for (name, path) in embed_scripts {
+        info!("→ Reading script {name}: {path}");
+        let data = fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read embedScripts.{name}: {path}"))?;
+        scripts.insert(name.clone(), String::from_utf8_lossy(&data).to_string());
+    }
+    Ok(Some(scripts))
+}
+
+/// Path of the temporary file an artifact is written to before being renamed
+/// into place. It lives in the same directory, so the rename is atomic.
+fn tmp_path_for(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
+
+/// Writes an artifact to a temporary file and renames it into place, so an
+/// interrupted build never leaves a truncated artifact behind.
+async fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp_path = tmp_path_for(path);
+    if let Err(e) = fs::write(&tmp_path, data).await {
+        let _ = fs::remove_file(&tmp_path).await;
+        return Err(e.into());
+    }
+    fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+/// Like [`write_atomic`], copying from `from`. Even if `from` is on another
+/// filesystem, only the copy to the temporary file crosses it.
+async fn copy_atomic(from: &Path, path: &Path) -> Result<()> {
+    let tmp_path = tmp_path_for(path);
+    if let Err(e) = fs::copy(from, &tmp_path).await {
+        let _ = fs::remove_file(&tmp_path).await;
+        return Err(e.into());
+    }
+    fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+/// Writes a zip of `entries` to a temporary file and renames it into place,
+/// like [`write_atomic`].
+fn write_zip_atomic(path: &Path, entries: &[(&str, &[u8])]) -> Result<()> {
+    use std::io::Write;
+
+    let tmp_path = tmp_path_for(path);
+    let written = (|| -> Result<()> {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&tmp_path)?);
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .unix_permissions(0o755);
+        for (name, data) in entries {
+            zip.start_file(*name, options)?;
+            zip.write_all(data)?;
+        }
+        zip.finish()?;
+        Ok(())
+    })();
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Moves `artifact` into the `outputStore` as `{sha256}.{ext}` and links it
+/// back to its name. Returns the path of the stored blob.
+async fn store_artifact(target: &ResolvedTarget, artifact: &Path) -> Result<PathBuf> {
+    let store_dir = Path::new(target.output_dir.as_deref().unwrap_or("./fripack")).join("store");
+    fs::create_dir_all(&store_dir).await?;
+    let mut blob_name = sha256_hex(&fs::read(artifact).await?);
+    if let Some(ext) = artifact.extension() {
+        blob_name.push('.');
+        blob_name.push_str(&ext.to_string_lossy());
+    }
+    let blob_path = store_dir.join(blob_name);
+
+    let same_filesystem = same_filesystem(&store_dir, artifact).await?;
+    if blob_path.exists() {
+        // Same content, already stored
+        fs::remove_file(artifact).await?;
+    } else if same_filesystem {
+        fs::rename(artifact, &blob_path).await?;
+    } else {
+        copy_atomic(artifact, &blob_path).await?;
+        fs::remove_file(artifact).await?;
+    }
+
+    if same_filesystem {
+        fs::hard_link(&blob_path, artifact)
+            .await
+            .with_context(|| format!("Failed to link {}", artifact.display()))?;
+    } else {
+        warn!(
+            "{} is on another filesystem than the output store, copying instead of hardlinking",
+            artifact.display()
+        );
+        copy_atomic(&blob_path, artifact).await?;
+    }
+    info!("✓ Stored artifact: {}", blob_path.display());
+    Ok(blob_path)
+}
+
+/// Whether `a` and `b` are on the same filesystem, so they can be hardlinked.
+#[cfg(unix)]
+async fn same_filesystem(a: &Path, b: &Path) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(fs::metadata(a).await?.dev() == fs::metadata(b).await?.dev())
+}
+
+/// Whether `a` and `b` are on the same filesystem, so they can be hardlinked.
+#[cfg(not(unix))]
+async fn same_filesystem(a: &Path, b: &Path) -> Result<bool> {
+    // No device ids here, compare the drives instead
+    let a = fs::canonicalize(a).await?;
+    let b = fs::canonicalize(b).await?;
+    Ok(a.components().next() == b.components().next())
+}
+
+/// stderr fragments of apktool/apksigner failures that usually pass on a rerun,
+/// such as file locks and races on apktool's framework or temp files
+const RETRYABLE_TOOL_ERRORS: &[&str] = &[
+    "brut.directory",
+    "filesystemexception",
+    "accessdeniedexception",
+    "being used by another process",
+    "text file busy",
+    "resource temporarily unavailable",
+];
+
+/// Runs an apktool/apksigner `command`, rerunning it up to `retries` times on
+/// failures that look transient. Each rerun gets a fresh Java temp dir. Other
+/// failures are returned right away for the caller to report.
+async fn tool_output_with_retry(
+    command: &mut Command,
+    name: &str,
+    retries: u32,
+) -> Result<std::process::Output> {
+    let mut attempt = 0;
+    let mut tmp_dirs = Vec::new();
+    loop {
+        let output = command.explain().output().await?;
+        if output.status.success() || attempt == retries {
+            return Ok(output);
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr).to_lowercase();
+        let Some(error) = RETRYABLE_TOOL_ERRORS
+            .iter()
+            .find(|error| stderr.contains(*error))
+        else {
+            return Ok(output);
+        };
+
+        attempt += 1;
+        warn!("{name} failed ({error}), retrying with a fresh temp dir ({attempt}/{retries})...");
+        let tmp_dir = tempfile::tempdir()?;
+        let mut java_options = std::env::var("JAVA_TOOL_OPTIONS").unwrap_or_default();
+        java_options.push_str(&format!(" -Djava.io.tmpdir={}", tmp_dir.path().display()));
+        command.env("JAVA_TOOL_OPTIONS", java_options.trim_start());
+        tmp_dirs.push(tmp_dir);
+    }
+}
+
+/// Signs `unsigned_apk` with apksigner into `signed_apk`.
+async fn sign_apk(
+    sign_config: &SignConfig,
+    unsigned_apk: &Path,
+    signed_apk: &Path,
+    retries: u32,
+) -> Result<()> {
+    let keystore = &sign_config.keystore;
+    let keystore_pass = &sign_config.keystore_password()?;
+    let key_pass = sign_config.key_pass.as_deref().unwrap_or(keystore_pass);
+    let keystore_path = if PathBuf::from(keystore).is_absolute() {
+        PathBuf::from(keystore)
+    } else {
+        std::env::current_dir()?.join(keystore)
+    };
+
+    let mut command = Command::new(find_sdk_binary("apksigner")?);
+    command
+        .arg("sign")
+        .arg("--ks")
+        .arg(&keystore_path)
+        .arg("--ks-key-alias")
+        .arg(&sign_config.keystore_alias)
+        .arg("--ks-pass")
+        .arg(format!("pass:{keystore_pass}"))
+        .arg("--key-pass")
+        .arg(format!("pass:{key_pass}"));
+
+    // Key rotation
+    if let Some(lineage) = &sign_config.signing_lineage {
+        if !Path::new(lineage).is_file() {
+            anyhow::bail!("Signing lineage file not found: {lineage}");
+        }
+        command.arg("--lineage").arg(lineage);
+    }
+    if let Some(min_sdk) = sign_config.rotation_min_sdk_version {
+        command
+            .arg("--rotation-min-sdk-version")
+            .arg(min_sdk.to_string());
+    }
+
+    command.arg("--out").arg(signed_apk).arg(unsigned_apk);
+    let output = tool_output_with_retry(&mut command, "apksigner", retries).await?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "apksigner failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(())
+}
+
+/// Points `<application android:name>` of a decoded manifest at `class`, returning
+/// the patched manifest and the fully qualified Application class it replaced.
+fn set_application_class(manifest: &str, class: &str) -> Result<(String, Option<String>)> {
+    let package = attribute_value(manifest, "<manifest", "package")
+        .context("Failed to find the package name in AndroidManifest.xml")?;
+
+    let tag_start = manifest
+        .find("<application")
+        .context("Failed to find <application> in AndroidManifest.xml")?;
+    let tag_end = tag_start
+        + manifest[tag_start..]
+            .find('>')
+            .context("Malformed <application> tag in AndroidManifest.xml")?;
+    let tag = &manifest[tag_start..tag_end];
+
+    let (new_tag, original) = match tag.find("android:name=\"") {
+        Some(pos) => {
+            let value_start = pos + "android:name=\"".len();
+            let value_end = value_start
+                + tag[value_start..]
+                    .find('"')
+                    .context("Malformed android:name in AndroidManifest.xml")?;
+            let name = &tag[value_start..value_end];
+            let original = if name.starts_with('.') {
+                format!("{package}{name}")
+            } else if !name.contains('.') {
+                format!("{package}.{name}")
+            } else {
+                name.to_string()
+            };
+            (
+                format!("{}{}{}", &tag[..value_start], class, &tag[value_end..]),
+                Some(original),
+            )
+        }
+        None => (
+            tag.replacen(
+                "<application",
+                &format!("<application android:name=\"{class}\""),
+                1,
+            ),
+            None,
+        ),
+    };
+
+    Ok((
+        format!(
+            "{}{}{}",
+            &manifest[..tag_start],
+            new_tag,
+            &manifest[tag_end..]
+        ),
+        original,
+    ))
+}
+
+/// Returns the value of `attribute` on the first tag starting with `tag`.
+fn attribute_value(xml: &str, tag: &str, attribute: &str) -> Option<String> {
+    let tag_start = xml.find(tag)?;
+    let tag_text = &xml[tag_start..tag_start + xml[tag_start..].find('>')?];
+    let needle = format!(" {attribute}=\"");
+    let value_start = tag_text.find(&needle)? + needle.len();
+    let value_end = value_start + tag_text[value_start..].find('"')?;
+    Some(tag_text[value_start..value_end].to_string())
+}
+
 fn generate_random_string(len: usize) -> String {
     rand::thread_rng()
         .sample_iter(&rand::distributions::Alphanumeric)
@@ -983,3 +2417,459 @@ fn generate_random_string(len: usize) -> String {
         .map(char::from)
         .collect()
 }
+
+/// Quotes a path for the shell hooks are run with.
+pub fn shell_quote(path: &str) -> String {
+    if cfg!(target_os = "windows") {
+        format!("\"{path}\"")
+    } else {
+        format!("'{}'", path.replace('\'', r"'\''"))
+    }
+}
+
+/// Adds a DT_NEEDED entry for `lib_name` to each library in `paths`. The
+/// rewrites are CPU-bound, so they run on blocking threads, at most one per CPU.
+async fn inject_needed_libraries(paths: &[PathBuf], lib_name: &str) -> Result<()> {
+    let parallelism = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let permits = std::sync::Arc::new(tokio::sync::Semaphore::new(parallelism));
+    let mut injections = tokio::task::JoinSet::new();
+    for path in paths {
+        let permit = permits.clone().acquire_owned().await?;
+        let (path, lib_name) = (path.clone(), lib_name.to_string());
+        injections.spawn_blocking(move || {
+            let _permit = permit;
+            inject_needed_library(&path, &lib_name)
+                .with_context(|| format!("Failed to inject into {}", path.display()))
+        });
+    }
+    while let Some(result) = injections.join_next().await {
+        result??;
+    }
+    Ok(())
+}
+
+/// Adds a DT_NEEDED entry for `lib_name` to the library at `path` and checks
+/// that the rewrite kept it.
+fn inject_needed_library(path: &Path, lib_name: &str) -> Result<()> {
+    add_needed_library_to_file(path, lib_name)?;
+
+    // Make sure the rewrite actually produced the DT_NEEDED entry
+    let needed = needed_libraries(&std::fs::read(path)?)?;
+    if !needed.iter().any(|needed| needed == lib_name) {
+        anyhow::bail!(
+            "DT_NEEDED injection failed: {} not found in {}",
+            lib_name,
+            path.display()
+        );
+    }
+    info!(
+        "✓ Verified DT_NEEDED entry: {} in {}",
+        lib_name,
+        path.display()
+    );
+    Ok(())
+}
+
+/// Copies the directory tree `source` to `destination`.
+async fn copy_dir(source: &Path, destination: &Path) -> Result<()> {
+    let source = source.to_path_buf();
+    let destination = destination.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut dirs = vec![(source, destination)];
+        while let Some((source, destination)) = dirs.pop() {
+            std::fs::create_dir_all(&destination)?;
+            for entry in std::fs::read_dir(&source)? {
+                let entry = entry?;
+                let target = destination.join(entry.file_name());
+                if entry.file_type()?.is_dir() {
+                    dirs.push((entry.path(), target));
+                } else {
+                    std::fs::copy(entry.path(), target)?;
+                }
+            }
+        }
+        Ok(())
+    })
+    .await?
+}
+
+/// Extracts the `.so` files directly under `prefix` (e.g. `lib/arm64-v8a/`) of an APK.
+pub fn extract_apk_libraries(apk: &Path, prefix: &str, output_dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(output_dir)?;
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(apk)?)?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let Some(name) = file.name().strip_prefix(prefix) else {
+            continue;
+        };
+        if name.contains('/') || !name.ends_with(".so") {
+            continue;
+        }
+        let mut output = std::fs::File::create(output_dir.join(name))?;
+        std::io::copy(&mut file, &mut output)?;
+    }
+    Ok(())
+}
+
+/// Copies an APK entry by entry, replacing `replaced` (name, data) with the
+/// same compression and appending `added` uncompressed. Other entries are
+/// copied without recompression; v1 signature files are dropped since they
+/// would no longer verify.
+fn repackage_apk(
+    source: &Path,
+    output: &Path,
+    replaced: &[(String, Vec<u8>)],
+    added: (&str, &[u8]),
+) -> Result<()> {
+    use std::io::Write;
+
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(source)?)?;
+    let mut writer = zip::ZipWriter::new(std::fs::File::create(output)?);
+    let mut replaced_found = vec![false; replaced.len()];
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)?;
+        let name = file.name().to_string();
+        if is_v1_signature_file(&name) {
+            continue;
+        }
+        if let Some(index) = replaced.iter().position(|(entry, _)| *entry == name) {
+            let options = zip::write::FileOptions::default()
+                .compression_method(file.compression())
+                .last_modified_time(file.last_modified());
+            drop(file);
+            writer.start_file(name, options)?;
+            writer.write_all(&replaced[index].1)?;
+            replaced_found[index] = true;
+        } else {
+            writer.raw_copy_file(file)?;
+        }
+    }
+    if let Some(index) = replaced_found.iter().position(|found| !found) {
+        anyhow::bail!("{} not found in {}", replaced[index].0, source.display());
+    }
+
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    writer.start_file(added.0, options)?;
+    writer.write_all(added.1)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Rewrites an APK with its entries sorted by name, zeroed timestamps and fixed
+/// permissions, and every entry recompressed, so equivalent builds give
+/// byte-identical files. Signatures don't survive it: v1 signature files are
+/// dropped and the v2+ signing block isn't copied. Returns the number of
+/// entries written.
+pub fn canonicalize_apk(source: &Path, output: &Path) -> Result<usize> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(source)?)
+        .with_context(|| format!("Failed to open APK: {}", source.display()))?;
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| !is_v1_signature_file(name))
+        .map(str::to_string)
+        .collect();
+    names.sort();
+
+    // Written next to the output, so overwriting the source is safe
+    let output_dir = output
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let temp = tempfile::NamedTempFile::new_in(output_dir)?;
+    let mut writer = zip::ZipWriter::new(temp.reopen()?);
+    for name in &names {
+        let mut file = archive.by_name(name)?;
+        // Stored entries (resources.arsc, page-aligned .so) must stay stored
+        let compression = match file.compression() {
+            zip::CompressionMethod::Stored => zip::CompressionMethod::Stored,
+            _ => zip::CompressionMethod::Deflated,
+        };
+        let options = zip::write::FileOptions::default()
+            .compression_method(compression)
+            .last_modified_time(zip::DateTime::default())
+            .unix_permissions(0o644);
+        if file.is_dir() {
+            writer.add_directory(name, options.unix_permissions(0o755))?;
+            continue;
+        }
+        writer.start_file(name, options)?;
+        std::io::copy(&mut file, &mut writer)?;
+    }
+    writer.finish()?;
+    temp.persist(output)?;
+    Ok(names.len())
+}
+
+fn is_v1_signature_file(name: &str) -> bool {
+    let Some(name) = name.strip_prefix("META-INF/") else {
+        return false;
+    };
+    name == "MANIFEST.MF"
+        || [".SF", ".RSA", ".DSA", ".EC"]
+            .iter()
+            .any(|ext| name.to_ascii_uppercase().ends_with(ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VANILLA_SO: &[u8] = include_bytes!("../tests/fixtures/libvanilla.so");
+    const PLACEHOLDER_SO: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/libplaceholder.so"
+    );
+
+    /// A target built from the placeholder fixture, embedding `entry`.
+    fn fixture_target(target_type: &str, platforms: &[&str], entry: &Path) -> ResolvedTarget {
+        let platforms: Vec<PlatformConfig> = platforms
+            .iter()
+            .map(|platform| PlatformConfig::from_str(platform.to_string()).unwrap())
+            .collect();
+        ResolvedTarget {
+            target_type: Some(target_type.to_string()),
+            platform: platforms.first().cloned(),
+            platforms,
+            frida_version: Some("17.5.1".to_string()),
+            entry: Some(entry.to_string_lossy().to_string()),
+            override_prebuild_file: Some(PLACEHOLDER_SO.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn inject_needed_library_adds_dt_needed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("libtarget.so");
+        std::fs::write(&path, VANILLA_SO).unwrap();
+
+        inject_needed_library(&path, "libpayload.so").unwrap();
+
+        let needed = needed_libraries(&std::fs::read(&path).unwrap()).unwrap();
+        assert!(
+            needed.iter().any(|lib| lib == "libpayload.so"),
+            "{needed:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn inject_needed_libraries_rewrites_every_library() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<_> = (0..4)
+            .map(|i| {
+                let path = dir.path().join(format!("libtarget{i}.so"));
+                let fixture = if i % 2 == 0 {
+                    VANILLA_SO.to_vec()
+                } else {
+                    std::fs::read(PLACEHOLDER_SO).unwrap()
+                };
+                std::fs::write(&path, fixture).unwrap();
+                path
+            })
+            .collect();
+
+        inject_needed_libraries(&paths, "libpayload.so")
+            .await
+            .unwrap();
+
+        for path in &paths {
+            let needed = needed_libraries(&std::fs::read(path).unwrap()).unwrap();
+            assert!(
+                needed.iter().any(|lib| lib == "libpayload.so"),
+                "{}: {needed:?}",
+                path.display()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn inject_needed_libraries_names_the_failing_library() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("libbroken.so");
+        std::fs::write(&path, b"not an ELF").unwrap();
+
+        let err = inject_needed_libraries(std::slice::from_ref(&path), "libpayload.so")
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains(&path.display().to_string()),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn fat_xposed_module_has_a_lib_dir_per_abi() {
+        let dir = tempfile::tempdir().unwrap();
+        let entry = dir.path().join("main.js");
+        std::fs::write(&entry, "console.log('fixture');").unwrap();
+        let target = fixture_target("xposed", &["android-arm64", "android-arm32"], &entry);
+        let apk_dir = dir.path().join("apk");
+
+        Builder::new()
+            .write_xposed_libs(&target, &apk_dir, "libgadget.so", &[], &[])
+            .await
+            .unwrap();
+
+        for abi in ["arm64-v8a", "armeabi-v7a"] {
+            let lib = apk_dir.join("lib").join(abi).join("libgadget.so");
+            assert!(lib.is_file(), "missing {}", lib.display());
+            crate::binary::read_embedded_data(&std::fs::read(&lib).unwrap()).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn shared_target_returns_an_artifact_per_platform() {
+        let dir = tempfile::tempdir().unwrap();
+        let entry = dir.path().join("main.js");
+        std::fs::write(&entry, "console.log('fixture');").unwrap();
+        let mut target = fixture_target("shared", &["linux-x86_64", "android-x86_64"], &entry);
+        target.output_dir = Some(dir.path().join("out").to_string_lossy().to_string());
+
+        let artifacts = Builder::new().build_target("agent", &target).await.unwrap();
+
+        assert_eq!(artifacts.len(), 2, "{artifacts:?}");
+        assert_ne!(artifacts[0], artifacts[1]);
+        for artifact in &artifacts {
+            assert!(artifact.is_file(), "missing {}", artifact.display());
+        }
+    }
+
+    #[tokio::test]
+    async fn emit_both_writes_the_signed_and_unsigned_apks() {
+        let dir = tempfile::tempdir().unwrap();
+        let signed = dir.path().join("app-signed.apk");
+        let unsigned = dir.path().join("app-aligned.apk");
+        std::fs::write(&signed, b"signed").unwrap();
+        std::fs::write(&unsigned, b"unsigned").unwrap();
+
+        let output_dir = dir.path().join("out");
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let final_apk = output_dir.join("app-android-arm64.apk");
+        let unsigned_final_apk = output_dir.join("app-android-arm64-unsigned.apk");
+        emit_signed_apk(
+            &signed,
+            &final_apk,
+            Some((unsigned.as_path(), unsigned_final_apk.as_path())),
+        )
+        .await
+        .unwrap();
+
+        let final_data = std::fs::read(&final_apk).unwrap();
+        let unsigned_final_data = std::fs::read(&unsigned_final_apk).unwrap();
+        assert_eq!(final_data, b"signed");
+        assert_eq!(unsigned_final_data, b"unsigned");
+        assert_ne!(final_data, unsigned_final_data);
+    }
+
+    #[test]
+    fn xposed_min_sdk_goes_into_the_manifest_and_apktool_yml() {
+        let xposed_config: XposedConfig =
+            serde_json::from_value(serde_json::json!({ "minSdk": 29 })).unwrap();
+        let sdk_versions = xposed_sdk_versions(&xposed_config).unwrap();
+        assert_eq!(sdk_versions, (29, 29));
+
+        let manifest =
+            render_xposed_manifest("com.example.module", "Module", &xposed_config, sdk_versions);
+        assert!(manifest.contains(r#"android:minSdkVersion="29" android:targetSdkVersion="29""#));
+
+        let apktool_yml: serde_yaml::Value =
+            serde_yaml::from_str(&render_xposed_apktool_yml(sdk_versions, &[])).unwrap();
+        assert_eq!(apktool_yml["sdkInfo"]["minSdkVersion"].as_u64(), Some(29));
+        assert_eq!(
+            apktool_yml["sdkInfo"]["targetSdkVersion"].as_u64(),
+            Some(29)
+        );
+    }
+
+    #[test]
+    fn xposed_target_sdk_must_not_be_below_min_sdk() {
+        let xposed_config: XposedConfig =
+            serde_json::from_value(serde_json::json!({ "minSdk": 29, "targetSdk": 28 })).unwrap();
+        assert!(xposed_sdk_versions(&xposed_config).is_err());
+    }
+
+    const PM_PATH_OUTPUT: &str = "package:/data/app/~~q1==/com.example-w2==/base.apk
+package:/data/app/~~q1==/com.example-w2==/split_config.arm64_v8a.apk
+package:/data/app/~~q1==/com.example-w2==/split_config.xxhdpi.apk
+";
+
+    #[test]
+    fn selects_the_device_apk_from_pm_path() {
+        let base = "/data/app/~~q1==/com.example-w2==/base.apk";
+        let arm64_split = "/data/app/~~q1==/com.example-w2==/split_config.arm64_v8a.apk";
+        let density_split = "/data/app/~~q1==/com.example-w2==/split_config.xxhdpi.apk";
+
+        let select = |prefer_split, abi| select_device_apk(PM_PATH_OUTPUT, prefer_split, abi);
+        assert_eq!(select(PreferSplit::Base, "arm64-v8a").unwrap(), [base]);
+        assert_eq!(
+            select(PreferSplit::Abi, "arm64-v8a").unwrap(),
+            [arm64_split]
+        );
+        assert!(select(PreferSplit::Abi, "x86_64").is_err());
+        // Every split is a candidate, whatever it's named, before base.apk
+        assert_eq!(
+            select(PreferSplit::Auto, "x86_64").unwrap(),
+            [arm64_split, density_split, base]
+        );
+
+        let unsplit = "package:/data/app/com.example/base.apk\n";
+        assert_eq!(
+            select_device_apk(unsplit, PreferSplit::Auto, "arm64-v8a").unwrap(),
+            ["/data/app/com.example/base.apk"]
+        );
+        assert!(select_device_apk("", PreferSplit::Base, "arm64-v8a").is_err());
+        assert!(select_device_apk("/data/app/base.apk", PreferSplit::Base, "arm64-v8a").is_err());
+    }
+
+    #[test]
+    fn finds_the_abi_libs_in_a_split() {
+        let dir = tempfile::tempdir().unwrap();
+        let split = dir.path().join("split_config.apk");
+        write_zip_atomic(
+            &split,
+            &[
+                ("AndroidManifest.xml", b"manifest"),
+                ("lib/arm64-v8a/libapp.so", b"lib"),
+            ],
+        )
+        .unwrap();
+
+        assert!(apk_has_abi_libs(&split, "arm64-v8a").unwrap());
+        assert!(!apk_has_abi_libs(&split, "armeabi-v7a").unwrap());
+    }
+
+    #[test]
+    fn xposed_libraries_are_stored_uncompressed() {
+        let platforms = [
+            PlatformConfig::from_str("android-arm64".to_string()).unwrap(),
+            PlatformConfig::from_str("android-arm32".to_string()).unwrap(),
+        ];
+        let native_lib_names = ["libgadget.so".to_string(), "libextra.so".to_string()];
+        let do_not_compress = xposed_do_not_compress(&platforms, &native_lib_names).unwrap();
+
+        let apktool_yml: serde_yaml::Value =
+            serde_yaml::from_str(&render_xposed_apktool_yml((24, 26), &do_not_compress)).unwrap();
+        let stored: Vec<_> = apktool_yml["doNotCompress"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|path| path.as_str().unwrap())
+            .collect();
+        for path in [
+            "lib/arm64-v8a/libgadget.so",
+            "lib/arm64-v8a/libextra.so",
+            "lib/armeabi-v7a/libgadget.so",
+            "lib/armeabi-v7a/libextra.so",
+            "resources.arsc",
+        ] {
+            assert!(stored.contains(&path), "{path} not in {stored:?}");
+        }
+    }
+
+    #[test]
+    fn android_library_names_have_the_lib_prefix_once() {
+        assert_eq!(android_library_name("myhook"), "libmyhook.so");
+        assert_eq!(android_library_name("libmyhook"), "libmyhook.so");
+    }
+}
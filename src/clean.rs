@@ -0,0 +1,74 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::collections::BTreeSet;
+use std::path::Path;
+use tokio::fs;
+
+use crate::config::ResolvedConfig;
+use crate::downloader::APK_CACHE_DIR;
+
+/// Removes the output directory of every target and, with `apk_cache`, the APKs
+/// pulled from devices. Relative paths are resolved against `project_dir`, and
+/// nothing is removed if any of the directories is outside of it.
+pub async fn clean(
+    config: &ResolvedConfig,
+    project_dir: &Path,
+    apk_cache: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let project_dir = project_dir
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve {}", project_dir.display()))?;
+
+    let mut dirs = BTreeSet::new();
+    for target_config in config.targets.values() {
+        if target_config.target_type.is_some() {
+            let output_dir = target_config.output_dir.as_deref().unwrap_or("./fripack");
+            dirs.insert(project_dir.join(output_dir));
+        }
+    }
+    if apk_cache {
+        dirs.insert(project_dir.join(APK_CACHE_DIR));
+    }
+
+    let mut existing = Vec::new();
+    for dir in dirs {
+        if !dir.exists() {
+            continue;
+        }
+        // Resolves `..` and symlinks, so they can't lead out of the project
+        let resolved = dir
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", dir.display()))?;
+        if resolved == project_dir || !resolved.starts_with(&project_dir) {
+            anyhow::bail!(
+                "Refusing to remove {}: it's not inside the project directory {}",
+                resolved.display(),
+                project_dir.display()
+            );
+        }
+        existing.push(resolved);
+    }
+    existing.sort();
+    // A directory inside another one is removed along with it
+    existing.dedup_by(|dir, parent| dir.starts_with(parent));
+
+    if existing.is_empty() {
+        info!("✓ Nothing to clean");
+        return Ok(());
+    }
+    for dir in &existing {
+        if dry_run {
+            info!("→ Would remove {}", dir.display());
+        } else {
+            fs::remove_dir_all(dir)
+                .await
+                .with_context(|| format!("Failed to remove {}", dir.display()))?;
+            info!("✓ Removed {}", dir.display());
+        }
+    }
+    if dry_run {
+        warn!("Dry run, nothing was removed");
+    }
+    Ok(())
+}
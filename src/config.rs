@@ -1,6 +1,16 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use crate::adb::validate_connect_address;
+use crate::binary::validate_anti_detection_keyword;
+use crate::downloader::{parse_headers, validate_prebuilt_base_url};
+use crate::keystore::keychain_password;
+use reqwest::header::HeaderMap;
+
+/// Reruns of apktool/apksigner after a transient failure, unless `toolRetries` is set.
+pub const DEFAULT_TOOL_RETRIES: u32 = 1;
 
 macro_rules! merge_fields {
     ($self:expr, $other:expr, $($field:ident),*) => {
@@ -16,17 +26,206 @@ macro_rules! merge_fields {
 pub struct SignConfig {
     pub keystore: String,
     #[serde(rename = "keystorePass")]
-    pub keystore_pass: String,
+    pub keystore_pass: Option<String>,
+    /// OS keychain entry holding the keystore password, instead of `keystorePass`
+    #[serde(rename = "keystorePassKeychain")]
+    pub keystore_pass_keychain: Option<KeychainEntry>,
     #[serde(rename = "keystoreAlias")]
     pub keystore_alias: String,
     #[serde(rename = "keyPass")]
     pub key_pass: Option<String>,
+    /// Signing certificate history, for APKs whose signing key was rotated
+    #[serde(rename = "signingLineage")]
+    pub signing_lineage: Option<String>,
+    #[serde(rename = "rotationMinSdkVersion")]
+    pub rotation_min_sdk_version: Option<u32>,
+}
+
+/// A password in the OS keychain (macOS Keychain, Windows Credential Manager or
+/// the Secret Service on Linux).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeychainEntry {
+    pub service: String,
+    pub account: String,
+}
+
+impl SignConfig {
+    /// Checks that exactly one source of the keystore password is configured.
+    pub fn validate(&self) -> Result<()> {
+        match (&self.keystore_pass, &self.keystore_pass_keychain) {
+            (Some(_), Some(_)) => anyhow::bail!(
+                "sign.keystorePass and sign.keystorePassKeychain can't be used together"
+            ),
+            (None, None) => anyhow::bail!(
+                "Missing required field: sign.keystorePass (or sign.keystorePassKeychain)"
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// The keystore password, read from the OS keychain with `keystorePassKeychain`.
+    pub fn keystore_password(&self) -> Result<String> {
+        match &self.keystore_pass_keychain {
+            Some(entry) => keychain_password(entry),
+            None => self
+                .keystore_pass
+                .clone()
+                .context("Missing required field: sign.keystorePass"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum InjectMode {
     #[serde(rename = "NativeAddNeeded")]
     NativeAddNeeded,
+    /// Leave the app's libraries untouched and load ours from a stub `Application` subclass.
+    #[serde(rename = "ApplicationStub")]
+    ApplicationStub,
+    /// Like `ApplicationStub`, but adds the stub as a generated dex instead of
+    /// having apktool assemble smali.
+    #[serde(rename = "LoaderDex")]
+    LoaderDex,
+}
+
+/// Where artifacts are placed inside `outputDir`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputLayout {
+    #[serde(rename = "flat")]
+    Flat,
+    /// `{outputDir}/{platform}/`
+    #[serde(rename = "by-platform")]
+    ByPlatform,
+    /// `{outputDir}/{target}/`
+    #[serde(rename = "by-target")]
+    ByTarget,
+}
+
+/// Which APK of a split-installed package is pulled from the device for injection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PreferSplit {
+    /// The config split holding the target ABI's native libraries
+    #[serde(rename = "abi")]
+    Abi,
+    /// `base.apk`
+    #[serde(rename = "base")]
+    Base,
+    /// The split holding `lib/{abi}/` if the package has one, otherwise `base.apk`
+    #[serde(rename = "auto")]
+    Auto,
+}
+
+/// How the (compressed) embedded data is stored in the binary.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EmbedEncoding {
+    #[serde(rename = "raw")]
+    Raw,
+    /// Base64 text without NUL bytes, only understood by version 3 loaders
+    #[serde(rename = "base64")]
+    Base64,
+}
+
+/// Where `watch` pushes the entry to: a path on the device, `true` for the
+/// default path or `false` (or `null`) to not push at all.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PushPath {
+    Path(String),
+    Enabled(bool),
+}
+
+/// Default `pushPath` of Android targets in watch mode.
+pub const DEFAULT_PUSH_PATH: &str = "/data/local/tmp/fripack_dev.js";
+
+/// Reads an explicit `pushPath: null` as `false` instead of as unset.
+fn deserialize_push_path<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<PushPath>, D::Error> {
+    Ok(Some(
+        Option::<PushPath>::deserialize(deserializer)?.unwrap_or(PushPath::Enabled(false)),
+    ))
+}
+
+/// Container used when `xz` is enabled.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum XzFormat {
+    /// Full `.xz` container with integrity check
+    #[serde(rename = "xz")]
+    Xz,
+    /// Raw LZMA2 stream, smaller but only understood by version 2 loaders
+    #[serde(rename = "lzma")]
+    Lzma,
+}
+
+/// Compression of the embedded data, as selected by `compression`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompressionKind {
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "xz")]
+    Xz,
+    /// Raw LZMA2 stream, see [`XzFormat::Lzma`]
+    #[serde(rename = "lzma")]
+    Lzma,
+    /// zstd, with `zstdDict` if set
+    #[serde(rename = "zstd")]
+    Zstd,
+}
+
+/// One compression for every platform, or one per platform pattern, e.g.
+/// `{ "android-*": "zstd", "*": "xz" }`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CompressionConfig {
+    All(CompressionKind),
+    PerPlatform(BTreeMap<String, CompressionKind>),
+}
+
+impl CompressionConfig {
+    /// Picks the compression for `platform`, or `None` if no pattern matches.
+    /// Exact platforms take precedence over `os-*`/`*-arch` patterns, which
+    /// take precedence over `*`/`all`.
+    pub fn for_platform(&self, platform: &PlatformConfig) -> Result<Option<CompressionKind>> {
+        let per_platform = match self {
+            CompressionConfig::All(kind) => return Ok(Some(*kind)),
+            CompressionConfig::PerPlatform(per_platform) => per_platform,
+        };
+        let platform_desc = platform.to_string();
+        let mut best: Option<(usize, &str, CompressionKind)> = None;
+        for (pattern, kind) in per_platform {
+            let (pattern_desc, wildcards) = match pattern.as_str() {
+                "*" | "all" => ("all", 2),
+                pattern => (pattern, pattern.matches('*').count()),
+            };
+            let matches = PlatformConfig::resolve_pattern(pattern_desc)
+                .with_context(|| format!("Invalid compression platform pattern: {pattern}"))?
+                .iter()
+                .any(|matched| matched.to_string() == platform_desc);
+            if !matches {
+                continue;
+            }
+            match best {
+                Some((best_wildcards, best_pattern, best_kind))
+                    if best_wildcards == wildcards && best_kind != *kind =>
+                {
+                    anyhow::bail!(
+                        "Compression patterns {best_pattern:?} and {pattern:?} both match {platform_desc}, add an entry for {platform_desc:?}"
+                    );
+                }
+                Some((best_wildcards, _, _)) if best_wildcards <= wildcards => {}
+                _ => best = Some((wildcards, pattern, *kind)),
+            }
+        }
+        Ok(best.map(|(_, _, kind)| kind))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Runtime {
+    #[serde(rename = "qjs")]
+    Qjs,
+    #[serde(rename = "v8")]
+    V8,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,6 +238,37 @@ pub struct InjectApkConfig {
     pub inject_mode: InjectMode,
     #[serde(rename = "targetLib")]
     pub target_lib: Option<String>,
+    /// Native libraries to inject into, all referencing the same payload
+    #[serde(rename = "targetLibs")]
+    pub target_libs: Option<Vec<String>>,
+    #[serde(rename = "preferSplit")]
+    pub prefer_split: Option<PreferSplit>,
+    /// Round-trip the APK through apktool even for `NativeAddNeeded`, which
+    /// otherwise only rewrites the zip entries it changes
+    #[serde(rename = "useApktool")]
+    pub use_apktool: Option<bool>,
+}
+
+impl InjectApkConfig {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(target_libs) = &self.target_libs {
+            if self.target_lib.is_some() {
+                anyhow::bail!(
+                    "injectApk.targetLib and injectApk.targetLibs can't be used together"
+                );
+            }
+            if target_libs.is_empty() {
+                anyhow::bail!("injectApk.targetLibs must not be empty");
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the APK is rewritten as a zip instead of going through apktool.
+    pub fn repackages_as_zip(&self) -> bool {
+        matches!(self.inject_mode, InjectMode::NativeAddNeeded)
+            && !self.use_apktool.unwrap_or(false)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,6 +279,16 @@ pub struct XposedConfig {
     pub icon: Option<String>,
     pub scope: Option<String>,
     pub description: Option<String>,
+    #[serde(rename = "nativeLibs")]
+    pub native_libs: Option<Vec<String>>,
+    #[serde(rename = "minSdk")]
+    pub min_sdk: Option<u32>,
+    #[serde(rename = "targetSdk")]
+    pub target_sdk: Option<u32>,
+    /// Write `assets/native_init`, which LSPosed loads the native libraries from
+    /// (default: true). The module's smali loads the main library either way.
+    #[serde(rename = "emitNativeInit")]
+    pub emit_native_init: Option<bool>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -63,6 +303,102 @@ pub struct ZygiskConfig {
     pub scope: Option<String>,
 }
 
+impl ZygiskConfig {
+    /// Checks the fields `module.prop` and the loader need. Magisk only accepts
+    /// module ids matching `^[a-zA-Z][a-zA-Z0-9._-]+$`.
+    pub fn validate(&self) -> Result<()> {
+        let id = self
+            .id
+            .as_deref()
+            .context("Missing required field: zygisk.id")?;
+        if self.name.is_none() {
+            anyhow::bail!("Missing required field: zygisk.name");
+        }
+        if self.scope.is_none() {
+            anyhow::bail!("Missing required field: zygisk.scope");
+        }
+        let mut chars = id.chars();
+        let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+            && id.len() > 1
+            && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+        if !valid {
+            anyhow::bail!("Invalid zygisk.id {id:?}: must match ^[a-zA-Z][a-zA-Z0-9._-]+$");
+        }
+        Ok(())
+    }
+}
+
+/// Compiles a (TypeScript) agent into the target's `entry` before embedding it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleConfig {
+    /// Agent entry point, e.g. `agent/index.ts`
+    pub input: Option<String>,
+    /// Directory watched in `watch` mode (default: the directory of `input`)
+    #[serde(rename = "inputDir")]
+    pub input_dir: Option<String>,
+    /// Bundler to run as `<command> <input> -o <entry>` (default: `frida-compile`)
+    pub command: Option<String>,
+}
+
+/// How `watch` runs the `frida` CLI against the app after each push.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttachMode {
+    /// Start the package with `frida -U -f <package>`
+    #[serde(rename = "spawn")]
+    Spawn,
+    /// Attach to a running process with `frida -U -n <name>` or `frida -U -p <pid>`
+    #[serde(rename = "attach")]
+    Attach,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachConfig {
+    pub mode: AttachMode,
+    /// Package name to spawn, or process name or PID to attach to
+    pub target: String,
+}
+
+impl AttachConfig {
+    /// Checks that `target` can be used with `mode`: a PID can't be spawned.
+    pub fn validate(&self) -> Result<()> {
+        if self.target.trim().is_empty() {
+            anyhow::bail!("Missing required field: attach.target");
+        }
+        if self.mode == AttachMode::Spawn && self.pid().is_some() {
+            anyhow::bail!(
+                "attach.target {:?} is a PID, which can only be used with mode \"attach\"",
+                self.target
+            );
+        }
+        Ok(())
+    }
+
+    /// The PID to attach to, if `target` is one.
+    pub fn pid(&self) -> Option<u32> {
+        self.target.parse().ok()
+    }
+
+    /// The `frida` arguments selecting the process, e.g. `["-n", "Gadget"]`.
+    pub fn frida_args(&self) -> [&str; 2] {
+        match (self.mode, self.pid()) {
+            (AttachMode::Spawn, _) => ["-f", &self.target],
+            (AttachMode::Attach, Some(_)) => ["-p", &self.target],
+            (AttachMode::Attach, None) => ["-n", &self.target],
+        }
+    }
+}
+
+/// Turns a Windows shared library into a proxy for another DLL.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyDllConfig {
+    /// The original DLL to impersonate, whose exports are forwarded
+    #[serde(rename = "proxyTarget")]
+    pub proxy_target: String,
+    /// Module the exports are forwarded to (default: `{proxyTarget stem}_orig`)
+    #[serde(rename = "forwardTo")]
+    pub forward_to: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FripackConfig {
     #[serde(flatten)]
@@ -95,6 +431,37 @@ impl FripackConfig {
                 zygisk: None,
                 watch_path: None,
                 push_path: None,
+                zstd_dict: None,
+                runtime: None,
+                pe_section_name: None,
+                max_output_size: None,
+                device: None,
+                embedded_version: None,
+                require_anti_detection: None,
+                bundle: None,
+                xz_format: None,
+                emit_both: None,
+                proxy_dll: None,
+                output_layout: None,
+                embed_env: None,
+                download_headers: None,
+                post_process: None,
+                tool_retries: None,
+                embed_encoding: None,
+                embed_vaddr: None,
+                embed_auto_align: None,
+                anti_detection: None,
+                anti_detection_keywords: None,
+                compression: None,
+                prebuilt_base_url: None,
+                ready_marker: None,
+                adb_connect: None,
+                output_store: None,
+                embed_scripts: None,
+                verify_command: None,
+                apktool_jar: None,
+                apktool_java_args: None,
+                attach: None,
             },
         );
 
@@ -104,7 +471,7 @@ impl FripackConfig {
             TargetConfig {
                 inherit: None,
                 target_type: Some("xposed".to_string()),
-                platform: Some("android-arm64".to_string()),
+                platform: Some(PlatformSpec::Single("android-arm64".to_string())),
                 version: Some("1.0.0".to_string()),
                 frida_version: None,
                 entry: None,
@@ -112,9 +479,12 @@ impl FripackConfig {
                 override_prebuild_file: None,
                 sign: Some(SignConfig {
                     keystore: "C:\\Users\\YourUser\\.android\\debug.keystore".to_string(),
-                    keystore_pass: "android".to_string(),
+                    keystore_pass: Some("android".to_string()),
+                    keystore_pass_keychain: None,
                     keystore_alias: "androiddebugkey".to_string(),
                     key_pass: None,
+                    signing_lineage: None,
+                    rotation_min_sdk_version: None,
                 }),
                 output_dir: None,
                 target_base_name: None,
@@ -130,10 +500,45 @@ impl FripackConfig {
                         "Easy example which makes the status bar clock red and adds a smiley"
                             .to_string(),
                     ),
+                    native_libs: None,
+                    min_sdk: None,
+                    target_sdk: None,
+                    emit_native_init: None,
                 }),
                 zygisk: None,
                 watch_path: None,
                 push_path: None,
+                zstd_dict: None,
+                runtime: None,
+                pe_section_name: None,
+                max_output_size: None,
+                device: None,
+                embedded_version: None,
+                require_anti_detection: None,
+                bundle: None,
+                xz_format: None,
+                emit_both: None,
+                proxy_dll: None,
+                output_layout: None,
+                embed_env: None,
+                download_headers: None,
+                post_process: None,
+                tool_retries: None,
+                embed_encoding: None,
+                embed_vaddr: None,
+                embed_auto_align: None,
+                anti_detection: None,
+                anti_detection_keywords: None,
+                compression: None,
+                prebuilt_base_url: None,
+                ready_marker: None,
+                adb_connect: None,
+                output_store: None,
+                embed_scripts: None,
+                verify_command: None,
+                apktool_jar: None,
+                apktool_java_args: None,
+                attach: None,
             },
         );
 
@@ -142,8 +547,8 @@ impl FripackConfig {
             "example-android-so".to_string(),
             TargetConfig {
                 inherit: Some("base".to_string()),
-                target_type: Some("shared".to_string()),
-                platform: Some("android-arm64".to_string()),
+                target_type: Some("android-so".to_string()),
+                platform: Some(PlatformSpec::Single("android-arm64".to_string())),
                 version: None,
                 frida_version: None,
                 entry: None,
@@ -159,6 +564,37 @@ impl FripackConfig {
                 zygisk: None,
                 watch_path: None,
                 push_path: None,
+                zstd_dict: None,
+                runtime: None,
+                pe_section_name: None,
+                max_output_size: None,
+                device: None,
+                embedded_version: None,
+                require_anti_detection: None,
+                bundle: None,
+                xz_format: None,
+                emit_both: None,
+                proxy_dll: None,
+                output_layout: None,
+                embed_env: None,
+                download_headers: None,
+                post_process: None,
+                tool_retries: None,
+                embed_encoding: None,
+                embed_vaddr: None,
+                embed_auto_align: None,
+                anti_detection: None,
+                anti_detection_keywords: None,
+                compression: None,
+                prebuilt_base_url: None,
+                ready_marker: None,
+                adb_connect: None,
+                output_store: None,
+                embed_scripts: None,
+                verify_command: None,
+                apktool_jar: None,
+                apktool_java_args: None,
+                attach: None,
             },
         );
 
@@ -168,7 +604,7 @@ impl FripackConfig {
             TargetConfig {
                 inherit: None,
                 target_type: Some("inject-apk".to_string()),
-                platform: Some("android-arm64".to_string()),
+                platform: Some(PlatformSpec::Single("android-arm64".to_string())),
                 version: Some("1.0.0".to_string()),
                 frida_version: Some("17.5.1".to_string()),
                 entry: Some("main.js".to_string()),
@@ -183,17 +619,54 @@ impl FripackConfig {
                     source_apk_package_name: Some("com.example.app".to_string()),
                     inject_mode: InjectMode::NativeAddNeeded,
                     target_lib: Some("libnative-lib.so".to_string()),
+                    target_libs: None,
+                    prefer_split: None,
+                    use_apktool: None,
                 }),
                 xposed: None,
                 zygisk: None,
                 sign: Some(SignConfig {
                     keystore: "C:\\Users\\YourUser\\.android\\debug.keystore".to_string(),
-                    keystore_pass: "android".to_string(),
+                    keystore_pass: Some("android".to_string()),
+                    keystore_pass_keychain: None,
                     keystore_alias: "androiddebugkey".to_string(),
                     key_pass: None,
+                    signing_lineage: None,
+                    rotation_min_sdk_version: None,
                 }),
                 watch_path: None,
                 push_path: None,
+                zstd_dict: None,
+                runtime: None,
+                pe_section_name: None,
+                max_output_size: None,
+                device: None,
+                embedded_version: None,
+                require_anti_detection: None,
+                bundle: None,
+                xz_format: None,
+                emit_both: None,
+                proxy_dll: None,
+                output_layout: None,
+                embed_env: None,
+                download_headers: None,
+                post_process: None,
+                tool_retries: None,
+                embed_encoding: None,
+                embed_vaddr: None,
+                embed_auto_align: None,
+                anti_detection: None,
+                anti_detection_keywords: None,
+                compression: None,
+                prebuilt_base_url: None,
+                ready_marker: None,
+                adb_connect: None,
+                output_store: None,
+                embed_scripts: None,
+                verify_command: None,
+                apktool_jar: None,
+                apktool_java_args: None,
+                attach: None,
             },
         );
 
@@ -203,7 +676,7 @@ impl FripackConfig {
             TargetConfig {
                 inherit: None,
                 target_type: Some("zygisk".to_string()),
-                platform: Some("android-arm64".to_string()),
+                platform: Some(PlatformSpec::Single("android-arm64".to_string())),
                 version: Some("1.0.0".to_string()),
                 frida_version: Some("17.5.1".to_string()),
                 entry: Some("main.js".to_string()),
@@ -227,12 +700,56 @@ impl FripackConfig {
                 sign: None,
                 watch_path: None,
                 push_path: None,
+                zstd_dict: None,
+                runtime: None,
+                pe_section_name: None,
+                max_output_size: None,
+                device: None,
+                embedded_version: None,
+                require_anti_detection: None,
+                bundle: None,
+                xz_format: None,
+                emit_both: None,
+                proxy_dll: None,
+                output_layout: None,
+                embed_env: None,
+                download_headers: None,
+                post_process: None,
+                tool_retries: None,
+                embed_encoding: None,
+                embed_vaddr: None,
+                embed_auto_align: None,
+                anti_detection: None,
+                anti_detection_keywords: None,
+                compression: None,
+                prebuilt_base_url: None,
+                ready_marker: None,
+                adb_connect: None,
+                output_store: None,
+                embed_scripts: None,
+                verify_command: None,
+                apktool_jar: None,
+                apktool_java_args: None,
+                attach: None,
             },
         );
 
         Self { targets }
     }
 
+    /// Loads the given configuration files and deep-merges them in order, so
+    /// later files override (or add) fields and targets of earlier ones.
+    pub fn load(paths: &[PathBuf]) -> Result<Self> {
+        let mut merged = serde_json::Value::Object(Default::default());
+        for path in paths {
+            let content = std::fs::read_to_string(path)?;
+            let layer: serde_json::Value = json5::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", path.display(), e))?;
+            merge_json(&mut merged, layer);
+        }
+        Ok(serde_json::from_value(merged)?)
+    }
+
     pub fn resolve_inheritance(&self) -> Result<ResolvedConfig> {
         let mut resolved_targets = HashMap::new();
         let mut processing = std::collections::HashSet::new();
@@ -278,7 +795,9 @@ impl FripackConfig {
         }
 
         // Override with current target values
-        resolved.merge_from(target);
+        resolved
+            .merge_from(target)
+            .with_context(|| format!("Failed to resolve target {name}"))?;
 
         processing.remove(name);
         resolved_targets.insert(name.to_string(), resolved);
@@ -297,7 +816,7 @@ pub struct TargetConfig {
     pub inherit: Option<String>,
     #[serde(rename = "type")]
     pub target_type: Option<String>,
-    pub platform: Option<String>,
+    pub platform: Option<PlatformSpec>,
     pub version: Option<String>,
     #[serde(rename = "fridaVersion")]
     pub frida_version: Option<String>,
@@ -320,8 +839,112 @@ pub struct TargetConfig {
     pub zygisk: Option<ZygiskConfig>,
     #[serde(rename = "watchPath")]
     pub watch_path: Option<String>,
-    #[serde(rename = "pushPath")]
-    pub push_path: Option<String>,
+    #[serde(
+        rename = "pushPath",
+        default,
+        deserialize_with = "deserialize_push_path"
+    )]
+    pub push_path: Option<PushPath>,
+    #[serde(rename = "zstdDict")]
+    pub zstd_dict: Option<String>,
+    pub runtime: Option<Runtime>,
+    #[serde(rename = "peSectionName")]
+    pub pe_section_name: Option<String>,
+    #[serde(rename = "maxOutputSize")]
+    pub max_output_size: Option<String>,
+    /// adb serial of the device to use
+    pub device: Option<String>,
+    /// Version of the embedded config header to emit (default 1)
+    #[serde(rename = "embeddedVersion")]
+    pub embedded_version: Option<i32>,
+    /// Fail the build if anti-detection didn't replace the core frida keywords
+    #[serde(rename = "requireAntiDetection")]
+    pub require_anti_detection: Option<bool>,
+    pub bundle: Option<BundleConfig>,
+    #[serde(rename = "xzFormat")]
+    pub xz_format: Option<XzFormat>,
+    /// Also write the aligned, unsigned APK next to the signed one
+    #[serde(rename = "emitBoth")]
+    pub emit_both: Option<bool>,
+    #[serde(rename = "proxyDll")]
+    pub proxy_dll: Option<ProxyDllConfig>,
+    #[serde(rename = "outputLayout")]
+    pub output_layout: Option<OutputLayout>,
+    /// Key/value pairs embedded for the agent to read at runtime
+    #[serde(rename = "embedEnv")]
+    pub embed_env: Option<BTreeMap<String, serde_json::Value>>,
+    /// Extra HTTP headers sent when downloading the prebuilt file
+    #[serde(rename = "downloadHeaders")]
+    pub download_headers: Option<BTreeMap<String, String>>,
+    /// Command run on the artifact, e.g. `obfuscate {artifact} -o {output}`
+    #[serde(rename = "postProcess")]
+    pub post_process: Option<String>,
+    /// How often apktool/apksigner are rerun after a transient failure
+    #[serde(rename = "toolRetries")]
+    pub tool_retries: Option<u32>,
+    #[serde(rename = "embedEncoding")]
+    pub embed_encoding: Option<EmbedEncoding>,
+    /// Virtual address of the ELF segment holding the embedded data
+    /// (default: after the highest mapped address)
+    #[serde(rename = "embedVaddr")]
+    pub embed_vaddr: Option<u64>,
+    /// Round the embedded data's address up to the page size
+    #[serde(rename = "embedAutoAlign")]
+    pub embed_auto_align: Option<bool>,
+    /// Replace telltale frida strings in the binary (default: true)
+    #[serde(rename = "antiDetection")]
+    pub anti_detection: Option<bool>,
+    /// Extra strings for anti-detection to replace, on top of the built-in ones
+    #[serde(rename = "antiDetectionKeywords")]
+    pub anti_detection_keywords: Option<Vec<String>>,
+    /// Compression of the embedded data, overriding `xz`/`zstdDict`
+    pub compression: Option<CompressionConfig>,
+    /// Base URL of the prebuilt files, followed by `/{version}/{filename}`
+    #[serde(rename = "prebuiltBaseUrl")]
+    pub prebuilt_base_url: Option<String>,
+    /// Logcat text `watch --wait-ready` waits for after an install or push
+    #[serde(rename = "readyMarker")]
+    pub ready_marker: Option<String>,
+    /// `host:port` of a network device to `adb connect` to before using it
+    #[serde(rename = "adbConnect")]
+    pub adb_connect: Option<String>,
+    /// Keep artifacts in `{outputDir}/store/{sha256}.{ext}`, with the named
+    /// artifact a hardlink to it
+    #[serde(rename = "outputStore")]
+    pub output_store: Option<bool>,
+    /// Additional scripts embedded by name next to `entry`, for loaders that pick
+    /// one at load time
+    #[serde(rename = "embedScripts")]
+    pub embed_scripts: Option<BTreeMap<String, String>>,
+    /// Command run on a freshly downloaded prebuilt file before it's used, e.g.
+    /// a signature check of `{file}`
+    #[serde(rename = "verifyCommand")]
+    pub verify_command: Option<String>,
+    /// Run this apktool jar with `java -jar` instead of the `apktool` wrapper
+    #[serde(rename = "apktoolJar")]
+    pub apktool_jar: Option<String>,
+    /// Arguments for `java` running `apktoolJar`, e.g. `["-Xmx4g"]`
+    #[serde(rename = "apktoolJavaArgs")]
+    pub apktool_java_args: Option<Vec<String>>,
+    /// Run the `frida` CLI against the app after each push in `watch`
+    pub attach: Option<AttachConfig>,
+}
+
+/// A single platform, or a list of platforms (e.g. for a multi-ABI Xposed module).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PlatformSpec {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl PlatformSpec {
+    pub fn descs(&self) -> Vec<String> {
+        match self {
+            PlatformSpec::Single(desc) => vec![desc.clone()],
+            PlatformSpec::Multiple(descs) => descs.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -368,6 +991,54 @@ impl std::fmt::Display for PlatformConfig {
 }
 
 impl PlatformConfig {
+    /// Canonical platform descriptions accepted by [`PlatformConfig::from_str`]
+    /// (which also takes `x64` as an alias of `x86_64`).
+    pub fn supported() -> &'static [&'static str] {
+        &[
+            "android-arm32",
+            "android-arm64",
+            "android-x86",
+            "android-x86_64",
+            "windows-x86",
+            "windows-x86_64",
+            "linux-x86",
+            "linux-x86_64",
+            "macos-x86_64",
+            "macos-arm64",
+        ]
+    }
+
+    /// Resolves a platform description that may be a wildcard against
+    /// [`PlatformConfig::supported`]: `all`, `android-*` (every Android arch) or
+    /// `*-x86_64` (every OS for that arch).
+    pub fn resolve_pattern(platform_desc: &str) -> Result<Vec<Self>> {
+        if platform_desc != "all" && !platform_desc.contains('*') {
+            return Ok(vec![Self::from_str(platform_desc.to_string())?]);
+        }
+        let (os, arch) = match platform_desc.split_once('-') {
+            _ if platform_desc == "all" => ("*", "*"),
+            Some((os, "x64")) => (os, "x86_64"),
+            Some((os, arch)) => (os, arch),
+            None => anyhow::bail!("Unsupported platform pattern: {platform_desc}"),
+        };
+
+        let matched = Self::supported()
+            .iter()
+            .filter(|supported| {
+                let (supported_os, supported_arch) = supported.split_once('-').unwrap();
+                (os == "*" || os == supported_os) && (arch == "*" || arch == supported_arch)
+            })
+            .map(|supported| Self::from_str(supported.to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        if matched.is_empty() {
+            anyhow::bail!(
+                "Platform pattern {platform_desc} matches no platform (supported: {})",
+                Self::supported().join(", ")
+            );
+        }
+        Ok(matched)
+    }
+
     pub fn from_str(platform_desc: String) -> Result<Self> {
         let parts: Vec<&str> = platform_desc.split('-').collect();
 
@@ -385,7 +1056,10 @@ impl PlatformConfig {
             ["linux", "x64"] => (Platform::Linux, Arch::X86_64),
             ["macos", "x86_64"] => (Platform::MacOS, Arch::X86_64),
             ["macos", "arm64"] => (Platform::MacOS, Arch::Arm64),
-            _ => anyhow::bail!("Unsupported platform description: {platform_desc}"),
+            _ => anyhow::bail!(
+                "Unsupported platform description: {platform_desc} (supported: {})",
+                Self::supported().join(", ")
+            ),
         };
         Ok(PlatformConfig { arch, platform })
     }
@@ -418,10 +1092,76 @@ impl PlatformConfig {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+/// Replaces `${VAR}` with the value of the environment variable `VAR`.
+fn expand_env(value: &str) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .with_context(|| format!("Unterminated ${{ in {value:?}"))?;
+        let name = &rest[start + 2..start + end];
+        let var = std::env::var(name)
+            .with_context(|| format!("Environment variable {name} is not set"))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&var);
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn merge_json(base: &mut serde_json::Value, overlay: serde_json::Value) {
+    match (base, overlay) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_json(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Parses a size such as `50MB`, `1.5 GB` or `1024` (bytes). Units are 1024-based.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid size: {size}"))?;
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" | "K" => 1024,
+        "MB" | "M" => 1024 * 1024,
+        "GB" | "G" => 1024 * 1024 * 1024,
+        other => anyhow::bail!("Invalid size unit in {size}: {other}"),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Serializes with the field names of [`TargetConfig`], e.g. for `build --resolve-only`.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ResolvedTarget {
+    #[serde(rename = "type")]
     pub target_type: Option<String>,
+    #[serde(skip)]
     pub platform: Option<PlatformConfig>,
+    /// All configured platforms, `platform` is the first of them.
+    #[serde(
+        rename = "platform",
+        serialize_with = "serialize_platforms",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub platforms: Vec<PlatformConfig>,
     pub version: Option<String>,
     pub frida_version: Option<String>,
     pub entry: Option<String>,
@@ -436,12 +1176,331 @@ pub struct ResolvedTarget {
     pub xposed: Option<XposedConfig>,
     pub zygisk: Option<ZygiskConfig>,
     pub watch_path: Option<String>,
-    pub push_path: Option<String>,
+    pub push_path: Option<PushPath>,
+    pub zstd_dict: Option<String>,
+    pub runtime: Option<Runtime>,
+    pub pe_section_name: Option<String>,
+    pub max_output_size: Option<String>,
+    pub device: Option<String>,
+    pub embedded_version: Option<i32>,
+    pub require_anti_detection: Option<bool>,
+    pub bundle: Option<BundleConfig>,
+    pub xz_format: Option<XzFormat>,
+    pub emit_both: Option<bool>,
+    pub proxy_dll: Option<ProxyDllConfig>,
+    pub output_layout: Option<OutputLayout>,
+    pub embed_env: Option<BTreeMap<String, serde_json::Value>>,
+    #[serde(serialize_with = "serialize_redacted_headers")]
+    pub download_headers: Option<BTreeMap<String, String>>,
+    pub post_process: Option<String>,
+    pub tool_retries: Option<u32>,
+    pub embed_encoding: Option<EmbedEncoding>,
+    pub embed_vaddr: Option<u64>,
+    pub embed_auto_align: Option<bool>,
+    pub anti_detection: Option<bool>,
+    pub anti_detection_keywords: Option<Vec<String>>,
+    pub compression: Option<CompressionConfig>,
+    pub prebuilt_base_url: Option<String>,
+    pub ready_marker: Option<String>,
+    pub adb_connect: Option<String>,
+    pub output_store: Option<bool>,
+    pub embed_scripts: Option<BTreeMap<String, String>>,
+    pub verify_command: Option<String>,
+    pub apktool_jar: Option<String>,
+    pub apktool_java_args: Option<Vec<String>>,
+    pub attach: Option<AttachConfig>,
+    #[serde(skip)]
     pub watch_mode: bool,
 }
 
+fn serialize_platforms<S: serde::Serializer>(
+    platforms: &[PlatformConfig],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(platforms.iter().map(ToString::to_string))
+}
+
+/// Header values are often tokens, so only the names are printed.
+fn serialize_redacted_headers<S: serde::Serializer>(
+    headers: &Option<BTreeMap<String, String>>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match headers {
+        Some(headers) => serializer.collect_map(headers.keys().map(|name| (name, "<redacted>"))),
+        None => serializer.serialize_none(),
+    }
+}
+
 impl ResolvedTarget {
-    pub fn merge_from(&mut self, other: &TargetConfig) {
+    /// Directory holding the bundler's sources, watched in `watch` mode.
+    pub fn bundle_input_dir(&self) -> Option<String> {
+        let bundle = self.bundle.as_ref()?;
+        bundle.input_dir.clone().or_else(|| {
+            let input = std::path::Path::new(bundle.input.as_ref()?);
+            let dir = input.parent()?.to_string_lossy().to_string();
+            Some(if dir.is_empty() { ".".to_string() } else { dir })
+        })
+    }
+
+    /// Directory the artifacts of `target_name` built for `platform` go to.
+    pub fn artifact_dir(&self, target_name: &str, platform: &str) -> PathBuf {
+        let output_dir = PathBuf::from(self.output_dir.as_deref().unwrap_or("./fripack"));
+        match self.output_layout.unwrap_or(OutputLayout::Flat) {
+            OutputLayout::Flat => output_dir,
+            OutputLayout::ByPlatform => output_dir.join(platform),
+            OutputLayout::ByTarget => output_dir.join(target_name),
+        }
+    }
+
+    /// `embedEnv` with `${VAR}` references in string values expanded from the
+    /// environment. Values must be strings, numbers or bools.
+    pub fn runtime_config(&self) -> Result<Option<BTreeMap<String, serde_json::Value>>> {
+        let Some(embed_env) = &self.embed_env else {
+            return Ok(None);
+        };
+        let mut runtime_config = BTreeMap::new();
+        for (key, value) in embed_env {
+            let value = match value {
+                serde_json::Value::String(value) => serde_json::Value::String(
+                    expand_env(value).with_context(|| format!("Invalid embedEnv.{key}"))?,
+                ),
+                serde_json::Value::Number(_) | serde_json::Value::Bool(_) => value.clone(),
+                _ => anyhow::bail!("embedEnv.{key} must be a string, number or bool"),
+            };
+            runtime_config.insert(key.clone(), value);
+        }
+        Ok(Some(runtime_config))
+    }
+
+    /// Where `watch` pushes the entry to, see [`PushPath`]. Android targets
+    /// default to [`DEFAULT_PUSH_PATH`], others don't push unless configured.
+    pub fn push_path(&self) -> Option<&str> {
+        match &self.push_path {
+            Some(PushPath::Path(path)) => Some(path),
+            Some(PushPath::Enabled(true)) => Some(DEFAULT_PUSH_PATH),
+            Some(PushPath::Enabled(false)) => None,
+            None => (!self.platforms.is_empty()
+                && self
+                    .platforms
+                    .iter()
+                    .all(|platform| platform.platform == Platform::Android))
+            .then_some(DEFAULT_PUSH_PATH),
+        }
+    }
+
+    /// How often apktool/apksigner are rerun after a transient failure.
+    pub fn tool_retries(&self) -> u32 {
+        self.tool_retries.unwrap_or(DEFAULT_TOOL_RETRIES)
+    }
+
+    /// `downloadHeaders` with `${VAR}` references expanded from the environment.
+    pub fn download_headers(&self) -> Result<HeaderMap> {
+        let Some(download_headers) = &self.download_headers else {
+            return Ok(HeaderMap::new());
+        };
+        let mut expanded = Vec::new();
+        for (name, value) in download_headers {
+            let value =
+                expand_env(value).with_context(|| format!("Invalid downloadHeaders.{name}"))?;
+            expanded.push((name.clone(), value));
+        }
+        parse_headers(expanded)
+    }
+
+    /// Checks the fields every build needs, without touching the network or disk.
+    pub fn validate(&self) -> Result<()> {
+        match self.problems().into_iter().next() {
+            Some(problem) => Err(problem),
+            None => Ok(()),
+        }
+    }
+
+    /// Every problem [`ResolvedTarget::validate`] would report, not just the first.
+    pub fn problems(&self) -> Vec<anyhow::Error> {
+        let mut problems = Vec::new();
+        let Some(target_type) = self.target_type.as_deref() else {
+            return problems;
+        };
+        if !matches!(
+            target_type,
+            "shared" | "android-so" | "xposed" | "inject-apk" | "zygisk"
+        ) {
+            problems.push(anyhow::anyhow!("Unsupported target type: {target_type}"));
+            return problems;
+        }
+        if self.platforms.is_empty() {
+            problems.push(anyhow::anyhow!("Missing required field: platform"));
+        }
+        if self.platforms.len() > 1 && !matches!(target_type, "xposed" | "shared") {
+            problems.push(anyhow::anyhow!(
+                "Multiple platforms are only supported for xposed and shared targets"
+            ));
+        }
+        if self
+            .platforms
+            .iter()
+            .any(|p| p.platform != Platform::Android)
+        {
+            match target_type {
+                "android-so" => problems.push(anyhow::anyhow!(
+                    "android-so target only supports Android platform"
+                )),
+                "xposed" => problems.push(anyhow::anyhow!(
+                    "Xposed target only supports Android platform"
+                )),
+                "inject-apk" => problems.push(anyhow::anyhow!(
+                    "Inject APK target only supports Android platform"
+                )),
+                "zygisk" => problems.push(anyhow::anyhow!(
+                    "Zygisk target only supports Android platform"
+                )),
+                _ => {}
+            }
+        }
+        if self.frida_version.is_none() {
+            problems.push(anyhow::anyhow!("Missing required field: fridaVersion"));
+        }
+        let section_missing = match target_type {
+            "xposed" => self.xposed.is_none().then_some("xposed"),
+            "inject-apk" => self.inject_apk.is_none().then_some("injectApk"),
+            "zygisk" => self.zygisk.is_none().then_some("zygisk"),
+            _ => None,
+        };
+        if let Some(field) = section_missing {
+            problems.push(anyhow::anyhow!("Missing required field: {field}"));
+        }
+        if let Some(compression) = &self.compression {
+            for platform in &self.platforms {
+                match compression.for_platform(platform) {
+                    Ok(Some(CompressionKind::Lzma)) if self.embedded_version.unwrap_or(1) < 2 => {
+                        problems.push(anyhow::anyhow!(
+                            "compression \"lzma\" for {platform} requires a loader with embeddedVersion 2"
+                        ));
+                    }
+                    Ok(_) => {}
+                    Err(e) => problems.push(e),
+                }
+            }
+        }
+        if let Some(inject_apk) = self
+            .inject_apk
+            .as_ref()
+            .filter(|_| target_type == "inject-apk")
+        {
+            problems.extend(inject_apk.validate().err());
+            if inject_apk.source_apk_path.is_none() && inject_apk.source_apk_package_name.is_none()
+            {
+                problems.push(anyhow::anyhow!(
+                    "Either sourceApkPath or sourceApkPackageName must be provided"
+                ));
+            }
+        }
+        if let Some(sign) = &self.sign {
+            problems.extend(sign.validate().err());
+        }
+        if self
+            .embed_scripts
+            .iter()
+            .flatten()
+            .any(|(name, _)| name.is_empty())
+        {
+            problems.push(anyhow::anyhow!("embedScripts names must not be empty"));
+        }
+        if let Some(xposed) = self.xposed.as_ref().filter(|_| target_type == "xposed") {
+            if xposed.package_name.is_none() {
+                problems.push(anyhow::anyhow!(
+                    "Missing required field: xposed.packageName"
+                ));
+            }
+            if xposed.name.is_none() {
+                problems.push(anyhow::anyhow!("Missing required field: xposed.name"));
+            }
+            // Only native_init loads the extra libraries, the smali loads the main one
+            if !xposed.emit_native_init.unwrap_or(true)
+                && xposed
+                    .native_libs
+                    .as_ref()
+                    .is_some_and(|libs| !libs.is_empty())
+            {
+                problems.push(anyhow::anyhow!(
+                    "xposed.nativeLibs are loaded through assets/native_init, which xposed.emitNativeInit disables"
+                ));
+            }
+        }
+        if let Some(zygisk) = self.zygisk.as_ref().filter(|_| target_type == "zygisk") {
+            problems.extend(zygisk.validate().err());
+        }
+        if let Some(max_output_size) = &self.max_output_size {
+            problems.extend(parse_size(max_output_size).err());
+        }
+        if self.require_anti_detection == Some(true) && self.anti_detection == Some(false) {
+            problems.push(anyhow::anyhow!(
+                "requireAntiDetection can't be used with antiDetection disabled"
+            ));
+        }
+        for keyword in self.anti_detection_keywords.iter().flatten() {
+            problems.extend(
+                validate_anti_detection_keyword(keyword)
+                    .context("Invalid antiDetectionKeywords")
+                    .err(),
+            );
+        }
+        problems.extend(self.runtime_config().err());
+        problems.extend(self.download_headers().err());
+        if let Some(prebuilt_base_url) = &self.prebuilt_base_url {
+            problems.extend(
+                validate_prebuilt_base_url(prebuilt_base_url)
+                    .context("Invalid prebuiltBaseUrl")
+                    .err(),
+            );
+        }
+        if let Some(adb_connect) = &self.adb_connect {
+            problems.extend(
+                validate_connect_address(adb_connect)
+                    .context("Invalid adbConnect")
+                    .err(),
+            );
+        }
+        if let Some(post_process) = &self.post_process {
+            if !post_process.contains("{artifact}") {
+                problems.push(anyhow::anyhow!(
+                    "postProcess must contain an {{artifact}} placeholder"
+                ));
+            }
+        }
+        if let Some(verify_command) = &self.verify_command {
+            if !verify_command.contains("{file}") {
+                problems.push(anyhow::anyhow!(
+                    "verifyCommand must contain a {{file}} placeholder"
+                ));
+            }
+        }
+        if self.apktool_java_args.is_some() && self.apktool_jar.is_none() {
+            problems.push(anyhow::anyhow!(
+                "apktoolJavaArgs can only be used with apktoolJar"
+            ));
+        }
+        if let Some(attach) = &self.attach {
+            problems.extend(attach.validate().err());
+            if self
+                .platforms
+                .iter()
+                .any(|platform| platform.platform != Platform::Android)
+            {
+                problems.push(anyhow::anyhow!(
+                    "attach only supports Android targets, as it runs frida -U"
+                ));
+            } else if self.push_path().is_none() {
+                // Attaching follows the push, so there'd be nothing to attach after
+                problems.push(anyhow::anyhow!(
+                    "attach requires a pushPath, frida is reattached after each push"
+                ));
+            }
+        }
+        problems
+    }
+
+    pub fn merge_from(&mut self, other: &TargetConfig) -> Result<()> {
         merge_fields!(
             self,
             other,
@@ -460,11 +1519,267 @@ impl ResolvedTarget {
             xposed,
             zygisk,
             watch_path,
-            push_path
+            push_path,
+            zstd_dict,
+            runtime,
+            pe_section_name,
+            max_output_size,
+            device,
+            embedded_version,
+            require_anti_detection,
+            bundle,
+            xz_format,
+            emit_both,
+            proxy_dll,
+            output_layout,
+            embed_env,
+            download_headers,
+            post_process,
+            tool_retries,
+            embed_encoding,
+            embed_vaddr,
+            embed_auto_align,
+            anti_detection,
+            anti_detection_keywords,
+            compression,
+            prebuilt_base_url,
+            ready_marker,
+            adb_connect,
+            output_store,
+            embed_scripts,
+            verify_command,
+            apktool_jar,
+            apktool_java_args,
+            attach
+        );
+
+        if let Some(platform_spec) = &other.platform {
+            self.platforms.clear();
+            for desc in platform_spec.descs() {
+                for platform in PlatformConfig::resolve_pattern(&desc)? {
+                    // Overlapping patterns, e.g. `android-*` and `android-arm64`
+                    if !self
+                        .platforms
+                        .iter()
+                        .any(|existing| existing.to_string() == platform.to_string())
+                    {
+                        self.platforms.push(platform);
+                    }
+                }
+            }
+            self.platform = self.platforms.first().cloned();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_config_files_override_earlier_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("fripack.base.json");
+        std::fs::write(
+            &base,
+            r#"{
+                // Shared by everyone
+                "base": {
+                    "fridaVersion": "17.5.1",
+                    "sign": {
+                        "keystore": "shared.keystore",
+                        "keystorePass": "android",
+                        "keystoreAlias": "androiddebugkey",
+                    },
+                },
+                "app": {
+                    "inherit": "base",
+                    "type": "shared",
+                    "platform": "android-arm64",
+                    "entry": "main.js",
+                },
+            }"#,
+        )
+        .unwrap();
+        let local = dir.path().join("fripack.local.json");
+        std::fs::write(
+            &local,
+            r#"{
+                "base": { "sign": { "keystore": "/home/me/release.keystore" } },
+                "app": { "device": "emulator-5554" },
+            }"#,
+        )
+        .unwrap();
+
+        let config = FripackConfig::load(&[base, local])
+            .unwrap()
+            .resolve_inheritance()
+            .unwrap();
+
+        let app = &config.targets["app"];
+        let sign = app.sign.as_ref().unwrap();
+        assert_eq!(sign.keystore, "/home/me/release.keystore");
+        assert_eq!(sign.keystore_alias, "androiddebugkey");
+        assert_eq!(sign.keystore_pass.as_deref(), Some("android"));
+        assert_eq!(app.device.as_deref(), Some("emulator-5554"));
+        assert_eq!(app.entry.as_deref(), Some("main.js"));
+    }
+
+    #[test]
+    fn every_supported_platform_parses() {
+        for platform in PlatformConfig::supported() {
+            let parsed = PlatformConfig::from_str(platform.to_string());
+            assert!(parsed.is_ok(), "{platform}: {:?}", parsed.err());
+        }
+    }
+
+    fn platform_names(platforms: &[PlatformConfig]) -> Vec<String> {
+        platforms.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn resolves_platform_wildcards() {
+        let android = PlatformConfig::resolve_pattern("android-*").unwrap();
+        assert_eq!(
+            platform_names(&android),
+            [
+                "android-arm",
+                "android-arm64",
+                "android-x86",
+                "android-x86_64"
+            ]
+        );
+        let x86_64 = PlatformConfig::resolve_pattern("*-x86_64").unwrap();
+        assert_eq!(
+            platform_names(&x86_64),
+            [
+                "android-x86_64",
+                "windows-x86_64",
+                "linux-x86_64",
+                "macos-x86_64"
+            ]
+        );
+        let all = PlatformConfig::resolve_pattern("all").unwrap();
+        assert_eq!(all.len(), PlatformConfig::supported().len());
+        assert!(PlatformConfig::resolve_pattern("ios-*").is_err());
+    }
+
+    #[test]
+    fn resolves_a_list_of_platform_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("fripack.json");
+        std::fs::write(
+            &config_path,
+            r#"{
+                "agent": {
+                    "type": "shared",
+                    "platform": ["android-*", "linux-x86_64", "android-arm64"],
+                    "fridaVersion": "17.5.1",
+                    "entry": "main.js",
+                },
+            }"#,
+        )
+        .unwrap();
+
+        let config = FripackConfig::load(&[config_path])
+            .unwrap()
+            .resolve_inheritance()
+            .unwrap();
+
+        // Overlapping patterns only add a platform once
+        let agent = &config.targets["agent"];
+        assert_eq!(
+            platform_names(&agent.platforms),
+            [
+                "android-arm",
+                "android-arm64",
+                "android-x86",
+                "android-x86_64",
+                "linux-x86_64"
+            ]
         );
+        assert_eq!(agent.platform.as_ref().unwrap().to_string(), "android-arm");
+    }
 
-        if let Some(platform_str) = &other.platform {
-            self.platform = Some(PlatformConfig::from_str(platform_str.clone()).unwrap());
+    /// Resolves a shared `app` target with `platform` and `attach`.
+    fn attach_target(platform: &str, attach: &str) -> ResolvedTarget {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("fripack.json");
+        std::fs::write(
+            &config_path,
+            format!(
+                r#"{{
+                    "app": {{
+                        "type": "shared",
+                        "platform": "{platform}",
+                        "fridaVersion": "17.5.1",
+                        "entry": "main.js",
+                        "attach": {attach},
+                    }},
+                }}"#
+            ),
+        )
+        .unwrap();
+        let config = FripackConfig::load(&[config_path])
+            .unwrap()
+            .resolve_inheritance()
+            .unwrap();
+        config.targets["app"].clone()
+    }
+
+    #[test]
+    fn attach_selects_the_process_for_frida() {
+        for (attach, args) in [
+            (
+                r#"{ mode: "spawn", target: "com.example.app" }"#,
+                ["-f", "com.example.app"],
+            ),
+            (r#"{ mode: "attach", target: "Gadget" }"#, ["-n", "Gadget"]),
+            (r#"{ mode: "attach", target: "4242" }"#, ["-p", "4242"]),
+        ] {
+            let target = attach_target("android-arm64", attach);
+            assert!(
+                target.validate().is_ok(),
+                "{attach}: {:?}",
+                target.problems()
+            );
+            assert_eq!(target.attach.as_ref().unwrap().frida_args(), args);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_attach_combinations() {
+        for (platform, attach, problem) in [
+            (
+                "android-arm64",
+                r#"{ mode: "spawn", target: "4242" }"#,
+                "is a PID, which can only be used with mode \"attach\"",
+            ),
+            (
+                "android-arm64",
+                r#"{ mode: "attach", target: " " }"#,
+                "Missing required field: attach.target",
+            ),
+            (
+                "linux-x86_64",
+                r#"{ mode: "attach", target: "Gadget" }"#,
+                "attach only supports Android targets",
+            ),
+        ] {
+            let problems = attach_target(platform, attach).problems();
+            assert!(
+                problems.iter().any(|e| e.to_string().contains(problem)),
+                "{attach}: {problems:?}"
+            );
         }
+
+        let mut target = attach_target("android-arm64", r#"{ mode: "attach", target: "Gadget" }"#);
+        target.push_path = Some(PushPath::Enabled(false));
+        assert!(target
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("attach requires a pushPath"));
     }
 }
@@ -0,0 +1,313 @@
+use anyhow::Result;
+use sha1::{Digest, Sha1};
+use std::collections::BTreeSet;
+
+const HEADER_SIZE: usize = 0x70;
+const NO_INDEX: u32 = 0xffff_ffff;
+
+const ACC_PUBLIC: u32 = 0x1;
+const ACC_STATIC: u32 = 0x8;
+const ACC_CONSTRUCTOR: u32 = 0x10000;
+
+const TYPE_HEADER_ITEM: u16 = 0x0000;
+const TYPE_STRING_ID_ITEM: u16 = 0x0001;
+const TYPE_TYPE_ID_ITEM: u16 = 0x0002;
+const TYPE_PROTO_ID_ITEM: u16 = 0x0003;
+const TYPE_METHOD_ID_ITEM: u16 = 0x0005;
+const TYPE_CLASS_DEF_ITEM: u16 = 0x0006;
+const TYPE_MAP_LIST: u16 = 0x1000;
+const TYPE_TYPE_LIST: u16 = 0x1001;
+const TYPE_CLASS_DATA_ITEM: u16 = 0x2000;
+const TYPE_CODE_ITEM: u16 = 0x2001;
+const TYPE_STRING_DATA_ITEM: u16 = 0x2002;
+
+/// Builds a dex file with a single class, equivalent to this smali:
+///
+/// ```smali
+/// .class public L{class};
+/// .super L{super_class};
+///
+/// .method static constructor <clinit>()V
+///     const-string v0, "{library}"
+///     invoke-static {v0}, Ljava/lang/System;->loadLibrary(Ljava/lang/String;)V
+///     return-void
+/// .end method
+///
+/// .method public constructor <init>()V
+///     invoke-direct {p0}, L{super_class};-><init>()V
+///     return-void
+/// .end method
+/// ```
+///
+/// Class names use dots, e.g. `android.app.Application`.
+pub fn library_loader_dex(class: &str, super_class: &str, library: &str) -> Result<Vec<u8>> {
+    if !(class.is_ascii() && super_class.is_ascii() && library.is_ascii()) {
+        // Sorting by bytes only matches the required UTF-16 order for ASCII
+        anyhow::bail!("Loader dex names must be ASCII: {class}, {super_class}, {library}");
+    }
+    let class = format!("L{};", class.replace('.', "/"));
+    let super_class = format!("L{};", super_class.replace('.', "/"));
+
+    let strings: Vec<&str> = BTreeSet::from([
+        "<clinit>",
+        "<init>",
+        "Ljava/lang/String;",
+        "Ljava/lang/System;",
+        "V",
+        "VL",
+        "loadLibrary",
+        class.as_str(),
+        super_class.as_str(),
+        library,
+    ])
+    .into_iter()
+    .collect();
+    let string_idx = |s: &str| strings.iter().position(|x| *x == s).unwrap() as u32;
+
+    // Type ids are sorted by string index, so sorting the descriptors is enough
+    let types: Vec<&str> = BTreeSet::from([
+        class.as_str(),
+        super_class.as_str(),
+        "Ljava/lang/String;",
+        "Ljava/lang/System;",
+        "V",
+    ])
+    .into_iter()
+    .collect();
+    let type_idx = |s: &str| types.iter().position(|x| *x == s).unwrap() as u16;
+
+    // ()V sorts before (Ljava/lang/String;)V
+    const PROTO_VOID: u16 = 0;
+    const PROTO_STRING: u16 = 1;
+
+    // (class, name, proto), sorted the same way as the method ids
+    let mut methods = vec![
+        (type_idx(&class), string_idx("<clinit>"), PROTO_VOID),
+        (type_idx(&class), string_idx("<init>"), PROTO_VOID),
+        (type_idx(&super_class), string_idx("<init>"), PROTO_VOID),
+        (
+            type_idx("Ljava/lang/System;"),
+            string_idx("loadLibrary"),
+            PROTO_STRING,
+        ),
+    ];
+    methods.sort();
+    let method_idx = |class: &str, name: &str| {
+        methods
+            .iter()
+            .position(|(c, n, _)| *c == type_idx(class) && *n == string_idx(name))
+            .unwrap() as u16
+    };
+
+    let clinit_code = code_item(
+        1,
+        0,
+        1,
+        &[
+            // const-string v0, library
+            0x001a,
+            string_idx(library) as u16,
+            // invoke-static {v0}, System.loadLibrary
+            0x1071,
+            method_idx("Ljava/lang/System;", "loadLibrary"),
+            0x0000,
+            // return-void
+            0x000e,
+        ],
+    );
+    let init_code = code_item(
+        1,
+        1,
+        1,
+        &[
+            // invoke-direct {p0}, super.<init>
+            0x1070,
+            method_idx(&super_class, "<init>"),
+            0x0000,
+            // return-void
+            0x000e,
+        ],
+    );
+
+    // Fixed-size id sections follow the header
+    let string_ids_off = HEADER_SIZE;
+    let type_ids_off = string_ids_off + 4 * strings.len();
+    let proto_ids_off = type_ids_off + 4 * types.len();
+    let method_ids_off = proto_ids_off + 12 * 2;
+    let class_defs_off = method_ids_off + 8 * methods.len();
+    let data_off = class_defs_off + 32;
+
+    // Data section: code items, type list, string data, class data, map list
+    let mut data = Vec::new();
+    let clinit_off = data_off + data.len();
+    data.extend_from_slice(&clinit_code);
+    align(&mut data, 4);
+    let init_off = data_off + data.len();
+    data.extend_from_slice(&init_code);
+    align(&mut data, 4);
+
+    let type_list_off = data_off + data.len();
+    push_u32(&mut data, 1);
+    push_u16(&mut data, type_idx("Ljava/lang/String;"));
+    align(&mut data, 4);
+
+    let string_data_off = data_off + data.len();
+    let mut string_offsets = Vec::new();
+    for string in &strings {
+        string_offsets.push((data_off + data.len()) as u32);
+        push_uleb128(&mut data, string.len() as u32);
+        data.extend_from_slice(string.as_bytes());
+        data.push(0);
+    }
+
+    let class_data_off = data_off + data.len();
+    for size in [0, 0, 2, 0] {
+        // static fields, instance fields, direct methods, virtual methods
+        push_uleb128(&mut data, size);
+    }
+    let clinit_idx = method_idx(&class, "<clinit>") as u32;
+    let init_idx = method_idx(&class, "<init>") as u32;
+    push_uleb128(&mut data, clinit_idx);
+    push_uleb128(&mut data, ACC_STATIC | ACC_CONSTRUCTOR);
+    push_uleb128(&mut data, clinit_off as u32);
+    push_uleb128(&mut data, init_idx - clinit_idx);
+    push_uleb128(&mut data, ACC_PUBLIC | ACC_CONSTRUCTOR);
+    push_uleb128(&mut data, init_off as u32);
+    align(&mut data, 4);
+
+    let map_off = data_off + data.len();
+    let map = [
+        (TYPE_HEADER_ITEM, 1, 0),
+        (TYPE_STRING_ID_ITEM, strings.len(), string_ids_off),
+        (TYPE_TYPE_ID_ITEM, types.len(), type_ids_off),
+        (TYPE_PROTO_ID_ITEM, 2, proto_ids_off),
+        (TYPE_METHOD_ID_ITEM, methods.len(), method_ids_off),
+        (TYPE_CLASS_DEF_ITEM, 1, class_defs_off),
+        (TYPE_CODE_ITEM, 2, clinit_off),
+        (TYPE_TYPE_LIST, 1, type_list_off),
+        (TYPE_STRING_DATA_ITEM, strings.len(), string_data_off),
+        (TYPE_CLASS_DATA_ITEM, 1, class_data_off),
+        (TYPE_MAP_LIST, 1, map_off),
+    ];
+    push_u32(&mut data, map.len() as u32);
+    for (item_type, size, offset) in map {
+        push_u16(&mut data, item_type);
+        push_u16(&mut data, 0);
+        push_u32(&mut data, size as u32);
+        push_u32(&mut data, offset as u32);
+    }
+
+    let file_size = data_off + data.len();
+    let mut dex = Vec::with_capacity(file_size);
+    dex.extend_from_slice(b"dex\n035\0");
+    // checksum and signature, filled in last
+    dex.extend_from_slice(&[0; 24]);
+    for field in [
+        file_size,
+        HEADER_SIZE,
+        0x1234_5678, // endian tag
+        0,           // link size
+        0,           // link offset
+        map_off,
+        strings.len(),
+        string_ids_off,
+        types.len(),
+        type_ids_off,
+        2,
+        proto_ids_off,
+        0, // field ids
+        0,
+        methods.len(),
+        method_ids_off,
+        1,
+        class_defs_off,
+        data.len(),
+        data_off,
+    ] {
+        push_u32(&mut dex, field as u32);
+    }
+
+    for offset in string_offsets {
+        push_u32(&mut dex, offset);
+    }
+    for ty in &types {
+        push_u32(&mut dex, string_idx(ty));
+    }
+    for (shorty, parameters_off) in [("V", 0), ("VL", type_list_off)] {
+        push_u32(&mut dex, string_idx(shorty));
+        push_u32(&mut dex, type_idx("V") as u32);
+        push_u32(&mut dex, parameters_off as u32);
+    }
+    for (class_idx, name_idx, proto_idx) in &methods {
+        push_u16(&mut dex, *class_idx);
+        push_u16(&mut dex, *proto_idx);
+        push_u32(&mut dex, *name_idx);
+    }
+    for field in [
+        type_idx(&class) as u32,
+        ACC_PUBLIC,
+        type_idx(&super_class) as u32,
+        0, // interfaces
+        NO_INDEX,
+        0, // annotations
+        class_data_off as u32,
+        0, // static values
+    ] {
+        push_u32(&mut dex, field);
+    }
+    debug_assert_eq!(dex.len(), data_off);
+    dex.extend_from_slice(&data);
+
+    let signature = Sha1::digest(&dex[32..]);
+    dex[12..32].copy_from_slice(&signature);
+    let checksum = adler32(&dex[12..]);
+    dex[8..12].copy_from_slice(&checksum.to_le_bytes());
+    Ok(dex)
+}
+
+fn code_item(registers: u16, ins: u16, outs: u16, insns: &[u16]) -> Vec<u8> {
+    let mut code = Vec::new();
+    for field in [registers, ins, outs, 0] {
+        push_u16(&mut code, field);
+    }
+    // debug info
+    push_u32(&mut code, 0);
+    push_u32(&mut code, insns.len() as u32);
+    for insn in insns {
+        push_u16(&mut code, *insn);
+    }
+    code
+}
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_uleb128(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn align(out: &mut Vec<u8>, alignment: usize) {
+    out.resize(out.len().next_multiple_of(alignment), 0);
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
@@ -0,0 +1,166 @@
+use anyhow::Result;
+use log::{info, warn};
+use std::path::PathBuf;
+use tokio::process::Command;
+
+use crate::builder::find_sdk_binary;
+use crate::downloader::Downloader;
+use crate::format_bytes;
+
+struct Tool {
+    name: &'static str,
+    version_args: &'static [&'static str],
+    /// Whether the common xposed/inject-apk flows can't work without it
+    required: bool,
+    in_sdk: bool,
+}
+
+const TOOLS: &[Tool] = &[
+    Tool {
+        name: "apktool",
+        version_args: &["--version"],
+        required: true,
+        in_sdk: false,
+    },
+    Tool {
+        name: "apksigner",
+        version_args: &["--version"],
+        required: true,
+        in_sdk: true,
+    },
+    Tool {
+        name: "zipalign",
+        version_args: &[],
+        required: false,
+        in_sdk: true,
+    },
+    Tool {
+        name: "adb",
+        version_args: &["--version"],
+        required: false,
+        in_sdk: false,
+    },
+    Tool {
+        name: "keytool",
+        version_args: &["-help"],
+        required: false,
+        in_sdk: false,
+    },
+];
+
+fn resolve_tool(tool: &Tool) -> Result<PathBuf> {
+    if tool.in_sdk {
+        find_sdk_binary(tool.name)
+    } else {
+        Ok(which::which(tool.name)?)
+    }
+}
+
+/// Finds each external tool, returning its path and whether it was found on
+/// `PATH` or in the Android SDK.
+pub fn locate_tools() -> Vec<(&'static str, Option<(PathBuf, &'static str)>)> {
+    TOOLS
+        .iter()
+        .map(|tool| {
+            let location = match which::which(tool.name) {
+                Ok(path) => Some((path, "PATH")),
+                Err(_) if tool.in_sdk => find_sdk_binary(tool.name)
+                    .ok()
+                    .map(|path| (path, "Android SDK build-tools")),
+                Err(_) => None,
+            };
+            (tool.name, location)
+        })
+        .collect()
+}
+
+async fn tool_version(path: &PathBuf, args: &[&str]) -> Option<String> {
+    let output = Command::new(path).args(args).output().await.ok()?;
+    // Some tools print their version (or usage) to stderr
+    [output.stdout, output.stderr].iter().find_map(|out| {
+        String::from_utf8_lossy(out)
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(str::to_string)
+    })
+}
+
+/// First line `name --version` (or the known version flag of a tool) prints,
+/// or `None` if the tool can't be found or run.
+pub async fn probe_tool_version(name: &str) -> Option<String> {
+    match TOOLS.iter().find(|tool| tool.name == name) {
+        Some(tool) => tool_version(&resolve_tool(tool).ok()?, tool.version_args).await,
+        None => tool_version(&which::which(name).ok()?, &["--version"]).await,
+    }
+}
+
+pub async fn run_doctor() -> Result<()> {
+    info!("Checking environment");
+    info!("====================");
+
+    let mut missing_required = Vec::new();
+
+    for tool in TOOLS {
+        match resolve_tool(tool) {
+            Ok(path) => {
+                let version = tool_version(&path, tool.version_args)
+                    .await
+                    .unwrap_or_else(|| "unknown version".to_string());
+                info!("✓ {}: {} ({})", tool.name, path.display(), version);
+            }
+            Err(e) => {
+                if tool.required {
+                    missing_required.push(tool.name);
+                    warn!("✗ {}: not found ({})", tool.name, e);
+                } else {
+                    warn!("✗ {} (optional): not found ({})", tool.name, e);
+                }
+            }
+        }
+    }
+
+    match std::env::var("ANDROID_SDK_ROOT").or_else(|_| std::env::var("ANDROID_HOME")) {
+        Ok(sdk_root) => info!("✓ Android SDK: {sdk_root}"),
+        Err(_) => warn!("✗ Neither ANDROID_SDK_ROOT nor ANDROID_HOME is set"),
+    }
+
+    if let Ok(adb) = which::which("adb") {
+        match Command::new(adb).arg("devices").output().await {
+            Ok(output) => {
+                let devices = String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .skip(1)
+                    .filter(|line| line.trim_end().ends_with("device"))
+                    .count();
+                if devices > 0 {
+                    info!("✓ Connected devices: {devices}");
+                } else {
+                    warn!("✗ No device connected");
+                }
+            }
+            Err(e) => warn!("✗ Failed to run adb devices: {e}"),
+        }
+    }
+
+    let downloader = Downloader::new();
+    match downloader.check_reachable().await {
+        Ok(()) => info!("✓ Prebuilt release host is reachable"),
+        Err(e) => warn!("✗ Prebuilt release host is not reachable: {e}"),
+    }
+
+    let stats = downloader.get_cache_stats().await?;
+    info!(
+        "✓ Cache: {} ({} files, {})",
+        downloader.cache_dir().display(),
+        stats.files.len(),
+        format_bytes(stats.files.iter().map(|file| file.size).sum())
+    );
+
+    if !missing_required.is_empty() {
+        anyhow::bail!("Missing required tools: {}", missing_required.join(", "));
+    }
+
+    info!("✓ Environment looks good!");
+    Ok(())
+}
@@ -1,16 +1,78 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use futures_util::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
-use log::{info, warn};
+use log::{debug, info, warn};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use reqwest::Client;
-use std::path::PathBuf;
-use tokio::fs;
-
-use crate::config::{Platform, PlatformConfig};
-
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::{
+    fs,
+    io::AsyncWriteExt,
+    process::Command,
+    sync::{Mutex, Semaphore},
+};
+
+use crate::builder::shell_quote;
+use crate::config::{parse_size, Platform, PlatformConfig};
+use crate::format_bytes;
+
+/// Default number of downloads that may run at the same time.
+pub const DEFAULT_PARALLEL_DOWNLOADS: usize = 4;
+
+/// GitHub releases the prebuilt files are downloaded from.
+pub const RELEASES_URL: &str = "https://github.com/FriRebuild/fripack-inject/releases";
+
+/// GitHub API listing the releases, for resolving `fridaVersion` patterns.
+const RELEASES_API_URL: &str =
+    "https://api.github.com/repos/FriRebuild/fripack-inject/releases?per_page=100";
+
+/// Base URL of the prebuilt files, followed by `/{version}/{filename}`.
+pub const DEFAULT_PREBUILT_BASE_URL: &str =
+    "https://github.com/FriRebuild/fripack-inject/releases/download";
+
+/// Where APKs pulled from a device are cached, relative to the working directory.
+pub const APK_CACHE_DIR: &str = "./fripack_cache/apks";
+
+/// Downloads and caches prebuilt files. Clones share the HTTP client and the
+/// limit on concurrent downloads.
+#[derive(Clone)]
 pub struct Downloader {
     client: Client,
     cache_dir: PathBuf,
+    download_permits: Arc<Semaphore>,
+    project: Option<String>,
+    index_lock: Arc<Mutex<()>>,
+    /// `{pattern}/{platform}` -> version resolved for it during this run
+    resolved_versions: Arc<Mutex<HashMap<String, String>>>,
+    save_downloads: bool,
+    verify_checksums: bool,
+    headers: HeaderMap,
+    counters: Arc<DownloadCounters>,
+    prebuilt_base_url: String,
+    /// Command run on each downloaded file before it's used, `{file}`/`{name}`
+    /// replaced
+    verify_command: Option<String>,
+}
+
+/// Prebuilt files served so far, shared by all clones of a `Downloader`.
+#[derive(Default)]
+struct DownloadCounters {
+    cache_hits: AtomicUsize,
+    downloads: AtomicUsize,
+    bytes_downloaded: AtomicU64,
+}
+
+/// Sidecar index of the cache, recording which projects use each cached file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheIndex {
+    /// Cached file name -> ids of the projects that fetched it
+    #[serde(default)]
+    projects: HashMap<String, Vec<String>>,
 }
 
 impl Downloader {
@@ -19,6 +81,80 @@ impl Downloader {
         Self {
             client: Client::new(),
             cache_dir,
+            download_permits: Arc::new(Semaphore::new(DEFAULT_PARALLEL_DOWNLOADS)),
+            project: None,
+            index_lock: Arc::new(Mutex::new(())),
+            resolved_versions: Arc::default(),
+            save_downloads: true,
+            verify_checksums: true,
+            headers: HeaderMap::new(),
+            counters: Arc::default(),
+            prebuilt_base_url: std::env::var("FRIPACK_PREBUILT_URL")
+                .ok()
+                .filter(|url| !url.is_empty())
+                .unwrap_or_else(|| DEFAULT_PREBUILT_BASE_URL.to_string()),
+            verify_command: None,
+        }
+    }
+
+    /// Records the prebuilt files used from now on as used by `project`.
+    pub fn with_project(mut self, project: String) -> Self {
+        self.project = Some(project);
+        self
+    }
+
+    /// Limits how many downloads may run at the same time across all clones.
+    pub fn with_parallel_downloads(mut self, parallel_downloads: usize) -> Self {
+        self.download_permits = Arc::new(Semaphore::new(parallel_downloads.max(1)));
+        self
+    }
+
+    /// Keeps downloaded prebuilt files in memory only instead of writing them
+    /// to the cache. Files already in the cache are still used.
+    pub fn without_download_cache(mut self) -> Self {
+        self.save_downloads = false;
+        self
+    }
+
+    /// Downloads prebuilt files from `{prebuilt_base_url}/{version}/{filename}`,
+    /// e.g. from a mirror or a fork of the inject library.
+    pub fn with_prebuilt_base_url(mut self, prebuilt_base_url: String) -> Self {
+        self.prebuilt_base_url = prebuilt_base_url;
+        self
+    }
+
+    /// Base URL prebuilt files are downloaded from.
+    pub fn prebuilt_base_url(&self) -> &str {
+        self.prebuilt_base_url.trim_end_matches('/')
+    }
+
+    /// Runs `verify_command` on each downloaded prebuilt file before it's cached
+    /// or used, with `{file}` replaced by its path and `{name}` by its file name.
+    /// A non-zero exit rejects it.
+    pub fn with_verify_command(mut self, verify_command: String) -> Self {
+        self.verify_command = Some(verify_command);
+        self
+    }
+
+    /// Skips checking downloaded prebuilt files against the release's checksums.
+    pub fn without_checksum_verification(mut self) -> Self {
+        self.verify_checksums = false;
+        self
+    }
+
+    /// Sends `headers` with every prebuilt download, on top of the target's
+    /// `downloadHeaders`.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Prebuilt files served by this downloader and its clones so far.
+    pub fn stats(&self) -> DownloadStats {
+        DownloadStats {
+            cache_hits: self.counters.cache_hits.load(Ordering::Relaxed),
+            downloads: self.counters.downloads.load(Ordering::Relaxed),
+            bytes_downloaded: self.counters.bytes_downloaded.load(Ordering::Relaxed),
         }
     }
 
@@ -26,6 +162,11 @@ impl Downloader {
         &self.cache_dir
     }
 
+    /// Where inject-apk caches the trees apktool decodes source APKs into.
+    pub fn decompiled_dir(&self) -> PathBuf {
+        self.cache_dir.join("decompiled")
+    }
+
     pub async fn ensure_cache_dir(&self) -> Result<()> {
         if !self.cache_dir.exists() {
             fs::create_dir_all(&self.cache_dir).await?;
@@ -34,7 +175,7 @@ impl Downloader {
         Ok(())
     }
 
-    fn get_cache_file_path(&self, platform: &PlatformConfig, frida_version: &str) -> PathBuf {
+    pub fn get_cache_file_path(&self, platform: &PlatformConfig, frida_version: &str) -> PathBuf {
         let filename = self.get_prebuilt_file_name(platform, frida_version);
         self.cache_dir.join(filename)
     }
@@ -51,22 +192,95 @@ impl Downloader {
     ) -> Result<Vec<u8>> {
         let cache_path = self.get_cache_file_path(platform, frida_version);
         info!("→ Loading from cache: {}", cache_path.display());
+        // The access time orders eviction, and may not be updated by the mount.
+        // A cache we can't touch (read-only, someone else's) is still readable.
+        if let Err(e) = filetime::set_file_atime(&cache_path, filetime::FileTime::now()) {
+            debug!(
+                "Failed to update the access time of {}: {e}",
+                cache_path.display()
+            );
+        }
         Ok(fs::read(&cache_path).await?)
     }
 
-    async fn save_to_cache(
-        &self,
-        platform: &PlatformConfig,
-        frida_version: &str,
-        data: &[u8],
-    ) -> Result<()> {
+    fn index_path(&self) -> PathBuf {
+        self.cache_dir.join("index.json")
+    }
+
+    async fn load_index(&self) -> Result<CacheIndex> {
+        match fs::read(self.index_path()).await {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(CacheIndex::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn save_index(&self, index: &CacheIndex) -> Result<()> {
         self.ensure_cache_dir().await?;
-        let cache_path = self.get_cache_file_path(platform, frida_version);
-        fs::write(&cache_path, data).await?;
-        info!("→ Cached to: {}", cache_path.display());
+        fs::write(self.index_path(), serde_json::to_vec_pretty(index)?).await?;
+        Ok(())
+    }
+
+    /// Associates a cached file with the current project, if any.
+    async fn record_project_use(&self, file_name: &str) -> Result<()> {
+        let Some(project) = &self.project else {
+            return Ok(());
+        };
+        let _lock = self.index_lock.lock().await;
+        let mut index = self.load_index().await?;
+        let projects = index.projects.entry(file_name.to_string()).or_default();
+        if !projects.contains(project) {
+            projects.push(project.clone());
+            self.save_index(&index).await?;
+        }
         Ok(())
     }
 
+    /// Projects that fetched `file_name` (empty if unknown).
+    pub async fn file_projects(&self, file_name: &str) -> Result<Vec<String>> {
+        let index = self.load_index().await?;
+        Ok(index.projects.get(file_name).cloned().unwrap_or_default())
+    }
+
+    /// Drops `project` from the cache index and removes the files no other
+    /// project uses anymore. Files fetched before projects were recorded are kept.
+    pub async fn clear_project_cache(&self, project: &str) -> Result<usize> {
+        let _lock = self.index_lock.lock().await;
+        let mut index = self.load_index().await?;
+        let mut count = 0;
+
+        for file in self.list_cached_files().await? {
+            let Some(file_name) = file.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let Some(projects) = index.projects.get_mut(file_name) else {
+                continue;
+            };
+            if !projects.iter().any(|p| p == project) {
+                continue;
+            }
+            projects.retain(|p| p != project);
+            if projects.is_empty() {
+                fs::remove_file(&file).await?;
+                index.projects.remove(file_name);
+                count += 1;
+            } else {
+                info!(
+                    "→ Keeping {file_name}, still used by: {}",
+                    projects.join(", ")
+                );
+            }
+        }
+
+        self.save_index(&index).await?;
+        if count > 0 {
+            info!("✓ Removed {count} cached files of project {project}");
+        } else {
+            warn!("No cached files to remove for project {project}.");
+        }
+        Ok(count)
+    }
+
     pub async fn list_cached_files(&self) -> Result<Vec<PathBuf>> {
         if !self.cache_dir.exists() {
             return Ok(Vec::new());
@@ -85,19 +299,43 @@ impl Downloader {
         Ok(files)
     }
 
-    pub async fn clear_cache(&self) -> Result<usize> {
-        if !self.cache_dir.exists() {
-            warn!("Cache directory does not exist.");
-            return Ok(0);
+    /// APKs pulled from a device and cached in [`APK_CACHE_DIR`].
+    pub async fn list_cached_apks(&self) -> Result<Vec<PathBuf>> {
+        let apk_cache_dir = Path::new(APK_CACHE_DIR);
+        if !apk_cache_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = fs::read_dir(apk_cache_dir).await?;
+        let mut files = Vec::new();
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "apk") {
+                files.push(path);
+            }
         }
 
-        let files = self.list_cached_files().await?;
+        Ok(files)
+    }
+
+    /// Removes the cached prebuilt files, the APKs pulled from a device and the
+    /// APKs decompiled by apktool.
+    pub async fn clear_cache(&self) -> Result<usize> {
+        let mut files = self.list_cached_files().await?;
+        files.extend(self.list_cached_apks().await?);
         let mut count = 0;
 
         for file in &files {
             fs::remove_file(file).await?;
             count += 1;
         }
+        let _ = fs::remove_file(self.index_path()).await;
+        let decompiled_dir = self.decompiled_dir();
+        if decompiled_dir.exists() {
+            fs::remove_dir_all(&decompiled_dir).await?;
+            info!("✓ Removed decompiled APKs: {}", decompiled_dir.display());
+        }
 
         if count > 0 {
             info!("✓ Removed {count} cached files");
@@ -108,40 +346,172 @@ impl Downloader {
         Ok(count)
     }
 
-    pub async fn get_cache_stats(&self) -> Result<CacheStats> {
-        if !self.cache_dir.exists() {
-            return Ok(CacheStats {
-                file_count: 0,
-                total_size: 0,
-                files: Vec::new(),
-            });
-        }
+    /// Removes the least recently used prebuilt files until `incoming` more bytes
+    /// fit into `FRIPACK_CACHE_MAX_BYTES`. Files being downloaded aren't counted.
+    async fn evict_for(&self, incoming: u64) -> Result<()> {
+        let Some(max_bytes) = cache_max_bytes()? else {
+            return Ok(());
+        };
+        let _lock = self.index_lock.lock().await;
 
-        let files = self.list_cached_files().await?;
-        let mut total_size = 0u64;
-        let mut file_info = Vec::new();
+        let mut files = Vec::new();
+        let mut total_size = 0;
+        for path in self.list_cached_files().await? {
+            let metadata = fs::metadata(&path).await?;
+            let last_used = metadata.accessed().or_else(|_| metadata.modified())?;
+            total_size += metadata.len();
+            files.push((last_used, metadata.len(), path));
+        }
+        if total_size + incoming <= max_bytes {
+            return Ok(());
+        }
 
-        for file in &files {
-            let metadata = fs::metadata(file).await?;
-            let size = metadata.len();
-            total_size += size;
-
-            if let Some(filename) = file.file_name().and_then(|n| n.to_str()) {
-                file_info.push(CachedFileInfo {
-                    name: filename.to_string(),
-                    size,
-                    path: file.clone(),
-                });
+        files.sort_by_key(|(last_used, ..)| *last_used);
+        let mut index = self.load_index().await?;
+        for (_, size, path) in files {
+            if total_size + incoming <= max_bytes {
+                break;
             }
+            fs::remove_file(&path).await?;
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            index.projects.remove(file_name.as_ref());
+            info!(
+                "→ Evicted {file_name} ({}) to stay within the cache limit",
+                format_bytes(size)
+            );
+            total_size -= size;
         }
+        self.save_index(&index).await?;
+
+        if total_size + incoming > max_bytes {
+            warn!(
+                "Prebuilt file ({}) is larger than the cache limit ({}), caching it anyway",
+                format_bytes(incoming),
+                format_bytes(max_bytes)
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn get_cache_stats(&self) -> Result<CacheStats> {
+        let (files, total_size) = cached_file_infos(&self.list_cached_files().await?).await?;
+        let (apks, apk_size) = cached_file_infos(&self.list_cached_apks().await?).await?;
 
         Ok(CacheStats {
-            file_count: files.len(),
-            total_size,
-            files: file_info,
+            file_count: files.len() + apks.len(),
+            total_size: total_size + apk_size,
+            files,
+            apks,
         })
     }
 
+    /// Resolves a `fridaVersion` of `latest` or `17.x`/`17.5.x` to the newest
+    /// release with a prebuilt for `platform`. Other versions are returned as is.
+    /// Without network access, the newest matching cached prebuilt is used.
+    pub async fn resolve_frida_version(
+        &self,
+        platform: &PlatformConfig,
+        frida_version: &str,
+    ) -> Result<String> {
+        if !is_version_pattern(frida_version) {
+            return Ok(frida_version.to_string());
+        }
+        let key = format!("{frida_version}/{platform}");
+        if let Some(resolved) = self.resolved_versions.lock().await.get(&key) {
+            return Ok(resolved.clone());
+        }
+
+        let resolved = match self.newest_released_version(platform, frida_version).await {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                let Some(cached) = self.newest_cached_version(platform, frida_version).await?
+                else {
+                    return Err(
+                        e.context(format!("Failed to resolve fridaVersion {frida_version:?}"))
+                    );
+                };
+                warn!("Failed to query releases ({e}), using the newest cached version");
+                cached
+            }
+        };
+        info!("→ Resolved fridaVersion {frida_version:?} to {resolved} for {platform}");
+        self.resolved_versions
+            .lock()
+            .await
+            .insert(key, resolved.clone());
+        Ok(resolved)
+    }
+
+    /// Newest published release matching `pattern` with a prebuilt for `platform`.
+    async fn newest_released_version(
+        &self,
+        platform: &PlatformConfig,
+        pattern: &str,
+    ) -> Result<String> {
+        let response = self
+            .client
+            .get(RELEASES_API_URL)
+            .header("User-Agent", "fripack-downloader")
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            anyhow::bail!("HTTP {}: {RELEASES_API_URL}", response.status());
+        }
+        let releases: Vec<serde_json::Value> = response.json().await?;
+
+        releases
+            .iter()
+            .filter(|release| {
+                !release["draft"].as_bool().unwrap_or(false)
+                    && !release["prerelease"].as_bool().unwrap_or(false)
+            })
+            .filter_map(|release| {
+                let tag = release["tag_name"].as_str()?;
+                let version = parse_version(tag).filter(|_| version_matches(pattern, tag))?;
+                let asset_name = self.get_prebuilt_file_name(platform, tag);
+                release["assets"]
+                    .as_array()?
+                    .iter()
+                    .any(|asset| asset["name"].as_str() == Some(asset_name.as_str()))
+                    .then(|| (version, tag.to_string()))
+            })
+            .max()
+            .map(|(_, tag)| tag)
+            .with_context(|| {
+                format!("No release matching {pattern:?} has a prebuilt for {platform}")
+            })
+    }
+
+    /// Newest cached prebuilt version matching `pattern` for `platform`.
+    async fn newest_cached_version(
+        &self,
+        platform: &PlatformConfig,
+        pattern: &str,
+    ) -> Result<Option<String>> {
+        let suffix = format!("-{platform}.{}", platform.platform.binary_ext());
+        let mut newest = None;
+        let Ok(mut entries) = fs::read_dir(&self.cache_dir).await else {
+            return Ok(None);
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let Some(version) = file_name
+                .strip_prefix("fripack-inject-")
+                .and_then(|name| name.strip_suffix(&suffix))
+            else {
+                continue;
+            };
+            let Some(parsed) = parse_version(version).filter(|_| version_matches(pattern, version))
+            else {
+                continue;
+            };
+            if newest.as_ref().is_none_or(|(newest, _)| parsed > *newest) {
+                newest = Some((parsed, version.to_string()));
+            }
+        }
+        Ok(newest.map(|(_, version)| version))
+    }
+
     pub fn get_prebuilt_file_name(&self, platform: &PlatformConfig, frida_version: &str) -> String {
         format!(
             "fripack-inject-{}-{}.{}",
@@ -153,12 +523,28 @@ impl Downloader {
 
     pub fn get_prebuilt_file_url(&self, platform: &PlatformConfig, frida_version: &str) -> String {
         format!(
-            "https://github.com/FriRebuild/fripack-inject/releases/download/{}/{}",
+            "{}/{}/{}",
+            self.prebuilt_base_url(),
             frida_version,
             self.get_prebuilt_file_name(platform, frida_version)
         )
     }
 
+    /// Checks that the default prebuilt release host can be reached.
+    pub async fn check_reachable(&self) -> Result<()> {
+        let response = self
+            .client
+            .head(RELEASES_URL)
+            .header("User-Agent", "fripack-downloader")
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            anyhow::bail!("HTTP {}", response.status());
+        }
+        Ok(())
+    }
+
     pub async fn download_zygisk_loader(&self, abi: &str) -> Result<Vec<u8>> {
         let loader_filename = format!("fripack-inject-zygisk-loader-{}.so", abi);
 
@@ -193,6 +579,7 @@ impl Downloader {
         );
 
         // Download the file
+        let _permit = self.download_permits.acquire().await?;
         info!(
             "→ Downloading zygisk loader for {}: {}",
             abi, loader_filename
@@ -218,17 +605,34 @@ impl Downloader {
         &self,
         platform: &PlatformConfig,
         frida_version: &str,
+        headers: &HeaderMap,
     ) -> Result<Vec<u8>> {
+        let filename = self.get_prebuilt_file_name(platform, frida_version);
+
         if self.is_file_cached(platform, frida_version).await {
+            self.record_project_use(&filename).await?;
+            self.counters.cache_hits.fetch_add(1, Ordering::Relaxed);
             return self.load_cached_file(platform, frida_version).await;
         }
 
+        validate_prebuilt_base_url(self.prebuilt_base_url())?;
         let url = self.get_prebuilt_file_url(platform, frida_version);
-        let filename = self.get_prebuilt_file_name(platform, frida_version);
 
+        let _permit = self.download_permits.acquire().await?;
         info!("→ Downloading prebuilt file: {filename}");
 
-        let response = self.client.get(&url).send().await?;
+        let mut headers = headers.clone();
+        headers.extend(self.headers.clone());
+        if !headers.is_empty() {
+            let names: Vec<&str> = headers.keys().map(HeaderName::as_str).collect();
+            info!("→ Sending download headers: {}", names.join(", "));
+        }
+        let response = self
+            .client
+            .get(&url)
+            .headers(headers.clone())
+            .send()
+            .await?;
 
         if !response.status().is_success() {
             anyhow::bail!(
@@ -238,6 +642,10 @@ impl Downloader {
             );
         }
 
+        let last_modified = response
+            .headers()
+            .get(reqwest::header::LAST_MODIFIED)
+            .and_then(|value| httpdate::parse_http_date(value.to_str().ok()?).ok());
         let total_size = response.content_length().unwrap_or(0);
         let pb = ProgressBar::new(total_size);
         pb.set_style(
@@ -247,23 +655,207 @@ impl Downloader {
                 .progress_chars("#>-")
         );
 
+        // Stream into a `.part` file that only becomes the cached file once
+        // complete and verified, and is removed if the download fails
+        let part = if self.save_downloads {
+            self.ensure_cache_dir().await?;
+            tempfile::Builder::new()
+                .prefix(&format!("{filename}."))
+                .suffix(".part")
+                .tempfile_in(&self.cache_dir)?
+        } else {
+            tempfile::Builder::new().suffix(".part").tempfile()?
+        };
+        let mut part_file = fs::File::from_std(part.reopen()?);
+        let mut hasher = Sha256::new();
         let mut downloaded = 0u64;
         let mut stream = response.bytes_stream();
-        let mut data = Vec::new();
 
         while let Some(chunk) = stream.next().await {
             let chunk = chunk?;
-            data.extend_from_slice(&chunk);
+            part_file.write_all(&chunk).await?;
+            hasher.update(&chunk);
             downloaded += chunk.len() as u64;
             pb.set_position(downloaded);
         }
+        part_file.flush().await?;
+        drop(part_file);
 
         pb.finish_with_message("Download complete!");
+        self.counters.downloads.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .bytes_downloaded
+            .fetch_add(downloaded, Ordering::Relaxed);
+
+        if self.verify_checksums {
+            let sha256: String = hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect();
+            self.verify_checksum(frida_version, &filename, &sha256, &headers)
+                .await?;
+        }
+        // A rejected `.part` file is deleted when `part` is dropped
+        if let Some(verify_command) = &self.verify_command {
+            run_verify_command(verify_command, &filename, part.path()).await?;
+        }
+
+        if !self.save_downloads {
+            return Ok(fs::read(part.path()).await?);
+        }
+        let cache_path = self.get_cache_file_path(platform, frida_version);
+        self.evict_for(downloaded).await?;
+        part.persist(&cache_path)?;
+        // Date the cached file like the upstream asset, so its age doesn't
+        // depend on when the cache was filled
+        if let Some(last_modified) = last_modified {
+            filetime::set_file_mtime(
+                &cache_path,
+                filetime::FileTime::from_system_time(last_modified),
+            )?;
+        }
+        info!("→ Cached to: {}", cache_path.display());
+        self.record_project_use(&filename).await?;
+
+        Ok(fs::read(&cache_path).await?)
+    }
+
+    /// Checks the SHA-256 (hex) of a download against the `{filename}.sha256`
+    /// asset of the release, or its `checksums.txt`. A release publishing
+    /// neither is only warned about.
+    async fn verify_checksum(
+        &self,
+        frida_version: &str,
+        filename: &str,
+        actual: &str,
+        headers: &HeaderMap,
+    ) -> Result<()> {
+        let release_url = format!("{}/{frida_version}", self.prebuilt_base_url());
+        let mut expected = None;
+        for checksum_file in [format!("{filename}.sha256"), "checksums.txt".to_string()] {
+            let url = format!("{release_url}/{checksum_file}");
+            let response = self
+                .client
+                .get(&url)
+                .headers(headers.clone())
+                .send()
+                .await?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                continue;
+            }
+            if !response.status().is_success() {
+                anyhow::bail!(
+                    "Failed to download checksum: HTTP {}: {}",
+                    response.status(),
+                    url
+                );
+            }
+            let checksums = response.text().await?;
+            expected = Some(
+                parse_checksum(&checksums, filename)
+                    .with_context(|| format!("No checksum for {filename} in {url}"))?,
+            );
+            break;
+        }
+
+        let Some(expected) = expected else {
+            warn!("No checksum published for {filename}, skipping verification");
+            return Ok(());
+        };
+        if actual != expected {
+            anyhow::bail!(
+                "Checksum mismatch for {filename}: expected {expected}, got {actual}. The download is corrupted or was tampered with (pass --no-verify to skip this check)"
+            );
+        }
+        info!("✓ Verified SHA-256 of {filename}");
+        Ok(())
+    }
+}
+
+/// Runs the `verifyCommand` hook on a downloaded prebuilt file.
+async fn run_verify_command(verify_command: &str, filename: &str, file: &Path) -> Result<()> {
+    // `{file}` is a temporary `.part` file, `{name}` the prebuilt's file name
+    let cmd = verify_command
+        .replace("{file}", &shell_quote(&file.to_string_lossy()))
+        .replace("{name}", &shell_quote(filename));
+    info!("→ Verifying {filename}: {cmd}");
+    let output = if cfg!(target_os = "windows") {
+        Command::new("cmd").arg("/C").arg(&cmd).output().await
+    } else {
+        Command::new("sh").arg("-c").arg(&cmd).output().await
+    }
+    .context("Failed to run verifyCommand")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "verifyCommand rejected {filename} ({}), the download was discarded: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    info!("✓ verifyCommand accepted {filename}");
+    Ok(())
+}
+
+/// Whether `frida_version` is resolved by [`Downloader::resolve_frida_version`].
+pub fn is_version_pattern(frida_version: &str) -> bool {
+    frida_version == "latest" || frida_version.ends_with(".x")
+}
 
-        self.save_to_cache(platform, frida_version, &data).await?;
+/// Whether `version` is matched by `latest` or a `17.x`-style prefix pattern.
+fn version_matches(pattern: &str, version: &str) -> bool {
+    pattern == "latest" || version.starts_with(pattern.trim_end_matches('x'))
+}
+
+/// Numeric components of a `17.5.1` version, `None` for other tags.
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version.split('.').map(|part| part.parse().ok()).collect()
+}
 
-        Ok(data)
+/// Checks that `url` is an absolute http(s) URL the prebuilt paths can be appended to.
+pub fn validate_prebuilt_base_url(url: &str) -> Result<()> {
+    let parsed =
+        reqwest::Url::parse(url).with_context(|| format!("Invalid prebuilt base URL: {url}"))?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.cannot_be_a_base() {
+        anyhow::bail!("Invalid prebuilt base URL: {url} (expected an http(s) URL)");
     }
+    Ok(())
+}
+
+/// Finds the SHA-256 of `filename` in `sha256sum` output, or the single hash of
+/// a `.sha256` file.
+fn parse_checksum(checksums: &str, filename: &str) -> Option<String> {
+    let entries: Vec<(&str, Option<&str>)> = checksums
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let hash = fields.next()?;
+            // `sha256sum` marks binary mode with a leading `*`
+            let name = fields.next().map(|name| name.trim_start_matches('*'));
+            Some((hash, name))
+        })
+        .collect();
+    let hash = match entries.as_slice() {
+        [(hash, None)] => hash,
+        entries => &entries.iter().find(|(_, name)| *name == Some(filename))?.0,
+    };
+    (hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+        .then(|| hash.to_ascii_lowercase())
+}
+
+/// Parses `(name, value)` pairs into headers, marked sensitive so their values
+/// stay out of debug output.
+pub fn parse_headers(headers: impl IntoIterator<Item = (String, String)>) -> Result<HeaderMap> {
+    let mut header_map = HeaderMap::new();
+    for (name, value) in headers {
+        let header_name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| anyhow::anyhow!("Invalid header name: {name:?}"))?;
+        let mut header_value = HeaderValue::from_str(value.trim())
+            .map_err(|_| anyhow::anyhow!("Invalid value for header {header_name}"))?;
+        header_value.set_sensitive(true);
+        header_map.insert(header_name, header_value);
+    }
+    Ok(header_map)
 }
 
 impl Default for Downloader {
@@ -277,11 +869,54 @@ fn get_cache_dir() -> PathBuf {
     home_dir.join(".fripack")
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadStats {
+    pub cache_hits: usize,
+    pub downloads: usize,
+    pub bytes_downloaded: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct CacheStats {
     pub file_count: usize,
     pub total_size: u64,
+    /// Prebuilt files.
     pub files: Vec<CachedFileInfo>,
+    /// APKs pulled from a device.
+    pub apks: Vec<CachedFileInfo>,
+}
+
+/// Size limit of the prebuilt cache, from `FRIPACK_CACHE_MAX_BYTES` (a byte
+/// count or a size such as `2GB`).
+pub fn cache_max_bytes() -> Result<Option<u64>> {
+    match std::env::var("FRIPACK_CACHE_MAX_BYTES") {
+        Ok(max_bytes) if !max_bytes.is_empty() => Ok(Some(
+            parse_size(&max_bytes).context("Invalid FRIPACK_CACHE_MAX_BYTES")?,
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Sizes of `files`, and their total size.
+async fn cached_file_infos(files: &[PathBuf]) -> Result<(Vec<CachedFileInfo>, u64)> {
+    let mut total_size = 0u64;
+    let mut file_info = Vec::new();
+
+    for file in files {
+        let metadata = fs::metadata(file).await?;
+        let size = metadata.len();
+        total_size += size;
+
+        if let Some(filename) = file.file_name().and_then(|n| n.to_str()) {
+            file_info.push(CachedFileInfo {
+                name: filename.to_string(),
+                size,
+                path: file.clone(),
+            });
+        }
+    }
+
+    Ok((file_info, total_size))
 }
 
 #[derive(Debug, Clone)]
@@ -290,3 +925,50 @@ pub struct CachedFileInfo {
     pub size: u64,
     pub path: PathBuf,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    /// Serves `body` with a `Last-Modified` header to every request on a local port.
+    async fn serve_prebuilt(body: &'static [u8], last_modified: &'static str) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 4096];
+                let _ = stream.read(&mut request).await;
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nLast-Modified: {last_modified}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream.write_all(head.as_bytes()).await;
+                let _ = stream.write_all(body).await;
+            }
+        });
+        format!("http://{address}")
+    }
+
+    #[tokio::test]
+    async fn cached_prebuilt_is_dated_like_the_upstream_asset() {
+        let base_url = serve_prebuilt(b"prebuilt", "Wed, 21 Oct 2015 07:28:00 GMT").await;
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut downloader = Downloader::new()
+            .with_prebuilt_base_url(base_url)
+            .without_checksum_verification();
+        downloader.cache_dir = cache_dir.path().to_path_buf();
+
+        let platform = PlatformConfig::from_str("android-arm64".to_string()).unwrap();
+        let data = downloader
+            .download_prebuilt_file(&platform, "17.5.1", &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(data, b"prebuilt");
+
+        let cache_path = downloader.get_cache_file_path(&platform, "17.5.1");
+        let mtime = std::fs::metadata(&cache_path).unwrap().modified().unwrap();
+        let expected = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(mtime, expected);
+    }
+}
@@ -0,0 +1,73 @@
+use log::info;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::process::Command;
+
+/// Whether `--explain` was given.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Arguments whose value is a password, e.g. apksigner's `--ks-pass pass:...`.
+const PASSWORD_FLAGS: &[&str] = &["--ks-pass", "--key-pass", "-storepass", "-keypass"];
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub trait Explain {
+    /// Logs the command as a copy-pasteable shell line if `--explain` was given.
+    /// Call it right before spawning the command.
+    fn explain(&mut self) -> &mut Self;
+}
+
+impl Explain for Command {
+    fn explain(&mut self) -> &mut Self {
+        if ENABLED.load(Ordering::Relaxed) {
+            info!("$ {}", command_line(self));
+        }
+        self
+    }
+}
+
+/// The command as a shell line, with the environment variables set for it and
+/// passwords redacted.
+fn command_line(command: &Command) -> String {
+    let command = command.as_std();
+    let mut words = Vec::new();
+    for (name, value) in command.get_envs() {
+        if let Some(value) = value {
+            words.push(format!(
+                "{}={}",
+                name.to_string_lossy(),
+                quote(&value.to_string_lossy())
+            ));
+        }
+    }
+    words.push(quote(&command.get_program().to_string_lossy()));
+
+    let mut redact_next = false;
+    for arg in command.get_args() {
+        let arg = arg.to_string_lossy();
+        words.push(if redact_next {
+            // Keep apksigner's `pass:` so the line shows the form it expects
+            match arg.strip_prefix("pass:") {
+                Some(_) => quote("pass:<redacted>"),
+                None => quote("<redacted>"),
+            }
+        } else {
+            quote(&arg)
+        });
+        redact_next = PASSWORD_FLAGS.contains(&arg.as_ref());
+    }
+    words.join(" ")
+}
+
+/// Quotes `word` for the shell if it contains anything but plain characters.
+fn quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./:=+,@%".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.to_string()
+    } else if cfg!(target_os = "windows") {
+        format!("\"{}\"", word.replace('"', "\\\""))
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::{collections::BTreeMap, path::Path, process::Stdio, time::Duration};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+
+use crate::config::AttachConfig;
+use crate::explain::Explain;
+
+/// How long a new session is watched for frida exiting, which it does right
+/// away when it can't spawn or attach.
+const ATTACH_GRACE_PERIOD: Duration = Duration::from_secs(3);
+
+/// The running `frida` session of each watched target, for `attach`.
+static SESSIONS: tokio::sync::Mutex<BTreeMap<String, Child>> =
+    tokio::sync::Mutex::const_new(BTreeMap::new());
+
+/// Creates a `frida` command for the USB device, or `device` (a serial) if given.
+pub fn frida_command(device: Option<&str>) -> Result<Command> {
+    let frida = which::which("frida")
+        .context("frida not found in PATH, attach needs frida-tools (pip install frida-tools)")?;
+    let mut command = Command::new(frida);
+    match device {
+        Some(device) => command.arg("-D").arg(device),
+        None => command.arg("-U"),
+    };
+    Ok(command)
+}
+
+/// Ends `target`'s previous `frida` session, if any, and starts a new one
+/// loading `script` into the process selected by `attach`. The session keeps
+/// running in the background, with its output logged, until the next call or
+/// [`detach_all`].
+pub async fn reattach(
+    target: &str,
+    attach: &AttachConfig,
+    device: Option<&str>,
+    script: &Path,
+) -> Result<()> {
+    let mut sessions = SESSIONS.lock().await;
+    if let Some(session) = sessions.remove(target) {
+        end_session(target, session).await;
+    }
+
+    info!("→ Attaching frida to {}...", attach.target);
+    let mut session = frida_command(device)?
+        .args(attach.frida_args())
+        .arg("-l")
+        .arg(script)
+        // The REPL quits at the end of its input, so stdin is kept open
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .explain()
+        .spawn()
+        .context("Failed to run frida")?;
+    let stdout = session
+        .stdout
+        .take()
+        .context("Failed to read frida output")?;
+    let stderr = session
+        .stderr
+        .take()
+        .context("Failed to read frida output")?;
+
+    if let Ok(status) = tokio::time::timeout(ATTACH_GRACE_PERIOD, session.wait()).await {
+        let status = status?;
+        let mut output = read_lines(stdout).await;
+        output.extend(read_lines(stderr).await);
+        anyhow::bail!(
+            "frida failed to attach to {} ({status}): {}",
+            attach.target,
+            output.join("\n").trim()
+        );
+    }
+
+    tokio::spawn(forward_lines(stdout, false));
+    tokio::spawn(forward_lines(stderr, true));
+    info!("✓ frida attached to {}", attach.target);
+    sessions.insert(target.to_string(), session);
+    Ok(())
+}
+
+/// Ends every session [`reattach`] started.
+pub async fn detach_all() {
+    let sessions = std::mem::take(&mut *SESSIONS.lock().await);
+    for (target, session) in sessions {
+        end_session(&target, session).await;
+    }
+}
+
+/// Kills `target`'s `session`, reporting if it already ended on its own, e.g.
+/// because the process it was attached to died.
+async fn end_session(target: &str, mut session: Child) {
+    match session.try_wait() {
+        Ok(Some(status)) => warn!("frida session of {target} had ended ({status})"),
+        _ => {
+            info!("→ Detaching frida from {target}...");
+            let _ = session.kill().await;
+        }
+    }
+}
+
+async fn read_lines(output: impl AsyncRead + Unpin) -> Vec<String> {
+    let mut lines = BufReader::new(output).lines();
+    let mut read = Vec::new();
+    while let Ok(Some(line)) = lines.next_line().await {
+        read.push(line);
+    }
+    read
+}
+
+/// Logs frida's output, e.g. the script's `console.log` and its errors.
+async fn forward_lines(output: impl AsyncRead + Unpin, is_stderr: bool) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if is_stderr {
+            warn!("[frida] {line}");
+        } else {
+            info!("[frida] {line}");
+        }
+    }
+}
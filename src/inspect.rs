@@ -0,0 +1,474 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::path::PathBuf;
+use tokio::fs;
+
+use crate::binary::{
+    decompress_embedded_data, read_embedded_data, zstd_dict_id, BinaryProcessor, Compression,
+    EmbeddedConfig, BASE64_ENCODING_FLAG,
+};
+use crate::builder::{sha256_hex, EmbeddedConfigData, Mode, EMBEDDED_DATA_VERSION};
+
+/// Reads the `EmbeddedConfig` of a fripack-built binary and decodes its data.
+async fn read_config_data(
+    file: &PathBuf,
+    zstd_dict: Option<&PathBuf>,
+) -> Result<(EmbeddedConfig, EmbeddedConfigData)> {
+    let data = fs::read(file)
+        .await
+        .with_context(|| format!("Failed to read {}", file.display()))?;
+    let (config, embedded) = read_embedded_data(&data)?;
+    let version = config.version;
+    info!("→ Found embedded config version {version}");
+
+    let dict = match zstd_dict {
+        Some(path) => Some(fs::read(path).await?),
+        None => None,
+    };
+    let recorded_dict_id = config.zstd_dict_id;
+    if recorded_dict_id != 0 {
+        let Some(dict) = &dict else {
+            anyhow::bail!(
+                "{} was built with zstd dictionary id {recorded_dict_id}, pass it with --zstd-dict",
+                file.display()
+            );
+        };
+        let dict_id = zstd_dict_id(dict)?;
+        if dict_id != recorded_dict_id {
+            anyhow::bail!(
+                "{} was built with zstd dictionary id {recorded_dict_id}, but the given dictionary has id {dict_id}",
+                file.display()
+            );
+        }
+    }
+    let decompressed = decompress_embedded_data(config.compression, &embedded, dict.as_deref())?;
+
+    let config_data: EmbeddedConfigData =
+        serde_json::from_slice(&decompressed).context("Failed to parse embedded config data")?;
+    if let Some(runtime_config) = &config_data.runtime_config {
+        info!(
+            "→ Runtime config: {}",
+            serde_json::to_string(runtime_config)?
+        );
+    }
+    Ok((config, config_data))
+}
+
+/// Recomputes the script hash, returning `None` if the binary doesn't record one.
+fn script_hash_matches(config_data: &EmbeddedConfigData) -> Option<bool> {
+    let expected = config_data.script_sha256.as_ref()?;
+    let js_content = config_data.js_content.as_deref().unwrap_or_default();
+    Some(sha256_hex(js_content.as_bytes()) == *expected)
+}
+
+pub async fn extract(
+    file: PathBuf,
+    output: Option<PathBuf>,
+    zstd_dict: Option<PathBuf>,
+) -> Result<()> {
+    let (_, config_data) = read_config_data(&file, zstd_dict.as_ref()).await?;
+    if script_hash_matches(&config_data) == Some(false) {
+        warn!(
+            "Embedded script does not match its recorded SHA-256, it was modified after the build"
+        );
+    }
+
+    let Some(js_content) = config_data.js_content else {
+        info!(
+            "Nothing to extract: {} loads its script from a path ({:?} mode)",
+            file.display(),
+            config_data.mode
+        );
+        return Ok(());
+    };
+
+    match output {
+        Some(output) => {
+            fs::write(&output, js_content).await?;
+            info!("✓ Extracted embedded script to: {}", output.display());
+        }
+        None => print!("{js_content}"),
+    }
+
+    Ok(())
+}
+
+/// Name of an [`EmbeddedConfig::compression`] id, without the base64 flag.
+fn compression_name(compression: u8) -> &'static str {
+    match compression & !BASE64_ENCODING_FLAG {
+        0 => "none",
+        1 => "xz",
+        2 => "zstd",
+        3 => "lzma",
+        _ => "unknown",
+    }
+}
+
+/// Prints the embedded config of a built binary, optionally writing the
+/// embedded script to `extract`.
+pub async fn inspect(
+    file: PathBuf,
+    extract: Option<PathBuf>,
+    zstd_dict: Option<PathBuf>,
+) -> Result<()> {
+    let (config, config_data) = read_config_data(&file, zstd_dict.as_ref()).await?;
+    let (version, data_size, data_offset, compression) = (
+        config.version,
+        config.data_size,
+        config.data_offset,
+        config.compression,
+    );
+
+    println!("{}", file.display());
+    println!("EmbeddedConfig:");
+    println!("  version: {version}");
+    println!("  dataSize: {data_size}");
+    println!("  dataOffset: {data_offset:#x}");
+    println!(
+        "  compression: {compression} ({}{})",
+        compression_name(compression),
+        if compression & BASE64_ENCODING_FLAG != 0 {
+            ", base64"
+        } else {
+            ""
+        }
+    );
+    println!("  dataXz: {}", compression & !BASE64_ENCODING_FLAG == 1);
+    if version >= 4 {
+        let zstd_dict_id = config.zstd_dict_id;
+        println!("  zstdDictId: {zstd_dict_id}");
+    }
+
+    println!("EmbeddedConfigData:");
+    println!("  version: {}", config_data.version);
+    println!("  mode: {:?}", config_data.mode);
+    if let Some(runtime) = &config_data.runtime {
+        println!("  runtime: {}", serde_json::to_value(runtime)?);
+    }
+    if let Some(js_filepath) = &config_data.js_filepath {
+        println!("  jsFilepath: {js_filepath}");
+    }
+    if let Some(watch_path) = &config_data.watch_path {
+        println!("  watchPath: {watch_path}");
+    }
+    match &config_data.js_content {
+        Some(js_content) => {
+            let hash = match script_hash_matches(&config_data) {
+                Some(true) => "matches recorded SHA-256",
+                Some(false) => "does NOT match recorded SHA-256",
+                None => "no recorded SHA-256",
+            };
+            println!(
+                "  script: {} bytes, {} ({hash})",
+                js_content.len(),
+                if compression & !BASE64_ENCODING_FLAG == 0 {
+                    "uncompressed"
+                } else {
+                    "compressed"
+                }
+            );
+        }
+        None => println!("  script: not embedded"),
+    }
+    if let Some(scripts) = &config_data.scripts {
+        println!("  scripts:");
+        for (name, content) in scripts {
+            println!("    {name}: {} bytes", content.len());
+        }
+    }
+
+    if let Some(extract) = extract {
+        let Some(js_content) = config_data.js_content else {
+            anyhow::bail!(
+                "Nothing to extract: {} loads its script from a path ({:?} mode)",
+                file.display(),
+                config_data.mode
+            );
+        };
+        fs::write(&extract, js_content).await?;
+        info!("✓ Extracted embedded script to: {}", extract.display());
+    }
+
+    Ok(())
+}
+
+/// Checks that the embedded script matches the hash recorded at build time.
+pub async fn verify(file: PathBuf, zstd_dict: Option<PathBuf>) -> Result<()> {
+    let (_, config_data) = read_config_data(&file, zstd_dict.as_ref()).await?;
+
+    if config_data.js_content.is_none() {
+        info!(
+            "Nothing to verify: {} loads its script from a path ({:?} mode)",
+            file.display(),
+            config_data.mode
+        );
+        return Ok(());
+    }
+    match script_hash_matches(&config_data) {
+        Some(true) => info!(
+            "✓ Embedded script matches its SHA-256: {}",
+            config_data.script_sha256.unwrap_or_default()
+        ),
+        Some(false) => anyhow::bail!(
+            "Embedded script of {} does not match its recorded SHA-256",
+            file.display()
+        ),
+        None => warn!(
+            "{} records no script hash (built by an older fripack), nothing to verify",
+            file.display()
+        ),
+    }
+
+    Ok(())
+}
+
+/// Replaces the embedded script of a fripack-built binary, keeping its other
+/// embedded settings and its compression (unless `xz`), without downloading or
+/// processing the prebuilt again.
+pub async fn reembed(
+    file: PathBuf,
+    entry: PathBuf,
+    xz: bool,
+    output: Option<PathBuf>,
+    zstd_dict: Option<PathBuf>,
+) -> Result<()> {
+    let (config, mut config_data) = read_config_data(&file, zstd_dict.as_ref()).await?;
+    let entry_data = fs::read(&entry)
+        .await
+        .with_context(|| format!("Failed to read {}", entry.display()))?;
+    let js_content = String::from_utf8_lossy(&entry_data).to_string();
+
+    config_data.version = EMBEDDED_DATA_VERSION;
+    config_data.mode = Mode::EmbedJs;
+    config_data.js_filepath = Some(entry.to_string_lossy().to_string());
+    config_data.script_sha256 = Some(sha256_hex(js_content.as_bytes()));
+    config_data.js_content = Some(js_content);
+    config_data.watch_path = None;
+
+    let mut processor = BinaryProcessor::new(fs::read(&file).await?)?;
+    processor.set_base64_encoding(config.compression & BASE64_ENCODING_FLAG != 0);
+    let compression = if xz {
+        Compression::Xz
+    } else {
+        original_compression(&config, zstd_dict.as_ref()).await?
+    };
+    info!("→ Embedding {}", entry.display());
+    processor.replace_embedded_config_data(
+        serde_json::to_string(&config_data)?.as_bytes(),
+        &compression,
+    )?;
+
+    let output = output.unwrap_or(file);
+    fs::write(&output, processor.into_data()).await?;
+    info!("✓ Re-embedded script into: {}", output.display());
+    Ok(())
+}
+
+/// The compression `config` records, with the dictionary it was built with.
+/// [`read_config_data`] already checked that `zstd_dict` has the recorded id.
+async fn original_compression(
+    config: &EmbeddedConfig,
+    zstd_dict: Option<&PathBuf>,
+) -> Result<Compression> {
+    let zstd_dict_id = config.zstd_dict_id;
+    Ok(match config.compression & !BASE64_ENCODING_FLAG {
+        0 => Compression::None,
+        1 => Compression::Xz,
+        2 if zstd_dict_id != 0 => {
+            let zstd_dict = zstd_dict.context("Missing zstd dictionary")?;
+            Compression::Zstd(Some(fs::read(zstd_dict).await?))
+        }
+        2 => Compression::Zstd(None),
+        3 => Compression::Lzma,
+        other => anyhow::bail!("Unsupported compression {other}"),
+    })
+}
+
+/// Compares the embedded configs of two binaries and diffs their scripts.
+pub async fn diff(file_a: PathBuf, file_b: PathBuf, zstd_dict: Option<PathBuf>) -> Result<()> {
+    let (config_a, data_a) = read_config_data(&file_a, zstd_dict.as_ref()).await?;
+    let (config_b, data_b) = read_config_data(&file_b, zstd_dict.as_ref()).await?;
+
+    println!("--- {}", file_a.display());
+    println!("+++ {}", file_b.display());
+
+    let header_fields = |config: &EmbeddedConfig| {
+        let (version, data_size, data_offset, compression, zstd_dict_id) = (
+            config.version,
+            config.data_size,
+            config.data_offset,
+            config.compression,
+            config.zstd_dict_id,
+        );
+        vec![
+            ("version".to_string(), version.to_string()),
+            ("compression".to_string(), compression.to_string()),
+            ("dataSize".to_string(), data_size.to_string()),
+            ("dataOffset".to_string(), data_offset.to_string()),
+            ("zstdDictId".to_string(), zstd_dict_id.to_string()),
+        ]
+    };
+    println!("\nEmbeddedConfig:");
+    print_field_diff(&header_fields(&config_a), &header_fields(&config_b));
+
+    let data_fields = |data: &EmbeddedConfigData| -> Result<Vec<(String, String)>> {
+        let serde_json::Value::Object(fields) = serde_json::to_value(data)? else {
+            anyhow::bail!("EmbeddedConfigData is not an object");
+        };
+        Ok(fields
+            .into_iter()
+            .filter(|(name, _)| name != "js_content")
+            .map(|(name, value)| (name, value.to_string()))
+            .collect())
+    };
+    println!("\nEmbeddedConfigData:");
+    print_field_diff(&data_fields(&data_a)?, &data_fields(&data_b)?);
+
+    let script_a = data_a.js_content.unwrap_or_default();
+    let script_b = data_b.js_content.unwrap_or_default();
+    println!("\nScript:");
+    if script_a == script_b {
+        println!("  (identical, {} bytes)", script_a.len());
+    } else {
+        print_line_diff(&script_a, &script_b);
+    }
+
+    Ok(())
+}
+
+/// Prints `name: a` for equal fields and `name: a -> b` (marked with `*`) for differing ones.
+fn print_field_diff(fields_a: &[(String, String)], fields_b: &[(String, String)]) {
+    let mut seen = std::collections::HashSet::new();
+    let names = fields_a.iter().chain(fields_b).map(|(name, _)| name);
+    for name in names.filter(|name| seen.insert(*name)) {
+        let value = |fields: &[(String, String)]| {
+            fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map_or("-".to_string(), |(_, value)| value.clone())
+        };
+        let (a, b) = (value(fields_a), value(fields_b));
+        if a == b {
+            println!("    {name}: {a}");
+        } else {
+            println!("  * {name}: {a} -> {b}");
+        }
+    }
+}
+
+/// Above this many compared line pairs, differing blocks are shown as a whole
+/// instead of computing a minimal diff.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Prints a unified diff (3 lines of context) of two texts.
+fn print_line_diff(a: &str, b: &str) {
+    let (a, b): (Vec<&str>, Vec<&str>) = (a.lines().collect(), b.lines().collect());
+
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (mid_a, mid_b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    // (tag, line) for every line, tags being ' ', '-' and '+'
+    let mut ops: Vec<(char, &str)> = a[..prefix].iter().map(|l| (' ', *l)).collect();
+    if mid_a.len() * mid_b.len() > MAX_DIFF_CELLS {
+        ops.extend(mid_a.iter().map(|l| ('-', *l)));
+        ops.extend(mid_b.iter().map(|l| ('+', *l)));
+    } else {
+        // Longest common subsequence table of the differing middle part
+        let mut lcs = vec![vec![0u32; mid_b.len() + 1]; mid_a.len() + 1];
+        for i in (0..mid_a.len()).rev() {
+            for j in (0..mid_b.len()).rev() {
+                lcs[i][j] = if mid_a[i] == mid_b[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < mid_a.len() || j < mid_b.len() {
+            if i < mid_a.len() && j < mid_b.len() && mid_a[i] == mid_b[j] {
+                ops.push((' ', mid_a[i]));
+                (i, j) = (i + 1, j + 1);
+            } else if j == mid_b.len() || (i < mid_a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+                ops.push(('-', mid_a[i]));
+                i += 1;
+            } else {
+                ops.push(('+', mid_b[j]));
+                j += 1;
+            }
+        }
+    }
+    ops.extend(a[a.len() - suffix..].iter().map(|l| (' ', *l)));
+
+    // Group changes into hunks with 3 lines of context
+    const CONTEXT: usize = 3;
+    let changed: Vec<usize> = (0..ops.len()).filter(|&k| ops[k].0 != ' ').collect();
+    let mut k = 0;
+    while k < changed.len() {
+        let start = changed[k].saturating_sub(CONTEXT);
+        let mut end = changed[k];
+        while k < changed.len() && changed[k] <= end + 2 * CONTEXT {
+            end = changed[k];
+            k += 1;
+        }
+        let end = (end + CONTEXT + 1).min(ops.len());
+
+        let line_a = 1 + ops[..start].iter().filter(|(tag, _)| *tag != '+').count();
+        let line_b = 1 + ops[..start].iter().filter(|(tag, _)| *tag != '-').count();
+        let len_a = ops[start..end]
+            .iter()
+            .filter(|(tag, _)| *tag != '+')
+            .count();
+        let len_b = ops[start..end]
+            .iter()
+            .filter(|(tag, _)| *tag != '-')
+            .count();
+        println!("@@ -{line_a},{len_a} +{line_b},{len_b} @@");
+        for (tag, line) in &ops[start..end] {
+            println!("{tag}{line}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLACEHOLDER_SO: &[u8] = include_bytes!("../tests/fixtures/libplaceholder.so");
+
+    #[tokio::test]
+    async fn reembed_keeps_the_compression() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_data = serde_json::json!({
+            "version": EMBEDDED_DATA_VERSION,
+            "mode": Mode::EmbedJs,
+            "js_content": "console.log('old');",
+        });
+        let mut processor = BinaryProcessor::new(PLACEHOLDER_SO.to_vec()).unwrap();
+        processor
+            .add_embedded_config_data(config_data.to_string().as_bytes(), &Compression::Zstd(None))
+            .unwrap();
+        let file = dir.path().join("libagent.so");
+        std::fs::write(&file, processor.into_data()).unwrap();
+        let entry = dir.path().join("new.js");
+        std::fs::write(&entry, "console.log('new');").unwrap();
+
+        reembed(file.clone(), entry, false, None, None)
+            .await
+            .unwrap();
+
+        let (config, config_data) = read_config_data(&file, None).await.unwrap();
+        let compression = config.compression;
+        assert_eq!(compression, Compression::Zstd(None).id());
+        assert_eq!(
+            config_data.js_content.as_deref(),
+            Some("console.log('new');")
+        );
+    }
+}
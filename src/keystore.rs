@@ -0,0 +1,87 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::path::Path;
+use tokio::process::Command;
+
+use crate::config::KeychainEntry;
+use crate::explain::Explain;
+
+/// Distinguished name of the Android debug keystore.
+pub const DEFAULT_DNAME: &str = "CN=Android Debug,O=Android,C=US";
+
+/// Creates a keystore with `keytool -genkeypair` and prints the `sign` block to use it.
+///
+/// Passwords that aren't given are prompted for by keytool itself.
+pub async fn init(
+    path: &Path,
+    alias: &str,
+    validity_days: u32,
+    dname: &str,
+    store_pass: Option<&str>,
+    key_pass: Option<&str>,
+) -> Result<()> {
+    let keytool = which::which("keytool")
+        .context("keytool not found on PATH; it ships with the JDK (e.g. install OpenJDK)")?;
+
+    if path.exists() {
+        anyhow::bail!(
+            "Keystore already exists: {} (remove it or pass another --path)",
+            path.display()
+        );
+    }
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    info!("→ Generating keystore: {}", path.display());
+    let mut command = Command::new(keytool);
+    command
+        .arg("-genkeypair")
+        .arg("-keystore")
+        .arg(path)
+        .args(["-alias", alias])
+        .args(["-keyalg", "RSA", "-keysize", "2048"])
+        .args(["-validity", &validity_days.to_string()])
+        .args(["-dname", dname]);
+    if let Some(store_pass) = store_pass {
+        command.args(["-storepass", store_pass]);
+    }
+    if let Some(key_pass) = key_pass {
+        command.args(["-keypass", key_pass]);
+    }
+    // Inherit stdio so keytool can prompt for missing passwords
+    let status = command.explain().status().await?;
+    if !status.success() {
+        anyhow::bail!("keytool failed with {status}");
+    }
+    info!("✓ Created keystore: {}", path.display());
+
+    let mut sign = serde_json::json!({
+        "keystore": path.to_string_lossy(),
+        "keystorePass": store_pass.unwrap_or("<keystore password>"),
+        "keystoreAlias": alias,
+    });
+    if let Some(key_pass) = key_pass.filter(|key_pass| Some(*key_pass) != store_pass) {
+        sign["keyPass"] = key_pass.into();
+    }
+    info!("Add this to your target in fripack.json:");
+    println!("\"sign\": {}", serde_json::to_string_pretty(&sign)?);
+    Ok(())
+}
+
+/// Reads a password from the OS keychain, e.g. `keystorePassKeychain`.
+pub fn keychain_password(entry: &KeychainEntry) -> Result<String> {
+    let KeychainEntry { service, account } = entry;
+    let keychain_entry = keyring::Entry::new(service, account).with_context(|| {
+        format!("Invalid keychain entry (service {service:?}, account {account:?})")
+    })?;
+    match keychain_entry.get_password() {
+        Ok(password) => Ok(password),
+        Err(keyring::Error::NoEntry) => anyhow::bail!(
+            "No keychain entry for service {service:?}, account {account:?}; add the password to your OS keychain first"
+        ),
+        Err(e) => anyhow::bail!(
+            "Failed to read the keychain entry for service {service:?}, account {account:?} (is access denied or the keychain locked?): {e}"
+        ),
+    }
+}
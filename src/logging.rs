@@ -0,0 +1,71 @@
+use anyhow::Result;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+/// Logs to the console as configured and additionally tees every record into a
+/// file, with timestamps. The download progress bar writes to the terminal
+/// directly, so it never ends up in the file.
+struct TeeLogger {
+    console: env_logger::Logger,
+    file: Mutex<File>,
+}
+
+impl log::Log for TeeLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.console.matches(record) {
+            return;
+        }
+        self.console.log(record);
+
+        let mut file = self.file.lock().unwrap();
+        let _ = writeln!(
+            file,
+            "[{} {} {}] {}",
+            humantime::format_rfc3339_seconds(SystemTime::now()),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+        let _ = self.file.lock().unwrap().flush();
+    }
+}
+
+pub fn init(log_file: Option<&Path>, append: bool) -> Result<()> {
+    let console = env_logger::Builder::from_default_env()
+        .format_timestamp(None)
+        .filter_level(log::LevelFilter::Info)
+        .build();
+    let max_level = console.filter();
+
+    match log_file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(append)
+                .truncate(!append)
+                .open(path)?;
+            log::set_boxed_logger(Box::new(TeeLogger {
+                console,
+                file: Mutex::new(file),
+            }))?;
+        }
+        None => log::set_boxed_logger(Box::new(console))?,
+    }
+    log::set_max_level(max_level);
+
+    Ok(())
+}
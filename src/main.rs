@@ -7,22 +7,38 @@ use notify_debouncer_full::{
 };
 use std::{
     cell::RefCell,
+    collections::HashMap,
     path::{Path, PathBuf},
     rc::Rc,
     sync::{Arc, Mutex},
     time::Duration,
 };
 
+mod adb;
+mod apk_init;
+mod bench;
 mod binary;
 mod builder;
+mod clean;
 mod config;
+mod dex;
+mod doctor;
 mod downloader;
+mod env;
+mod explain;
+mod frida;
+mod inspect;
+mod keystore;
+mod logging;
+mod smali;
+mod validate;
 
 use builder::Builder;
 use config::FripackConfig;
 use downloader::Downloader;
 
 use crate::config::{Platform, ResolvedConfig};
+use crate::explain::Explain;
 
 #[derive(Parser)]
 #[command(name = "fripack")]
@@ -31,6 +47,37 @@ use crate::config::{Platform, ResolvedConfig};
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Configuration file to use instead of searching for one (also FRIPACK_CONFIG).
+    /// Repeat to merge several files, later ones overriding earlier ones
+    #[arg(long, global = true)]
+    config: Vec<PathBuf>,
+
+    /// Also write logs (with timestamps) to this file
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+
+    /// Append to the log file instead of truncating it
+    #[arg(long, global = true, requires = "log_file")]
+    log_append: bool,
+
+    /// adb serial of the device to use (overrides `device` in the configuration)
+    #[arg(long, global = true)]
+    device: Option<String>,
+
+    /// `adb connect` to this network device (host:port) before using it, and use it
+    /// unless --device is given (overrides `adbConnect` in the configuration)
+    #[arg(long, global = true, value_name = "HOST:PORT", value_parser = parse_connect_address)]
+    adb_connect: Option<String>,
+
+    /// `adb disconnect` the network devices fripack connected to when done
+    #[arg(long, global = true)]
+    adb_disconnect: bool,
+
+    /// Log each external command (adb, apktool, apksigner, ...) as a shell line
+    /// before running it, with passwords redacted
+    #[arg(long, global = true)]
+    explain: bool,
 }
 
 #[derive(Subcommand)]
@@ -40,22 +87,253 @@ enum Commands {
         /// Path to create the configuration file (default: current directory)
         #[arg(short, long, default_value = ".")]
         path: PathBuf,
+        /// Create an inject-apk target for this APK file, or for this package pulled
+        /// from the device
+        #[arg(long, value_name = "APK_OR_PACKAGE")]
+        from_apk: Option<String>,
     },
     /// Build targets from configuration
     Build {
         /// Specific target to build (optional, builds all if not specified)
         target: Option<String>,
+        /// Compress the embedded script with zstd using this trained dictionary
+        #[arg(long)]
+        zstd_dict: Option<PathBuf>,
+        /// Treat warnings such as an exceeded maxOutputSize as errors
+        #[arg(long)]
+        strict: bool,
+        /// Only print the prebuilt download URLs and cache paths of the targets
+        #[arg(long)]
+        print_url: bool,
+        /// Only resolve and validate the configuration and print the resolved targets
+        #[arg(long)]
+        resolve_only: bool,
+        /// Only print the external tools each target needs to build
+        #[arg(long)]
+        list_requirements: bool,
+        /// Number of targets to build at the same time (default: FRIPACK_JOBS, or the
+        /// number of CPUs)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        /// Maximum number of prebuilt downloads running at the same time
+        #[arg(long, default_value_t = downloader::DEFAULT_PARALLEL_DOWNLOADS)]
+        parallel_downloads: usize,
+        /// Don't save downloaded prebuilt files to the cache (also FRIPACK_NO_CACHE=1)
+        #[arg(long)]
+        no_cache_download: bool,
+        /// Print how many prebuilt files were served from the cache or downloaded
+        #[arg(long)]
+        stats: bool,
+        /// Extra HTTP header for prebuilt downloads, as `'Name: value'` (repeatable)
+        #[arg(long = "header", value_name = "HEADER")]
+        headers: Vec<String>,
+        /// Write `{artifact}.buildinfo` with the versions and prebuilts used
+        #[arg(long)]
+        buildinfo: bool,
+        /// Don't check downloaded prebuilt files against the release's SHA-256 checksums
+        #[arg(long)]
+        no_verify: bool,
+        /// Cancel the remaining targets on the first failure instead of building
+        /// all of them and reporting every failure
+        #[arg(long)]
+        fail_fast: bool,
+        /// Assemble the generated smali with the `smali` tool (if installed) before
+        /// running apktool, to report mistakes in it early
+        #[arg(long)]
+        check_smali: bool,
+        /// Decompile inject-apk source APKs with apktool again instead of using
+        /// the cached trees in ~/.fripack/decompiled
+        #[arg(long)]
+        refresh_decompile: bool,
     },
     /// Watch and auto-rebuild targets when files change
     Watch {
-        /// Specific target to watch (required)
-        target: String,
+        /// Targets to watch (at least one)
+        #[arg(required = true)]
+        targets: Vec<String>,
+        /// Number of targets to rebuild at the same time
+        #[arg(short, long, default_value_t = 1)]
+        jobs: usize,
+        /// Treat warnings such as a device ABI mismatch as errors
+        #[arg(long)]
+        strict: bool,
+        /// Stop watching after this long, e.g. `10m` (default: watch until Ctrl+C)
+        #[arg(long, value_parser = humantime::parse_duration)]
+        watch_timeout: Option<Duration>,
+        /// Don't push the entry to the device, rebuild with the script embedded instead
+        /// (like `pushPath: false` on every target)
+        #[arg(long)]
+        no_push: bool,
+        /// After an install or push, wait this long (default 30s) for the target's
+        /// readyMarker in logcat to confirm the gadget loaded
+        #[arg(
+            long,
+            value_name = "TIMEOUT",
+            value_parser = humantime::parse_duration,
+            num_args = 0..=1,
+            default_missing_value = "30s"
+        )]
+        wait_ready: Option<Duration>,
+    },
+    /// Extract the embedded script from a built binary
+    Extract {
+        /// Built binary (.so / .dll) to read
+        file: PathBuf,
+        /// Where to write the script (default: stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// zstd dictionary the binary was built with
+        #[arg(long)]
+        zstd_dict: Option<PathBuf>,
+    },
+    /// Print the embedded config of a built binary
+    Inspect {
+        /// Built binary (.so / .dll) to read
+        file: PathBuf,
+        /// Also write the embedded script to this file
+        #[arg(long)]
+        extract: Option<PathBuf>,
+        /// zstd dictionary the binary was built with
+        #[arg(long)]
+        zstd_dict: Option<PathBuf>,
+    },
+    /// Check the embedded script of a built binary against its recorded hash
+    Verify {
+        /// Built binary (.so / .dll) to check
+        file: PathBuf,
+        /// zstd dictionary the binary was built with
+        #[arg(long)]
+        zstd_dict: Option<PathBuf>,
+    },
+    /// Download a prebuilt library into the cache
+    Fetch {
+        /// Platform to fetch, e.g. android-arm64
+        #[arg(long)]
+        platform: String,
+        /// Frida version of the prebuilt library
+        #[arg(long)]
+        frida_version: String,
+        /// Only print the download URL and cache path instead of downloading
+        #[arg(long)]
+        print_url: bool,
+        /// Don't check the download against the release's SHA-256 checksums
+        #[arg(long)]
+        no_verify: bool,
+    },
+    /// Compare the embedded configs and scripts of two built binaries
+    Diff {
+        /// First built binary
+        file_a: PathBuf,
+        /// Second built binary
+        file_b: PathBuf,
+        /// zstd dictionary the binaries were built with
+        #[arg(long)]
+        zstd_dict: Option<PathBuf>,
+    },
+    /// Replace the embedded script of a built binary without rebuilding it
+    Reembed {
+        /// Binary built by fripack
+        file: PathBuf,
+        /// New script to embed
+        #[arg(long)]
+        entry: PathBuf,
+        /// Compress the script with xz instead of the binary's compression
+        #[arg(long)]
+        xz: bool,
+        /// Where to write the result (default: overwrite the input)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// zstd dictionary the binary was built with
+        #[arg(long)]
+        zstd_dict: Option<PathBuf>,
+    },
+    /// Rewrite an APK with sorted entries and zeroed timestamps, so equivalent
+    /// builds can be compared byte for byte. The result is unsigned.
+    ApkCanonicalize {
+        /// APK to rewrite
+        apk: PathBuf,
+        /// Where to write the result (default: overwrite the input)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Time embedding a script into a local prebuilt library, without writing
+    /// any output
+    Bench {
+        /// Prebuilt library (.so / .dll / .dylib) with a fripack config placeholder
+        prebuilt: PathBuf,
+        /// Script to embed
+        #[arg(long)]
+        entry: PathBuf,
+        /// Number of times to process the prebuilt
+        #[arg(short = 'n', long, default_value_t = 10)]
+        iterations: usize,
+        /// Compress the script with xz
+        #[arg(long)]
+        xz: bool,
+        /// Also time anti-detection
+        #[arg(long)]
+        anti_detection: bool,
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Remove the output directories of the configured targets
+    Clean {
+        /// Also remove the APKs pulled from devices (./fripack_cache/apks)
+        #[arg(long)]
+        apk_cache: bool,
+        /// Only print what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check every target's configuration and the files it references, reporting
+    /// all problems at once
+    Validate,
+    /// List the configured targets with their resolved type and platform
+    List {
+        /// Print the targets as JSON
+        #[arg(long)]
+        json: bool,
     },
+    /// Check that the external tools and environment fripack needs are available
+    Doctor,
+    /// Print the settings in effect (config files, cache, proxy, device, tools) and their source
+    Env,
     /// Cache management commands
     Cache {
         #[command(subcommand)]
         action: CacheAction,
     },
+    /// Keystore management commands
+    Keystore {
+        #[command(subcommand)]
+        action: KeystoreAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeystoreAction {
+    /// Generate a signing keystore with keytool and print its `sign` config
+    Init {
+        /// Path of the keystore to create
+        #[arg(long, default_value = ".android/debug.keystore")]
+        path: PathBuf,
+        /// Alias of the generated key
+        #[arg(long, default_value = "androiddebugkey")]
+        alias: String,
+        /// How long the certificate is valid for, in days
+        #[arg(long, default_value_t = 10000)]
+        validity_days: u32,
+        /// Distinguished name of the certificate
+        #[arg(long, default_value = keystore::DEFAULT_DNAME)]
+        dname: String,
+        /// Keystore password (prompted for by keytool when omitted)
+        #[arg(long)]
+        store_pass: Option<String>,
+        /// Key password (defaults to the keystore password)
+        #[arg(long)]
+        key_pass: Option<String>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -63,37 +341,250 @@ enum CacheAction {
     /// Show cache statistics and list cached files
     Query,
     /// Clear all cached files
-    Clear,
+    Clear {
+        /// Only remove files fetched for this project (its configuration file path)
+        /// that no other project uses
+        #[arg(long, conflicts_with = "this_project")]
+        project: Option<String>,
+        /// Like --project, for the project of the current configuration
+        #[arg(long)]
+        this_project: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::Builder::from_default_env()
-        .format_timestamp(None)
-        .filter_level(log::LevelFilter::Info)
-        .init();
-
     let cli = Cli::parse();
 
+    logging::init(cli.log_file.as_deref(), cli.log_append)?;
+    if cli.explain {
+        explain::enable();
+    }
+
+    let adb_disconnect = cli.adb_disconnect;
+    let result = run(cli).await;
+    if adb_disconnect {
+        adb::disconnect_all().await;
+    }
+    result
+}
+
+async fn run(mut cli: Cli) -> Result<()> {
+    if let Some(address) = &cli.adb_connect {
+        cli.device.get_or_insert_with(|| address.clone());
+    }
+
     match cli.command {
-        Commands::Init { path } => {
-            init_config(path).await?;
+        Commands::Init { path, from_apk } => {
+            if from_apk.is_some() {
+                connect_flag_device(cli.adb_connect.as_deref()).await?;
+            }
+            init_config(path, from_apk, cli.device.as_deref()).await?;
+        }
+        Commands::Build {
+            target,
+            zstd_dict,
+            strict,
+            print_url,
+            resolve_only,
+            list_requirements,
+            jobs,
+            parallel_downloads,
+            no_cache_download,
+            stats,
+            headers,
+            buildinfo,
+            no_verify,
+            fail_fast,
+            check_smali,
+            refresh_decompile,
+        } => {
+            if print_url {
+                print_target_urls(cli.config, target).await?;
+            } else if list_requirements {
+                print_target_requirements(cli.config, target)?;
+            } else if resolve_only {
+                print_resolved_targets(cli.config, target, cli.device.as_deref())?;
+            } else {
+                connect_flag_device(cli.adb_connect.as_deref()).await?;
+                let mut downloader = Downloader::new().with_parallel_downloads(parallel_downloads);
+                if no_cache_download
+                    || std::env::var_os("FRIPACK_NO_CACHE").is_some_and(|v| v == "1")
+                {
+                    downloader = downloader.without_download_cache();
+                }
+                if no_verify {
+                    downloader = downloader.without_checksum_verification();
+                }
+                if !headers.is_empty() {
+                    downloader = downloader.with_headers(parse_header_args(&headers)?);
+                }
+                let builder = Builder::with_downloader(downloader.clone())
+                    .with_strict(strict)
+                    .with_buildinfo(buildinfo)
+                    .with_check_smali(check_smali)
+                    .with_refresh_decompile(refresh_decompile);
+                let result = build_target(
+                    cli.config,
+                    target,
+                    zstd_dict,
+                    cli.device,
+                    match jobs {
+                        Some(jobs) => jobs,
+                        None => default_jobs()?,
+                    },
+                    fail_fast,
+                    builder,
+                )
+                .await;
+                if stats {
+                    print_download_stats(downloader.stats());
+                }
+                result?;
+            }
+        }
+        Commands::Watch {
+            targets,
+            jobs,
+            strict,
+            watch_timeout,
+            no_push,
+            wait_ready,
+        } => {
+            connect_flag_device(cli.adb_connect.as_deref()).await?;
+            watch_target(
+                cli.config,
+                targets,
+                cli.device,
+                jobs,
+                watch_timeout,
+                no_push,
+                UpdateOptions { strict, wait_ready },
+            )
+            .await?;
+        }
+        Commands::Extract {
+            file,
+            output,
+            zstd_dict,
+        } => {
+            inspect::extract(file, output, zstd_dict).await?;
+        }
+        Commands::Inspect {
+            file,
+            extract,
+            zstd_dict,
+        } => {
+            inspect::inspect(file, extract, zstd_dict).await?;
+        }
+        Commands::Verify { file, zstd_dict } => {
+            inspect::verify(file, zstd_dict).await?;
+        }
+        Commands::Fetch {
+            platform,
+            frida_version,
+            print_url,
+            no_verify,
+        } => {
+            fetch_prebuilt(platform, frida_version, print_url, no_verify).await?;
+        }
+        Commands::Diff {
+            file_a,
+            file_b,
+            zstd_dict,
+        } => {
+            inspect::diff(file_a, file_b, zstd_dict).await?;
+        }
+        Commands::Reembed {
+            file,
+            entry,
+            xz,
+            output,
+            zstd_dict,
+        } => {
+            inspect::reembed(file, entry, xz, output, zstd_dict).await?;
+        }
+        Commands::ApkCanonicalize { apk, output } => {
+            let output = output.unwrap_or_else(|| apk.clone());
+            let entries = builder::canonicalize_apk(&apk, &output)?;
+            info!(
+                "✓ Wrote canonical APK with {entries} entries: {}",
+                output.display()
+            );
+            warn!("The canonical APK is unsigned, zipalign and sign it again to install it");
         }
-        Commands::Build { target } => {
-            build_target(target).await?;
+        Commands::Bench {
+            prebuilt,
+            entry,
+            iterations,
+            xz,
+            anti_detection,
+            json,
+        } => {
+            bench::bench(prebuilt, entry, iterations, xz, anti_detection, json).await?;
         }
-        Commands::Watch { target } => {
-            watch_target(target).await?;
+        Commands::Clean { apk_cache, dry_run } => {
+            let config_paths = locate_configs(cli.config)?;
+            let resolved_config = load_config(&config_paths, false, cli.device.as_deref())?;
+            let project_dir = config_paths[0].parent().unwrap_or(Path::new("."));
+            clean::clean(&resolved_config, project_dir, apk_cache, dry_run).await?;
+        }
+        Commands::Validate => {
+            let config_paths = locate_configs(cli.config)?;
+            info!("→ Using configuration: {}", display_paths(&config_paths));
+            let resolved_config = load_config(&config_paths, false, cli.device.as_deref())?;
+            let config_dir = config_paths[0].parent().unwrap_or(Path::new("."));
+            validate::run_validate(&resolved_config, config_dir)?;
+        }
+        Commands::List { json } => {
+            list_targets(cli.config, cli.device.as_deref(), json)?;
+        }
+        Commands::Doctor => {
+            doctor::run_doctor().await?;
+        }
+        Commands::Env => {
+            let source = if !cli.config.is_empty() {
+                "flag --config"
+            } else if std::env::var_os("FRIPACK_CONFIG").is_some() {
+                "env FRIPACK_CONFIG"
+            } else {
+                "search from the current directory"
+            };
+            let config = env::ConfigSource {
+                paths: locate_configs(cli.config),
+                source,
+            };
+            env::print_env(config, cli.device.as_deref())?;
         }
         Commands::Cache { action } => {
-            handle_cache_action(action).await?;
+            handle_cache_action(action, cli.config).await?;
         }
+        Commands::Keystore { action } => match action {
+            KeystoreAction::Init {
+                path,
+                alias,
+                validity_days,
+                dname,
+                store_pass,
+                key_pass,
+            } => {
+                keystore::init(
+                    &path,
+                    &alias,
+                    validity_days,
+                    &dname,
+                    store_pass.as_deref(),
+                    key_pass.as_deref(),
+                )
+                .await?;
+            }
+        },
     }
 
     Ok(())
 }
 
-async fn init_config(path: PathBuf) -> Result<()> {
+async fn init_config(path: PathBuf, from_apk: Option<String>, device: Option<&str>) -> Result<()> {
     info!("Initializing fripack configuration...");
 
     let config_path = if path.is_dir() {
@@ -102,6 +593,10 @@ async fn init_config(path: PathBuf) -> Result<()> {
         path
     };
 
+    if let Some(from_apk) = from_apk {
+        return apk_init::init_from_apk(&from_apk, &config_path, device).await;
+    }
+
     if config_path.exists() {
         warn!("Configuration file already exists!");
         return Ok(());
@@ -117,31 +612,99 @@ async fn init_config(path: PathBuf) -> Result<()> {
     Ok(())
 }
 
-fn load_config(path: &PathBuf, watch_mode: bool) -> Result<ResolvedConfig> {
-    let config_content = std::fs::read_to_string(path)?;
-    let config: FripackConfig = json5::from_str(&config_content)?;
+/// Connects to the `--adb-connect` device, if given.
+async fn connect_flag_device(adb_connect: Option<&str>) -> Result<()> {
+    if let Some(address) = adb_connect {
+        adb::connect(address).await?;
+    }
+    Ok(())
+}
+
+/// Connects to the target's `adbConnect` device, if set.
+async fn connect_target_device(target_config: &config::ResolvedTarget) -> Result<()> {
+    if let Some(address) = &target_config.adb_connect {
+        adb::connect(address).await?;
+    }
+    Ok(())
+}
+
+/// Number of targets `build` builds at the same time without `--jobs`.
+fn default_jobs() -> Result<usize> {
+    let Some(jobs) = std::env::var("FRIPACK_JOBS")
+        .ok()
+        .filter(|jobs| !jobs.is_empty())
+    else {
+        return Ok(std::thread::available_parallelism().map_or(1, |n| n.get()));
+    };
+    jobs.parse()
+        .ok()
+        .filter(|&jobs| jobs > 0)
+        .with_context(|| format!("Invalid FRIPACK_JOBS: {jobs:?} (expected a positive number)"))
+}
+
+fn parse_connect_address(address: &str) -> Result<String> {
+    adb::validate_connect_address(address)?;
+    Ok(address.to_string())
+}
+
+fn load_config(
+    paths: &[PathBuf],
+    watch_mode: bool,
+    device: Option<&str>,
+) -> Result<ResolvedConfig> {
+    let config = FripackConfig::load(paths)?;
     let mut resolved_config = config.resolve_inheritance()?;
     resolved_config
         .targets
         .values_mut()
         .for_each(|target| {
             target.watch_mode = watch_mode;
-            if watch_mode {
-                target.push_path.get_or_insert_with(|| "/data/local/tmp/fripack_dev.js".to_string());
+            if let Some(device) = device {
+                target.device = Some(device.to_string());
+            }
+            if target.device.is_none() {
+                target.device = target.adb_connect.clone();
+            }
+            if watch_mode && target.watch_path.is_none() {
+                target.watch_path = target.bundle_input_dir();
             }
         });
     Ok(resolved_config)
 }
 
-async fn build_target(target: Option<String>) -> Result<()> {
+/// Builds `target`, or every target, with copies of `builder`.
+async fn build_target(
+    config: Vec<PathBuf>,
+    target: Option<String>,
+    zstd_dict: Option<PathBuf>,
+    device: Option<String>,
+    jobs: usize,
+    fail_fast: bool,
+    builder: Builder,
+) -> Result<()> {
     info!("Building fripack targets...");
 
-    let config_path = find_config_file(std::env::current_dir()?)?;
-    info!("→ Using configuration: {}", config_path.display());
-
-    let config_dir = config_path.parent().unwrap_or(std::path::Path::new("."));
+    let config_paths = locate_configs(config)?;
+    info!("→ Using configuration: {}", display_paths(&config_paths));
+    // Relative to where fripack was run, not to the configuration
+    let zstd_dict = zstd_dict
+        .map(|path| {
+            path.canonicalize()
+                .with_context(|| format!("Failed to find zstd dictionary: {}", path.display()))
+        })
+        .transpose()?;
+
+    let config_dir = config_paths[0]
+        .parent()
+        .unwrap_or(std::path::Path::new("."));
     std::env::set_current_dir(config_dir)?;
-    let resolved_config = load_config(&config_path, false)?;
+    let mut resolved_config = load_config(&config_paths, false, device.as_deref())?;
+    let mut builder = builder.with_project(project_id(&config_paths));
+    if let Some(zstd_dict) = zstd_dict {
+        resolved_config.targets.values_mut().for_each(|target| {
+            target.zstd_dict = Some(zstd_dict.to_string_lossy().to_string());
+        });
+    }
 
     match target {
         Some(target_name) => {
@@ -150,17 +713,46 @@ async fn build_target(target: Option<String>) -> Result<()> {
                 .get(&target_name)
                 .context("Failed to find the target")?;
             info!("→ Building target: {target_name}");
-            let mut builder = Builder::new();
-            builder.build_target(&target_name, target_config).await?;
-            info!("✓ Successfully built target: {target_name}");
+            let artifacts = builder.build_target(&target_name, target_config).await?;
+            log_built_target(&target_name, &artifacts);
         }
         None => {
             info!("Building all targets...");
-            let mut builder = Builder::new();
+            let job_permits = Arc::new(tokio::sync::Semaphore::new(jobs.max(1)));
+            let mut builds = tokio::task::JoinSet::new();
+
+            for (target_name, target_config) in resolved_config.targets {
+                let job_permits = job_permits.clone();
+                let mut builder = builder.clone();
+                builds.spawn(async move {
+                    let _permit = job_permits.acquire_owned().await?;
+                    info!("→ Building target: {target_name}");
+                    let artifacts = builder
+                        .build_target(&target_name, &target_config)
+                        .await
+                        .with_context(|| format!("Failed to build target: {target_name}"))?;
+                    log_built_target(&target_name, &artifacts);
+                    Ok(())
+                });
+            }
 
-            for (target_name, target_config) in &resolved_config.targets {
-                info!("→ Building target: {target_name}");
-                builder.build_target(target_name, target_config).await?;
+            let mut failures = Vec::new();
+            while let Some(result) = builds.join_next().await {
+                let Err(e) = result? else {
+                    continue;
+                };
+                if fail_fast {
+                    // Queued targets never start, running ones are cancelled
+                    builds.abort_all();
+                    return Err(e);
+                }
+                warn!("{e:#}");
+                failures.push(e);
+            }
+            match failures.len() {
+                0 => {}
+                1 => return Err(failures.remove(0)),
+                count => anyhow::bail!("{count} targets failed to build, see the errors above"),
             }
 
             info!("✓ Successfully built all targets!");
@@ -171,10 +763,350 @@ async fn build_target(target: Option<String>) -> Result<()> {
     Ok(())
 }
 
+fn log_built_target(target_name: &str, artifacts: &[PathBuf]) {
+    if artifacts.is_empty() {
+        info!("✓ Skipped target without a type: {target_name}");
+    }
+    for artifact in artifacts {
+        info!(
+            "✓ Successfully built target: {target_name} ({})",
+            artifact.display()
+        );
+    }
+}
+
+fn print_download_stats(stats: downloader::DownloadStats) {
+    info!(
+        "→ {} prebuilts ({} cached, {} downloaded, {} fetched)",
+        stats.cache_hits + stats.downloads,
+        stats.cache_hits,
+        stats.downloads,
+        format_bytes(stats.bytes_downloaded)
+    );
+}
+
+fn print_prebuilt_location(
+    downloader: &Downloader,
+    platform: &config::PlatformConfig,
+    frida_version: &str,
+) {
+    println!(
+        "{}",
+        downloader.get_prebuilt_file_url(platform, frida_version)
+    );
+    println!(
+        "  cache: {}",
+        downloader
+            .get_cache_file_path(platform, frida_version)
+            .display()
+    );
+}
+
+/// Parses `--header 'Name: value'` arguments.
+fn parse_header_args(headers: &[String]) -> Result<reqwest::header::HeaderMap> {
+    let pairs = headers
+        .iter()
+        .map(|header| {
+            let (name, value) = header
+                .split_once(':')
+                .with_context(|| format!("Invalid --header {header:?}, expected 'Name: value'"))?;
+            Ok((name.to_string(), value.to_string()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    downloader::parse_headers(pairs)
+}
+
+/// Loads the configuration from its directory, which relative paths in it are
+/// relative to, and returns `target` (or every target) sorted by name.
+fn select_targets(
+    config: Vec<PathBuf>,
+    target: Option<&str>,
+    device: Option<&str>,
+) -> Result<Vec<(String, config::ResolvedTarget)>> {
+    let config_paths = locate_configs(config)?;
+    let config_dir = config_paths[0]
+        .parent()
+        .unwrap_or(std::path::Path::new("."));
+    std::env::set_current_dir(config_dir)?;
+    let mut resolved_config = load_config(&config_paths, false, device)?;
+
+    let mut targets: Vec<_> = match target {
+        Some(target_name) => vec![(
+            target_name.to_string(),
+            resolved_config
+                .targets
+                .remove(target_name)
+                .context("Failed to find the target")?,
+        )],
+        None => resolved_config.targets.into_iter().collect(),
+    };
+    targets.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(targets)
+}
+
+fn print_target_requirements(config: Vec<PathBuf>, target: Option<String>) -> Result<()> {
+    let targets = select_targets(config, target.as_deref(), None)?;
+    for (target_name, target_config) in &targets {
+        if target_config.target_type.is_none() {
+            continue;
+        }
+        println!("{target_name}:");
+        let tools = builder::required_tools(target_config);
+        if tools.is_empty() {
+            println!("  (none)");
+        }
+        for tool in tools {
+            println!("  {tool}");
+        }
+    }
+    Ok(())
+}
+
+async fn print_target_urls(config: Vec<PathBuf>, target: Option<String>) -> Result<()> {
+    let targets = select_targets(config, target.as_deref(), None)?;
+    let downloader = Downloader::new();
+    for (target_name, target_config) in &targets {
+        if target_config.target_type.is_none() {
+            continue;
+        }
+        println!("{target_name}:");
+        if let Some(override_file) = &target_config.override_prebuild_file {
+            println!("  override: {override_file}");
+            continue;
+        }
+        let Some(frida_version) = &target_config.frida_version else {
+            println!("  (no fridaVersion)");
+            continue;
+        };
+        let downloader = match &target_config.prebuilt_base_url {
+            Some(prebuilt_base_url) => downloader
+                .clone()
+                .with_prebuilt_base_url(prebuilt_base_url.clone()),
+            None => downloader.clone(),
+        };
+        for platform in &target_config.platforms {
+            let frida_version = downloader
+                .resolve_frida_version(platform, frida_version)
+                .await?;
+            print_prebuilt_location(&downloader, platform, &frida_version);
+        }
+    }
+    Ok(())
+}
+
+/// Resolves and validates the targets, printing them as JSON with unset fields
+/// left out. Fails if any of them doesn't resolve.
+fn print_resolved_targets(
+    config: Vec<PathBuf>,
+    target: Option<String>,
+    device: Option<&str>,
+) -> Result<()> {
+    let targets = select_targets(config, target.as_deref(), device)?;
+    let mut resolved = serde_json::Map::new();
+    let mut failed = 0;
+    for (target_name, target_config) in &targets {
+        if let Err(e) = target_config.validate() {
+            warn!("Target {target_name}: {e}");
+            failed += 1;
+        }
+        let mut value = serde_json::to_value(target_config)?;
+        if let serde_json::Value::Object(fields) = &mut value {
+            fields.retain(|_, field| !field.is_null());
+        }
+        resolved.insert(target_name.clone(), value);
+    }
+    println!("{}", serde_json::to_string_pretty(&resolved)?);
+
+    if failed > 0 {
+        anyhow::bail!("{failed} target(s) failed to validate");
+    }
+    Ok(())
+}
+
+/// Prints every target after inheritance, and whether `build` would build it.
+fn list_targets(config: Vec<PathBuf>, device: Option<&str>, json: bool) -> Result<()> {
+    let targets = select_targets(config, None, device)?;
+
+    // Targets without a type only serve as bases to inherit from
+    let status = |target_config: &config::ResolvedTarget| {
+        if let Err(e) = target_config.validate() {
+            (false, Some(e.to_string()))
+        } else if target_config.target_type.is_none() {
+            (false, Some("no type".to_string()))
+        } else {
+            (true, None)
+        }
+    };
+
+    if json {
+        let mut listed = Vec::new();
+        for (target_name, target_config) in &targets {
+            let (buildable, reason) = status(target_config);
+            listed.push(serde_json::json!({
+                "name": target_name,
+                "type": target_config.target_type,
+                "platform": target_config
+                    .platforms
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+                "fridaVersion": target_config.frida_version,
+                "buildable": buildable,
+                "reason": reason,
+            }));
+        }
+        println!("{}", serde_json::to_string_pretty(&listed)?);
+        return Ok(());
+    }
+
+    let mut rows = vec![[
+        "NAME".to_string(),
+        "TYPE".to_string(),
+        "PLATFORM".to_string(),
+        "FRIDA".to_string(),
+        "BUILDABLE".to_string(),
+    ]];
+    for (target_name, target_config) in &targets {
+        let (buildable, reason) = status(target_config);
+        let platforms = target_config
+            .platforms
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        rows.push([
+            target_name.clone(),
+            target_config
+                .target_type
+                .clone()
+                .unwrap_or_else(|| "-".to_string()),
+            if platforms.is_empty() {
+                "-".to_string()
+            } else {
+                platforms
+            },
+            target_config
+                .frida_version
+                .clone()
+                .unwrap_or_else(|| "-".to_string()),
+            match (buildable, reason) {
+                (true, _) => "yes".to_string(),
+                (false, Some(reason)) => format!("no ({reason})"),
+                (false, None) => "no".to_string(),
+            },
+        ]);
+    }
+    let widths: Vec<usize> = (0..4)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    for row in rows {
+        println!(
+            "{:w0$}  {:w1$}  {:w2$}  {:w3$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+        );
+    }
+    Ok(())
+}
+
+async fn fetch_prebuilt(
+    platform: String,
+    frida_version: String,
+    print_url: bool,
+    no_verify: bool,
+) -> Result<()> {
+    let platform = config::PlatformConfig::from_str(platform)?;
+    let mut downloader = Downloader::new();
+    if no_verify {
+        downloader = downloader.without_checksum_verification();
+    }
+    let frida_version = downloader
+        .resolve_frida_version(&platform, &frida_version)
+        .await?;
+
+    if print_url {
+        print_prebuilt_location(&downloader, &platform, &frida_version);
+        return Ok(());
+    }
+
+    downloader
+        .download_prebuilt_file(&platform, &frida_version, &Default::default())
+        .await?;
+    info!(
+        "✓ Prebuilt file cached: {}",
+        downloader
+            .get_cache_file_path(&platform, &frida_version)
+            .display()
+    );
+    Ok(())
+}
+
+/// Finds the configuration files to use, in order of precedence: the `--config`
+/// flags, the `FRIPACK_CONFIG` path list, then an upward search from the current
+/// directory that stops at `FRIPACK_CONFIG_ROOT` if set.
+fn locate_configs(config: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+    let explicit = if config.is_empty() {
+        std::env::var_os("FRIPACK_CONFIG")
+            .map(|paths| std::env::split_paths(&paths).collect())
+            .unwrap_or_default()
+    } else {
+        config
+    };
+    if explicit.is_empty() {
+        return Ok(vec![find_config_file(std::env::current_dir()?)?]);
+    }
+
+    let current_dir = std::env::current_dir()?;
+    explicit
+        .into_iter()
+        .map(|path| {
+            if !path.is_file() {
+                anyhow::bail!("Configuration file not found: {}", path.display());
+            }
+            Ok(current_dir.join(path))
+        })
+        .collect()
+}
+
+/// Identifies a project in the cache index by its (first) configuration file.
+fn project_id(config_paths: &[PathBuf]) -> String {
+    canonical_path(&config_paths[0]).display().to_string()
+}
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 fn find_config_file(start_dir: PathBuf) -> Result<PathBuf> {
-    let mut current_dir = start_dir;
+    // Compared as canonical paths, so `..` or symlinks can't hide the root
+    let root_dir = std::env::var_os("FRIPACK_CONFIG_ROOT")
+        .map(|root| {
+            let root = PathBuf::from(root);
+            root.canonicalize()
+                .with_context(|| format!("Invalid FRIPACK_CONFIG_ROOT: {}", root.display()))
+        })
+        .transpose()?;
+    let mut current_dir = start_dir.canonicalize()?;
 
     loop {
+        if root_dir
+            .as_ref()
+            .is_some_and(|root_dir| !current_dir.starts_with(root_dir))
+        {
+            break;
+        }
+
         let fripack_json = current_dir.join("fripack.json");
         let fripack_config = current_dir.join("fripack.config.json");
 
@@ -191,18 +1123,40 @@ fn find_config_file(start_dir: PathBuf) -> Result<PathBuf> {
             break;
         }
     }
-    anyhow::bail!("Could not find fripack configuration file in current or parent directories");
+    match root_dir {
+        Some(root_dir) => anyhow::bail!(
+            "Could not find fripack configuration file in current or parent directories within FRIPACK_CONFIG_ROOT ({})",
+            root_dir.display()
+        ),
+        None => anyhow::bail!(
+            "Could not find fripack configuration file in current or parent directories"
+        ),
+    }
 }
 
-async fn handle_cache_action(action: CacheAction) -> Result<()> {
+async fn handle_cache_action(action: CacheAction, config: Vec<PathBuf>) -> Result<()> {
     let downloader = Downloader::new();
 
     match action {
         CacheAction::Query => {
             query_cache(&downloader).await?;
         }
-        CacheAction::Clear => {
-            clear_cache(&downloader).await?;
+        CacheAction::Clear {
+            project,
+            this_project,
+        } => {
+            let project = if this_project {
+                Some(project_id(&locate_configs(config)?))
+            } else {
+                project
+            };
+            match project {
+                Some(project) => {
+                    warn!("Clearing cache of project: {project}");
+                    downloader.clear_project_cache(&project).await?;
+                }
+                None => clear_cache(&downloader).await?,
+            }
         }
     }
 
@@ -215,6 +1169,7 @@ async fn query_cache(downloader: &Downloader) -> Result<()> {
 
     let cache_dir = downloader.cache_dir();
     info!("Cache Directory: {}", cache_dir.display());
+    info!("APK Cache Directory: {}", downloader::APK_CACHE_DIR);
 
     let stats = downloader.get_cache_stats().await?;
 
@@ -225,12 +1180,33 @@ async fn query_cache(downloader: &Downloader) -> Result<()> {
 
     info!("Total Files: {}", stats.file_count);
     info!("Total Size: {}", format_bytes(stats.total_size));
+    if let Some(max_bytes) = downloader::cache_max_bytes()? {
+        let prebuilt_size: u64 = stats.files.iter().map(|file| file.size).sum();
+        info!(
+            "Prebuilt Cache Usage: {} of {} ({:.0}%)",
+            format_bytes(prebuilt_size),
+            format_bytes(max_bytes),
+            prebuilt_size as f64 * 100.0 / max_bytes.max(1) as f64
+        );
+    }
 
-    info!("\nCached Files:");
-    info!("------------");
-
+    if !stats.files.is_empty() {
+        info!("\nCached Prebuilt Files:");
+        info!("---------------------");
+    }
     for file_info in stats.files {
         info!("  • {} ({})", file_info.name, format_bytes(file_info.size));
+        for project in downloader.file_projects(&file_info.name).await? {
+            info!("      used by: {project}");
+        }
+    }
+
+    if !stats.apks.is_empty() {
+        info!("\nCached APKs:");
+        info!("-----------");
+    }
+    for file_info in stats.apks {
+        info!("  • {} ({})", file_info.name, format_bytes(file_info.size));
     }
 
     Ok(())
@@ -242,7 +1218,7 @@ async fn clear_cache(downloader: &Downloader) -> Result<()> {
 
     let stats = downloader.get_cache_stats().await?;
 
-    if stats.file_count == 0 {
+    if stats.file_count == 0 && !downloader.decompiled_dir().exists() {
         warn!("No cached files to clear.");
         return Ok(());
     }
@@ -262,18 +1238,49 @@ async fn clear_cache(downloader: &Downloader) -> Result<()> {
     Ok(())
 }
 
+/// Serializes device access of concurrently updated targets.
+static ADB_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Android ABIs the target is built for.
+fn target_abis(target_config: &config::ResolvedTarget) -> Result<Vec<String>> {
+    target_config
+        .platforms
+        .iter()
+        .map(|platform| platform.android_abi())
+        .collect()
+}
+
+/// How `watch` updates its targets.
+#[derive(Clone, Copy)]
+struct UpdateOptions {
+    /// Stop an update on a device ABI mismatch (and fail builds on warnings)
+    strict: bool,
+    /// Wait for the target's `readyMarker` after each install or push
+    wait_ready: Option<Duration>,
+}
+
 async fn rebuild_install_target(
     target: &str,
     target_config: &config::ResolvedTarget,
+    options: UpdateOptions,
 ) -> Result<()> {
-    let mut builder = Builder::new();
-    let output_path = builder.build_target(&target, target_config).await?.unwrap();
+    let mut builder = Builder::new().with_strict(options.strict);
+    let artifacts = builder.build_target(target, target_config).await?;
+    // Only xposed modules are installed, and they're a single APK
+    let [output_path] = artifacts.as_slice() else {
+        return Ok(());
+    };
 
     if target_config.target_type.as_deref() == Some("xposed") {
+        connect_target_device(target_config).await?;
+        let adb_lock = ADB_LOCK.lock().await;
+        let device = target_config.device.as_deref();
+        adb::check_device_abi(device, &target_abis(target_config)?, options.strict).await?;
         info!("→ Installing APK to device...");
-        let output = tokio::process::Command::new(which::which("adb")?)
+        let output = adb::adb_command(target_config.device.as_deref())?
             .arg("install")
             .arg(&output_path)
+            .explain()
             .output()
             .await?;
 
@@ -284,27 +1291,71 @@ async fn rebuild_install_target(
             );
         } else {
             info!("✓ APK installed successfully");
+            drop(adb_lock);
+            if let Some(timeout) = options.wait_ready {
+                wait_until_ready(target_config, timeout).await?;
+            }
         }
     }
     Ok(())
 }
 
+/// Watches logcat for the target's `readyMarker` to confirm the gadget loaded.
+async fn wait_until_ready(target_config: &config::ResolvedTarget, timeout: Duration) -> Result<()> {
+    let marker = target_config
+        .ready_marker
+        .as_deref()
+        .unwrap_or(adb::DEFAULT_READY_MARKER);
+    info!(
+        "→ Waiting up to {} for {marker:?} in logcat...",
+        humantime::format_duration(timeout)
+    );
+    if adb::wait_for_logcat_marker(target_config.device.as_deref(), marker, timeout).await? {
+        info!("✓ Gadget loaded");
+    } else {
+        warn!("Gadget not detected within {}s", timeout.as_secs());
+    }
+    Ok(())
+}
+
 async fn update_target(
     target: &str,
     target_config: &config::ResolvedTarget,
     config_updated: bool,
+    sources_changed: bool,
+    options: UpdateOptions,
 ) -> Result<()> {
+    // Without a push path the script is embedded, so changes to it need a rebuild
+    let push_path = target_config.push_path();
+    let bundles = sources_changed && target_config.bundle.is_some();
     if config_updated {
         info!("→ Configuration changed, rebuilding the target...");
-        rebuild_install_target(target, target_config).await?;
+        rebuild_install_target(target, target_config, options).await?;
+    } else if push_path.is_none() && !bundles {
+        info!("→ Script changed, rebuilding the target...");
+        rebuild_install_target(target, target_config, options).await?;
+    } else if bundles {
+        // Keep watching on compile errors, the next save may fix them
+        if let Err(e) = Builder::new().bundle(target_config).await {
+            warn!("Failed to bundle agent: {}", e);
+            return Ok(());
+        }
     }
     let entry = target_config.entry.as_ref().unwrap();
+    let Some(push_path) = push_path else {
+        return Ok(());
+    };
     if Path::new(entry).exists() && target_config.platform.as_ref().unwrap().platform == Platform::Android {
+        connect_target_device(target_config).await?;
+        let adb_lock = ADB_LOCK.lock().await;
+        let device = target_config.device.as_deref();
+        adb::check_device_abi(device, &target_abis(target_config)?, options.strict).await?;
         info!("→ Pushing JS file to device...");
-        let output = tokio::process::Command::new(which::which("adb")?)
+        let output = adb::adb_command(target_config.device.as_deref())?
             .arg("push")
             .arg(entry)
-            .arg(&target_config.push_path.as_ref().unwrap())
+            .arg(push_path)
+            .explain()
             .output()
             .await?;
 
@@ -315,6 +1366,13 @@ async fn update_target(
             );
         } else {
             info!("✓ JS file pushed successfully");
+            drop(adb_lock);
+            if let Some(attach) = &target_config.attach {
+                frida::reattach(target, attach, device, Path::new(entry)).await?;
+            }
+            if let Some(timeout) = options.wait_ready {
+                wait_until_ready(target_config, timeout).await?;
+            }
         }
     }
 
@@ -326,12 +1384,15 @@ fn update_watcher_targets(
         notify_debouncer_full::notify::RecommendedWatcher,
         notify_debouncer_full::RecommendedCache,
     >,
+    config_paths: &[PathBuf],
     target_config: &config::ResolvedTarget,
 ) -> Result<()> {
-    watcher.watch(
-        "./fripack.json",
-        notify_debouncer_full::notify::RecursiveMode::NonRecursive,
-    )?;
+    for config_path in config_paths {
+        watcher.watch(
+            config_path,
+            notify_debouncer_full::notify::RecursiveMode::NonRecursive,
+        )?;
+    }
     if let Some(watch_path) = &target_config.watch_path {
         watcher.watch(
             watch_path,
@@ -347,22 +1408,78 @@ fn update_watcher_targets(
     Ok(())
 }
 
-async fn watch_target(target: String) -> Result<()> {
-    info!("Watching target: {target}");
+/// Stops watching the entry and `watchPath` of a target, e.g. before watching
+/// the ones of its reloaded configuration.
+fn unwatch_target_paths(
+    watcher: &mut notify_debouncer_full::Debouncer<
+        notify_debouncer_full::notify::RecommendedWatcher,
+        notify_debouncer_full::RecommendedCache,
+    >,
+    target_config: &config::ResolvedTarget,
+) {
+    // A path that's gone or was never watched is fine to skip
+    if let Some(watch_path) = &target_config.watch_path {
+        let _ = watcher.unwatch(watch_path);
+    }
+    if let Some(entry) = &target_config.entry {
+        let _ = watcher.unwatch(entry);
+    }
+}
 
-    let config_path = find_config_file(std::env::current_dir()?)?;
-    info!("→ Using configuration: {}", config_path.display());
+async fn watch_target(
+    config: Vec<PathBuf>,
+    targets: Vec<String>,
+    device: Option<String>,
+    jobs: usize,
+    watch_timeout: Option<Duration>,
+    no_push: bool,
+    options: UpdateOptions,
+) -> Result<()> {
+    info!("Watching targets: {}", targets.join(", "));
+
+    let config_paths = locate_configs(config)?;
+    info!("→ Using configuration: {}", display_paths(&config_paths));
 
-    let config_dir = config_path.parent().unwrap_or(std::path::Path::new("."));
+    let config_dir = config_paths[0]
+        .parent()
+        .unwrap_or(std::path::Path::new("."));
     std::env::set_current_dir(config_dir)?;
 
-    let resolved_config = load_config(&config_path, true)?;
-    let target_config_cloned = resolved_config.targets[&target].clone();
-    if let Err(e) = update_target(&target, &target_config_cloned, true).await {
-        warn!("Failed to update target first: {}", e);
+    let load_watch_config = move |config_paths: &[PathBuf]| {
+        let mut resolved_config = load_config(config_paths, true, device.as_deref())?;
+        if no_push {
+            for target in resolved_config.targets.values_mut() {
+                target.push_path = Some(config::PushPath::Enabled(false));
+            }
+        }
+        anyhow::Ok(resolved_config)
     };
-
-    let target_config = Arc::new(Mutex::new(resolved_config.targets[&target].clone()));
+    let resolved_config = load_watch_config(&config_paths)?;
+    let mut target_configs = HashMap::new();
+    for target in &targets {
+        let target_config = resolved_config
+            .targets
+            .get(target)
+            .with_context(|| format!("Failed to find the target: {target}"))?;
+        target_configs.insert(target.clone(), target_config.clone());
+    }
+    let job_permits = Arc::new(tokio::sync::Semaphore::new(jobs.max(1)));
+
+    let initial_updates = target_configs
+        .iter()
+        .map(|(target, target_config)| (target.clone(), target_config.clone(), true, false))
+        .collect();
+    update_targets(initial_updates, job_permits.clone(), options).await;
+
+    let watched_targets: Vec<_> = target_configs.values().cloned().collect();
+    let watched_configs = config_paths.clone();
+    let target_configs = Arc::new(Mutex::new(target_configs));
+    let current_configs = target_configs.clone();
+    // Only this task owns the watcher, so the callback asks it to move the
+    // watches of targets whose entry or watchPath changed
+    let (rewatch_tx, mut rewatch_rx) = tokio::sync::mpsc::unbounded_channel();
+    // The debouncer calls back on its own thread, updates run on this runtime
+    let runtime = tokio::runtime::Handle::current();
     let mut watcher = notify_debouncer_full::new_debouncer(
         Duration::from_millis(500),
         None,
@@ -372,68 +1489,171 @@ async fn watch_target(target: String) -> Result<()> {
             match res {
                 Ok(events) => {
                     let mut config_updated = false;
+                    let mut changed_paths = Vec::new();
                     for event in events {
                         match &event.kind {
                             EventKind::Modify(_) | EventKind::Create(_) | EventKind::Remove(_) => {
-                                if event.paths.contains(&config_path) {
-                                    config_updated = true;
+                                for path in &event.paths {
+                                    if config_paths.contains(path) {
+                                        config_updated = true;
+                                    } else {
+                                        changed_paths.push(path.clone());
+                                    }
                                 }
                             }
                             _ => {}
                         }
                     }
 
-                    let rt = tokio::runtime::Runtime::new().unwrap();
-                    rt.block_on(async {
-                        let target_config = if config_updated {
-                            match load_config(&config_path, true) {
-                                Ok(new_target_config) => {
-                                    info!("→ Configuration updated, reloading...");
-                                    let new_config = new_target_config
-                                        .targets[&target]
-                                        .clone();
-
-                                    if new_config.entry != target_config.lock().unwrap().entry || new_config.watch_path != target_config.lock().unwrap().watch_path {
-                                        panic!("Target entry or watchPath changed, please restart the watcher.");
+                    if config_updated {
+                        match load_watch_config(&config_paths) {
+                            Ok(new_config) => {
+                                info!("→ Configuration updated, reloading...");
+                                let mut target_configs = target_configs.lock().unwrap();
+                                for (target, target_config) in target_configs.iter_mut() {
+                                    let Some(new_target_config) = new_config.targets.get(target)
+                                    else {
+                                        warn!("Target {target} is no longer in the configuration, keeping its previous settings");
+                                        continue;
+                                    };
+                                    if new_target_config.entry != target_config.entry
+                                        || new_target_config.watch_path != target_config.watch_path
+                                    {
+                                        info!("→ Entry or watchPath of {target} changed, updating the watched paths...");
+                                        let _ = rewatch_tx.send(target_config.clone());
                                     }
-
-                                    target_config.lock().unwrap().clone_from(&new_config);
-                                    target_config.clone()
-                                }
-                                Err(e) => {
-                                    panic!("Failed to reload configuration: {}", e);
+                                    target_config.clone_from(new_target_config);
                                 }
                             }
-                        } else {
-                            target_config.clone()
-                        };
-
-                        if let Err(e) = update_target(
-                            &target,
-                            &target_config.lock().unwrap(),
-                            config_updated,
-                        )
-                        .await
-                        {
-                            warn!("Failed to update target: {}", e);
-                        };
-                    });
+                            Err(e) => {
+                                warn!("Failed to reload configuration, keeping the previous one: {e:#}");
+                                config_updated = false;
+                            }
+                        }
+                    }
+
+                    // A config change rebuilds every target, other changes only the
+                    // targets watching the changed files
+                    let updates: Vec<_> = target_configs
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .filter_map(|(target, target_config)| {
+                            let entry =
+                                Path::new(target_config.entry.as_deref().unwrap_or_default());
+                            let watch_path = target_config.watch_path.as_ref().map(canonical_path);
+                            let affected: Vec<_> = changed_paths
+                                .iter()
+                                .filter(|path| {
+                                    is_same_file(path, entry)
+                                        || watch_path.as_ref().is_some_and(|watch_path| {
+                                            canonical_path(path).starts_with(watch_path)
+                                        })
+                                })
+                                .collect();
+                            // Changes to the entry alone are the bundler's own output
+                            let sources_changed =
+                                affected.iter().any(|path| !is_same_file(path, entry));
+                            (config_updated || !affected.is_empty()).then(|| {
+                                (
+                                    target.clone(),
+                                    target_config.clone(),
+                                    config_updated,
+                                    sources_changed,
+                                )
+                            })
+                        })
+                        .collect();
+
+                    runtime.block_on(update_targets(updates, job_permits.clone(), options));
                 }
                 Err(e) => warn!("Watch error: {:?}", e),
             }
         },
     )?;
 
-    update_watcher_targets(&mut watcher, &target_config_cloned)?;
+    for target_config in &watched_targets {
+        update_watcher_targets(&mut watcher, &watched_configs, target_config)?;
+    }
     info!("✓ Watching for changes... Press Ctrl+C to stop.");
 
+    let timeout = async {
+        match watch_timeout {
+            Some(watch_timeout) => tokio::time::sleep(watch_timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(timeout);
     loop {
-        tokio::time::sleep(Duration::from_secs(60)).await;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                result?;
+                info!("→ Stopping watcher...");
+                break;
+            }
+            _ = &mut timeout => {
+                info!("→ Watch timeout reached, stopping watcher...");
+                break;
+            }
+            Some(old_target_config) = rewatch_rx.recv() => {
+                unwatch_target_paths(&mut watcher, &old_target_config);
+                // Other targets may share the paths that were just unwatched
+                let target_configs = current_configs.lock().unwrap().clone();
+                for (target, target_config) in &target_configs {
+                    if let Err(e) =
+                        update_watcher_targets(&mut watcher, &watched_configs, target_config)
+                    {
+                        warn!("Failed to watch the paths of {target}: {e}");
+                    }
+                }
+                info!("✓ Watching the updated paths");
+            }
+        }
     }
+    frida::detach_all().await;
 
     Ok(())
 }
 
+/// Updates `(target, config, config_updated, sources_changed)` concurrently, at
+/// most `job_permits` at a time. A failing target doesn't cancel the others.
+async fn update_targets(
+    updates: Vec<(String, config::ResolvedTarget, bool, bool)>,
+    job_permits: Arc<tokio::sync::Semaphore>,
+    options: UpdateOptions,
+) {
+    let mut jobs = tokio::task::JoinSet::new();
+    for (target, target_config, config_updated, sources_changed) in updates {
+        let job_permits = job_permits.clone();
+        jobs.spawn(async move {
+            let _permit = job_permits.acquire_owned().await;
+            if let Err(e) = update_target(
+                &target,
+                &target_config,
+                config_updated,
+                sources_changed,
+                options,
+            )
+            .await
+            {
+                warn!("Failed to update target {target}: {}", e);
+            }
+        });
+    }
+    while jobs.join_next().await.is_some() {}
+}
+
+fn canonical_path(path: impl AsRef<Path>) -> PathBuf {
+    std::fs::canonicalize(&path).unwrap_or_else(|_| path.as_ref().to_path_buf())
+}
+
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (std::result::Result::Ok(a), std::result::Result::Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
 fn format_bytes(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB"];
     let mut size = bytes as f64;
@@ -450,3 +1670,144 @@ fn format_bytes(bytes: u64) -> String {
         format!("{:.2} {}", size, UNITS[unit_index])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Held by tests running commands that change the current directory, which
+    /// is shared by the whole process.
+    static CURRENT_DIR_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    #[tokio::test]
+    async fn watch_stops_after_the_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let entry = dir.path().join("main.js");
+        std::fs::write(&entry, "console.log('hi');").unwrap();
+        let prebuilt = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/libplaceholder.so"
+        );
+        let config_path = dir.path().join("fripack.json");
+        std::fs::write(
+            &config_path,
+            serde_json::json!({
+                "agent": {
+                    "type": "shared",
+                    "platform": "linux-x86_64",
+                    "fridaVersion": "17.5.1",
+                    "entry": entry,
+                    "overridePrebuildFile": prebuilt,
+                    "outputDir": dir.path().join("out"),
+                }
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let _current_dir_lock = CURRENT_DIR_LOCK.lock().await;
+        let current_dir = std::env::current_dir().unwrap();
+        let started = std::time::Instant::now();
+        let result = watch_target(
+            vec![config_path],
+            vec!["agent".to_string()],
+            None,
+            1,
+            Some(Duration::from_millis(100)),
+            true,
+            UpdateOptions {
+                strict: false,
+                wait_ready: None,
+            },
+        )
+        .await;
+        std::env::set_current_dir(current_dir).unwrap();
+
+        result.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(30));
+    }
+
+    /// A config with a `broken` target failing right away and an `other` one
+    /// running `before_build` first.
+    fn failing_build_config(dir: &Path, before_build: Option<&str>) -> PathBuf {
+        let entry = dir.join("main.js");
+        std::fs::write(&entry, "console.log('hi');").unwrap();
+        let fixture = |name: &str| format!("{}/tests/fixtures/{name}", env!("CARGO_MANIFEST_DIR"));
+        let target = |prebuilt: String| {
+            serde_json::json!({
+                "type": "shared",
+                "platform": "linux-x86_64",
+                "fridaVersion": "17.5.1",
+                "entry": entry,
+                "overridePrebuildFile": prebuilt,
+                "outputDir": dir.join("out"),
+            })
+        };
+        let mut other = target(fixture("libplaceholder.so"));
+        if let Some(before_build) = before_build {
+            other["beforeBuild"] = before_build.into();
+        }
+
+        let config_path = dir.join("fripack.json");
+        let config = serde_json::json!({
+            "broken": target(fixture("libvanilla.so")),
+            "other": other,
+        });
+        std::fs::write(&config_path, config.to_string()).unwrap();
+        config_path
+    }
+
+    async fn build_all(config_path: PathBuf, fail_fast: bool) -> Result<()> {
+        let _current_dir_lock = CURRENT_DIR_LOCK.lock().await;
+        let current_dir = std::env::current_dir().unwrap();
+        let result = build_target(
+            vec![config_path],
+            None,
+            None,
+            None,
+            2,
+            fail_fast,
+            Builder::new(),
+        )
+        .await;
+        std::env::set_current_dir(current_dir).unwrap();
+        result
+    }
+
+    fn built_other_target(dir: &Path) -> bool {
+        std::fs::read_dir(dir.join("out"))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .any(|entry| entry.file_name().to_string_lossy().starts_with("other"))
+            })
+            .unwrap_or(false)
+    }
+
+    #[tokio::test]
+    async fn fail_fast_cancels_the_other_targets() {
+        let dir = tempfile::tempdir().unwrap();
+        // Never finishes, so only cancelling it ends the build. It runs in the
+        // shell process itself, which is what cancelling kills.
+        let hook = if cfg!(target_os = "windows") {
+            "for /L %i in (0,0,1) do @rem"
+        } else {
+            "exec sleep 86400"
+        };
+        let config_path = failing_build_config(dir.path(), Some(hook));
+
+        let err = build_all(config_path, true).await.unwrap_err();
+        assert!(format!("{err:#}").contains("broken"), "{err:#}");
+        assert!(!built_other_target(dir.path()));
+    }
+
+    #[tokio::test]
+    async fn without_fail_fast_the_other_targets_are_built() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = failing_build_config(dir.path(), None);
+
+        let err = build_all(config_path, false).await.unwrap_err();
+        assert!(format!("{err:#}").contains("broken"), "{err:#}");
+        assert!(built_other_target(dir.path()));
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::collections::HashSet;
+use std::path::Path;
+use tokio::process::Command;
+
+use crate::explain::Explain;
+
+/// Checks generated smali before apktool assembles it, whose errors are hard to
+/// trace back. With `assemble`, the file is also assembled with the `smali` tool
+/// if it's installed.
+pub async fn check(path: &Path, content: &str, assemble: bool) -> Result<()> {
+    validate(content).context("generated smali is invalid")?;
+    if !assemble {
+        return Ok(());
+    }
+
+    let Ok(smali) = which::which("smali") else {
+        warn!("smali not found on PATH, skipping the assembly check of the generated smali");
+        return Ok(());
+    };
+    let temp_dir = tempfile::tempdir()?;
+    let output = Command::new(smali)
+        .arg("assemble")
+        .arg(path)
+        .arg("-o")
+        .arg(temp_dir.path().join("classes.dex"))
+        .explain()
+        .output()
+        .await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let detail = if stderr.trim().is_empty() {
+            stdout.trim()
+        } else {
+            stderr.trim()
+        };
+        anyhow::bail!("generated smali is invalid: {detail}");
+    }
+    info!("✓ Generated smali assembles: {}", path.display());
+    Ok(())
+}
+
+/// A `.method` being validated.
+struct Method {
+    start: usize,
+    has_registers: bool,
+    labels: HashSet<String>,
+    /// Labels referenced by branches, with their line
+    references: Vec<(usize, String)>,
+}
+
+/// Minimal syntax check of a smali class: the class header, balanced methods
+/// with a register count, and branch targets defined in their method.
+pub fn validate(content: &str) -> Result<()> {
+    let mut has_class = false;
+    let mut has_super = false;
+    let mut annotation_depth = 0;
+    let mut method: Option<Method> = None;
+
+    for (i, line) in content.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !has_class {
+            if !line.starts_with(".class ") {
+                anyhow::bail!("line {line_number}: expected .class, found {line:?}");
+            }
+            has_class = true;
+            continue;
+        }
+
+        // Annotation elements aren't code
+        if line.starts_with(".annotation ") || line.starts_with(".subannotation ") {
+            annotation_depth += 1;
+            continue;
+        }
+        if line == ".end annotation" || line == ".end subannotation" {
+            if annotation_depth == 0 {
+                anyhow::bail!("line {line_number}: {line} without a start");
+            }
+            annotation_depth -= 1;
+            continue;
+        }
+        if annotation_depth > 0 {
+            continue;
+        }
+
+        let directive = line.split_whitespace().next().unwrap_or_default();
+        match (directive, &mut method) {
+            (".class", _) => anyhow::bail!("line {line_number}: duplicate .class"),
+            (".super", None) => has_super = true,
+            (".method", Some(method)) => anyhow::bail!(
+                "line {line_number}: .method inside the method of line {}",
+                method.start
+            ),
+            (".method", None) => {
+                // Abstract and native methods have no code
+                let has_code = !line
+                    .split_whitespace()
+                    .any(|word| word == "abstract" || word == "native");
+                method = Some(Method {
+                    start: line_number,
+                    has_registers: !has_code,
+                    labels: HashSet::new(),
+                    references: Vec::new(),
+                });
+            }
+            (".end", None) if line == ".end method" => {
+                anyhow::bail!("line {line_number}: .end method without .method")
+            }
+            (".end", Some(_)) if line == ".end method" => {
+                let method = method.take().unwrap();
+                if !method.has_registers {
+                    anyhow::bail!("line {}: method has no .locals or .registers", method.start);
+                }
+                if let Some((line_number, label)) = method
+                    .references
+                    .iter()
+                    .find(|(_, label)| !method.labels.contains(label))
+                {
+                    anyhow::bail!("line {line_number}: undefined label :{label}");
+                }
+            }
+            (".locals" | ".registers", Some(method)) => method.has_registers = true,
+            (_, Some(method)) => {
+                if let Some(label) = line.strip_prefix(':') {
+                    if !method.labels.insert(label.to_string()) {
+                        anyhow::bail!("line {line_number}: duplicate label :{label}");
+                    }
+                    continue;
+                }
+                // Ignore string literals, which may contain colons
+                let code = line.split('"').next().unwrap_or_default();
+                for word in code.split(|c: char| c.is_whitespace() || matches!(c, ',' | '{' | '}'))
+                {
+                    if let Some(label) = word.strip_prefix(':') {
+                        method.references.push((line_number, label.to_string()));
+                    }
+                }
+            }
+            (_, None) if directive.starts_with('.') => {}
+            (_, None) => anyhow::bail!("line {line_number}: instruction outside of a method"),
+        }
+    }
+
+    if !has_class {
+        anyhow::bail!("missing .class");
+    }
+    if !has_super {
+        anyhow::bail!("missing .super");
+    }
+    if let Some(method) = method {
+        anyhow::bail!("line {}: method has no .end method", method.start);
+    }
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use anyhow::Result;
+use log::{info, warn};
+use std::path::Path;
+
+use crate::builder::find_java;
+use crate::config::{ResolvedConfig, ResolvedTarget};
+
+/// Checks every target of the configuration up front, the required fields as
+/// well as the files they reference, and reports all problems found.
+pub fn run_validate(config: &ResolvedConfig, config_dir: &Path) -> Result<()> {
+    let mut targets: Vec<_> = config.targets.iter().collect();
+    targets.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut checked = 0;
+    let mut failed = 0;
+    for (target_name, target_config) in targets {
+        if target_config.target_type.is_none() {
+            info!("- {target_name}: no type, only inherited from");
+            continue;
+        }
+        checked += 1;
+        let problems = target_problems(target_config, config_dir);
+        if problems.is_empty() {
+            info!("✓ {target_name}");
+            continue;
+        }
+        failed += 1;
+        for problem in problems {
+            warn!("✗ {target_name}: {problem}");
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{failed} of {checked} target(s) have problems");
+    }
+    info!("✓ All targets look good!");
+    Ok(())
+}
+
+fn target_problems(target: &ResolvedTarget, config_dir: &Path) -> Vec<String> {
+    let mut problems: Vec<String> = target
+        .problems()
+        .iter()
+        .map(|problem| format!("{problem:#}"))
+        .collect();
+    let mut check_file = |field: &str, path: &str| {
+        if !config_dir.join(path).is_file() {
+            problems.push(format!("{field} not found: {path}"));
+        }
+    };
+    // With `bundle`, the entry is written by the bundler
+    if let Some(entry) = target.entry.as_ref().filter(|_| target.bundle.is_none()) {
+        check_file("entry", entry);
+    }
+    if let Some(sign) = &target.sign {
+        check_file("sign.keystore", &sign.keystore);
+    }
+    if let Some(source_apk_path) = target
+        .inject_apk
+        .as_ref()
+        .and_then(|inject_apk| inject_apk.source_apk_path.as_ref())
+    {
+        check_file("injectApk.sourceApkPath", source_apk_path);
+    }
+    if let Some(override_file) = &target.override_prebuild_file {
+        check_file("overridePrebuildFile", override_file);
+    }
+    if let Some(apktool_jar) = &target.apktool_jar {
+        check_file("apktoolJar", apktool_jar);
+        if let Err(e) = find_java() {
+            problems.push(format!("apktoolJar is set, but java was not found: {e}"));
+        }
+    }
+    problems
+}
@@ -0,0 +1,22 @@
+// Source of the ELF test fixtures, built with
+//   gcc -shared -fPIC -O2 -s -o libplaceholder.so fixture.c
+//   gcc -shared -fPIC -O2 -s -DNO_PLACEHOLDER -o libvanilla.so fixture.c
+
+#ifndef NO_PLACEHOLDER
+// The zeroed `EmbeddedConfig` placeholder of a version 1 loader
+struct embedded_config {
+    int magic1;
+    int magic2;
+    int version;
+    int data_size;
+    int data_offset;
+    char compression;
+} __attribute__((packed));
+
+__attribute__((used)) volatile struct embedded_config fripack_config = {
+    0x0d000721, 0x1f8a4e2b, 1, 0, 0, 0,
+};
+#endif
+
+// Strings for the anti-detection pass, which works on .rodata
+const char *fixture_name(void) { return "frida-gadget fixture, gum-js-loop"; }